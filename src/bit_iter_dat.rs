@@ -11,9 +11,10 @@ pub struct BitIterDat<'a> {
 impl<'a> BitIterDat<'a> {
     pub fn new(data: &'a [u8], starting_byte_bits: u8) -> Self {
         if starting_byte_bits == 0 { // Skip initial (at end) byte for weirdly-compressed sections.
-            return BitIterDat { data, byte_index: (data.len() - 2) as isize, bit: 0, is_starting_byte: true, starting_byte_bits: 8 }
+            // Signed, so too little data just runs out of bits.
+            return BitIterDat { data, byte_index: data.len() as isize - 2, bit: 0, is_starting_byte: true, starting_byte_bits: 8 }
         }
-        BitIterDat { data, byte_index: (data.len() - 1) as isize, bit: 0, is_starting_byte: true, starting_byte_bits }
+        BitIterDat { data, byte_index: data.len() as isize - 1, bit: 0, is_starting_byte: true, starting_byte_bits }
     }
 
     pub fn next_byte(&mut self) -> Option<u8> {
@@ -53,6 +54,7 @@ impl<'a> Iterator for BitIterDat<'a> {
 mod tests {
     use super::*;
     #[test]
+    #[allow(non_snake_case)]
     fn bitBitIterDat() {
         let input: Vec<u8> = vec![0b00001111, 0];
 
@@ -25,3 +25,17 @@ impl Iterator for BitIterMsFirst {
 pub fn iterate(arr: &[u8]) -> impl Iterator<Item = u8> {
     arr.iter().flat_map(BitIterMsFirst::new)
 }
+
+// The reverse of iterate: packs bits into bytes, most significant first. The last byte is padded with 0s.
+pub fn pack(bits: impl Iterator<Item = bool>) -> Vec<u8> {
+    let mut bytes: Vec<u8> = Vec::new();
+    for (index, bit) in bits.enumerate() {
+        if index % 8 == 0 {
+            bytes.push(0);
+        }
+        if bit {
+            *bytes.last_mut().unwrap() |= 0x80 >> (index % 8);
+        }
+    }
+    bytes
+}
@@ -0,0 +1,81 @@
+// This writes bits in the order that BitIterDat reads them back.
+// Bits are pushed in reading order; the output is laid out backwards, and the
+// first-read byte (which ends up last in the output) only holds the leftover bits.
+#[derive(Default)]
+pub struct BitWriterDat {
+    bits: Vec<bool>,
+}
+
+impl BitWriterDat {
+    pub fn new() -> Self {
+        BitWriterDat::default()
+    }
+
+    pub fn push(&mut self, bit: bool) {
+        self.bits.push(bit);
+    }
+
+    // Pushes the lowest 'count' bits of value, most significant first, to match BitIterDat::next_byte.
+    pub fn push_bits(&mut self, value: u16, count: u8) {
+        for i in (0..count).rev() {
+            self.push((value >> i) & 1 != 0);
+        }
+    }
+
+    pub fn push_byte(&mut self, byte: u8) {
+        self.push_bits(byte as u16, 8);
+    }
+
+    // Returns the bytes, and the number of bits used in the first-read byte (aka num_bits_in_first_byte).
+    pub fn finish(self) -> (Vec<u8>, u8) {
+        if self.bits.is_empty() {
+            return (vec![0], 8); // A single padding byte, as BitIterDat needs something to point at.
+        }
+        let starting_byte_bits = ((self.bits.len() - 1) % 8 + 1) as u8;
+        let mut bytes: Vec<u8> = Vec::with_capacity(self.bits.len() / 8 + 1);
+        let (first, rest) = self.bits.split_at(starting_byte_bits as usize);
+        for chunk in std::iter::once(first).chain(rest.chunks(8)) {
+            let mut byte: u8 = 0;
+            for (bit_index, bit) in chunk.iter().enumerate() {
+                if *bit {
+                    byte |= 1 << bit_index;
+                }
+            }
+            bytes.push(byte);
+        }
+        bytes.reverse();
+        (bytes, starting_byte_bits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bit_iter_dat::BitIterDat;
+
+    #[test]
+    fn test_round_trip() {
+        for count in 0..20 {
+            let bits: Vec<bool> = (0..count).map(|i| i % 3 == 0 || i % 5 == 0).collect();
+            let mut writer = BitWriterDat::new();
+            for bit in bits.iter() {
+                writer.push(*bit);
+            }
+            let (bytes, starting_byte_bits) = writer.finish();
+            let read: Vec<bool> = BitIterDat::new(&bytes, starting_byte_bits).take(count).collect();
+            assert_eq!(read, bits);
+        }
+    }
+
+    #[test]
+    fn test_push_byte() {
+        let mut writer = BitWriterDat::new();
+        writer.push(false);
+        writer.push_byte(0b01111000);
+        let (bytes, starting_byte_bits) = writer.finish();
+        assert_eq!(starting_byte_bits, 1);
+        assert_eq!(BitIterDat::new(&bytes, starting_byte_bits).skip(1).collect::<Vec<bool>>(), vec![
+            false, true, true, true, true, false, false, false,
+        ]);
+    }
+}
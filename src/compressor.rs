@@ -0,0 +1,229 @@
+// This is for compressing lemmings DAT files, the inverse of decompressor.rs:
+// https://www.camanis.net/lemmings/files/docs/lemmings_dat_file_format.txt
+
+use crate::bit_writer_dat::BitWriterDat;
+use crate::error::{Error, Result};
+
+const HEADER_SIZE: usize = 10;
+const MAX_SECTION_SIZE: usize = 0xffff; // Sizes are stored as 16 bit words in the header.
+const MAX_DISTANCE: usize = 4096; // 12 bit offsets in chunk type 5.
+const MAX_LENGTH: usize = 256; // 8 bit length in chunk type 5.
+const MAX_CANDIDATES: usize = 512; // How far back along the hash chain to look for matches.
+const LITERAL_COST: isize = 9; // Rough bits per raw byte, including the chunk overhead.
+
+// Compresses sections into a dat file.
+pub fn compress(sections: &[Vec<u8>]) -> Result<Vec<u8>> {
+    let mut dat: Vec<u8> = Vec::new();
+    for section in sections {
+        dat.extend(compress_section(section)?);
+    }
+    Ok(dat)
+}
+
+// Compresses one section, including its 10 byte header.
+pub fn compress_section(data: &[u8]) -> Result<Vec<u8>> {
+    if data.len() > MAX_SECTION_SIZE {
+        return Err(Error::invalid(format!("section is too large to compress ({} bytes)", data.len())));
+    }
+    let (compressed, num_bits_in_first_byte) = encode(data);
+    let compressed_data_size = compressed.len() + HEADER_SIZE;
+    if compressed_data_size > MAX_SECTION_SIZE {
+        return Err(Error::invalid(format!("compressed section is too large ({} bytes)", compressed_data_size)));
+    }
+    let checksum = compressed.iter().fold(0, |acc, b| acc ^ b);
+    let mut output: Vec<u8> = Vec::with_capacity(compressed_data_size);
+    output.push(num_bits_in_first_byte);
+    output.push(checksum);
+    output.extend_from_slice(&[0, 0]); // Unused.
+    output.push((data.len() >> 8) as u8);
+    output.push(data.len() as u8);
+    output.extend_from_slice(&[0, 0]); // Unused.
+    output.push((compressed_data_size >> 8) as u8);
+    output.push(compressed_data_size as u8);
+    output.extend(compressed);
+    Ok(output)
+}
+
+// One step of the decompressor, in the order they are decoded.
+enum Chunk {
+    Raw(usize, usize), // Start, length, into the reversed data.
+    Reuse { length: usize, offset: usize }, // Offset 0 means the most recently decoded byte.
+}
+
+// The decompressor builds its output backwards then reverses it, so we do the same:
+// matches are searched for in the reversed data, and 'offset' counts back from the newest byte.
+fn encode(data: &[u8]) -> (Vec<u8>, u8) {
+    let reversed: Vec<u8> = data.iter().rev().copied().collect();
+    let chunks = find_chunks(&reversed);
+    let mut bits = BitWriterDat::new();
+    for chunk in chunks {
+        match chunk {
+            Chunk::Raw(start, length) => write_raw(&mut bits, &reversed[start..start + length]),
+            Chunk::Reuse { length, offset } => write_reuse(&mut bits, length, offset),
+        }
+    }
+    bits.finish()
+}
+
+// Greedy LZ77 parse using hash chains keyed on the next 2 bytes.
+fn find_chunks(data: &[u8]) -> Vec<Chunk> {
+    let mut chunks: Vec<Chunk> = Vec::new();
+    let mut heads: Vec<usize> = vec![usize::MAX; 0x10000];
+    let mut previous: Vec<usize> = vec![usize::MAX; data.len()];
+    fn insert(data: &[u8], position: usize, heads: &mut [usize], previous: &mut [usize]) {
+        if position + 1 < data.len() {
+            let key = ((data[position] as usize) << 8) | data[position + 1] as usize;
+            previous[position] = heads[key];
+            heads[key] = position;
+        }
+    }
+    let mut raw_start: usize = 0;
+    let mut position: usize = 0;
+    while position < data.len() {
+        let best = best_match(data, position, &heads, &previous);
+        if let Some((length, distance)) = best {
+            if raw_start < position {
+                chunks.push(Chunk::Raw(raw_start, position - raw_start));
+            }
+            chunks.push(Chunk::Reuse { length, offset: distance - 1 });
+            for p in position..position + length {
+                insert(data, p, &mut heads, &mut previous);
+            }
+            position += length;
+            raw_start = position;
+        } else {
+            insert(data, position, &mut heads, &mut previous);
+            position += 1;
+        }
+    }
+    if raw_start < data.len() {
+        chunks.push(Chunk::Raw(raw_start, data.len() - raw_start));
+    }
+    chunks
+}
+
+// Finds the match that saves the most bits, if any are worthwhile. Returns (length, distance).
+fn best_match(data: &[u8], position: usize, heads: &[usize], previous: &[usize]) -> Option<(usize, usize)> {
+    if position + 1 >= data.len() {
+        return None;
+    }
+    let key = ((data[position] as usize) << 8) | data[position + 1] as usize;
+    let max_length = MAX_LENGTH.min(data.len() - position);
+    let mut best: Option<(usize, usize)> = None;
+    let mut best_saving: isize = 0;
+    let mut candidate = heads[key];
+    for _ in 0..MAX_CANDIDATES {
+        if candidate == usize::MAX || position - candidate > MAX_DISTANCE {
+            break;
+        }
+        let distance = position - candidate;
+        let mut length: usize = 0;
+        while length < max_length && data[candidate + length] == data[position + length] {
+            length += 1; // Overlapping matches are fine, the decompressor copies byte by byte.
+        }
+        if let Some((usable_length, cost)) = cheapest_reuse(length, distance) {
+            let saving = LITERAL_COST * usable_length as isize - cost;
+            if saving > best_saving {
+                best_saving = saving;
+                best = Some((usable_length, distance));
+            }
+        }
+        candidate = previous[candidate];
+    }
+    best
+}
+
+// Picks the chunk type that can encode (most of) a match. Returns (length, cost in bits).
+fn cheapest_reuse(length: usize, distance: usize) -> Option<(usize, isize)> {
+    if length >= 5 || (length >= 2 && distance > 1024) {
+        return Some((length, 23)); // Type 5.
+    }
+    if length == 4 && distance <= 1024 {
+        return Some((4, 13)); // Type 4.
+    }
+    if length >= 3 && distance <= 512 {
+        return Some((3, 12)); // Type 3.
+    }
+    if length >= 2 && distance <= 256 {
+        return Some((2, 10)); // Type 2.
+    }
+    if length >= 3 {
+        return Some((length, 23)); // Type 5 again, for short matches that are too far away for types 3 and 4.
+    }
+    None
+}
+
+fn write_raw(bits: &mut BitWriterDat, bytes: &[u8]) {
+    let mut remaining = bytes;
+    while !remaining.is_empty() {
+        let length = if remaining.len() >= 9 { remaining.len().min(264) } else { remaining.len() };
+        if length >= 9 { // 6: many raw bytes.
+            bits.push_bits(0b111, 3);
+            bits.push_byte((length - 9) as u8);
+        } else { // 1: some raw bytes.
+            bits.push_bits(0b00, 2);
+            bits.push_bits((length - 1) as u16, 3);
+        }
+        for byte in &remaining[..length] {
+            bits.push_byte(*byte);
+        }
+        remaining = &remaining[length..];
+    }
+}
+
+fn write_reuse(bits: &mut BitWriterDat, length: usize, offset: usize) {
+    match length {
+        2 if offset < 0x100 => { // 2: Reuse 2 bytes.
+            bits.push_bits(0b01, 2);
+            bits.push_bits(offset as u16, 8);
+        },
+        3 if offset < 0x200 => { // 3: reuse 3 bytes.
+            bits.push_bits(0b100, 3);
+            bits.push_bits(offset as u16, 9);
+        },
+        4 if offset < 0x400 => { // 4: reuse 4 bytes.
+            bits.push_bits(0b101, 3);
+            bits.push_bits(offset as u16, 10);
+        },
+        _ => { // 5: reuse N bytes.
+            bits.push_bits(0b110, 3);
+            bits.push_byte((length - 1) as u8);
+            bits.push_bits(offset as u16, 12);
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decompressor;
+
+    #[test]
+    fn test_round_trip_small() {
+        let inputs: Vec<Vec<u8>> = vec![
+            vec![],
+            vec![42],
+            vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10],
+            vec![7; 1000],
+            (0..5000).map(|i| (i * 7 % 13) as u8).collect(),
+            (0..3000).map(|i| (i * i % 251) as u8).collect(),
+        ];
+        let dat = compress(&inputs).unwrap();
+        assert_eq!(decompressor::decompress("TEST.DAT", &dat).unwrap(), inputs);
+    }
+
+    #[test]
+    fn test_round_trip_bundled_files() {
+        for folder in std::fs::read_dir("data").unwrap() {
+            for entry in std::fs::read_dir(folder.unwrap().path()).unwrap() {
+                let path = entry.unwrap().path();
+                let name = path.file_name().unwrap().to_string_lossy().to_lowercase();
+                let is_compressed = ["level", "dlvel", "vgagr", "vgaspec", "main"].iter().any(|p| name.starts_with(p));
+                if !is_compressed { continue }
+                let sections = decompressor::decompress(&name, &std::fs::read(&path).unwrap()).unwrap();
+                let dat = compress(&sections).unwrap();
+                assert_eq!(decompressor::decompress(&name, &dat).unwrap(), sections, "{}", path.display());
+            }
+        }
+    }
+}
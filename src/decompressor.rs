@@ -2,127 +2,154 @@
 // https://www.camanis.net/lemmings/files/docs/lemmings_dat_file_format.txt
 
 use crate::bit_iter_dat;
+use crate::error::{Error, Result, SectionProblem};
+
+const HEADER_SIZE: usize = 10;
 
 // Decompresses a dat file into sections.
-pub fn decompress(dat: &[u8]) -> Vec<Vec<u8>> {
-    decompose_into_compressed_sections(dat)
-        .iter()
-        .map(|s| s.decompress())
-        .collect()
+// The file name is only used to describe any problems.
+pub fn decompress(file: &str, dat: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let corrupt = |section_index: usize, problem: SectionProblem|
+        Error::CorruptDat { file: file.to_string(), section_index, problem };
+    let sections = decompose_into_compressed_sections(dat).map_err(|(section_index, problem)| corrupt(section_index, problem))?;
+    let mut decompressed: Vec<Vec<u8>> = Vec::with_capacity(sections.len());
+    for (section_index, section) in sections.iter().enumerate() {
+        let data = section.decompress().map_err(|problem| corrupt(section_index, problem))?;
+        if data.len() != section.decompressed_data_size {
+            return Err(corrupt(section_index, SectionProblem::DecompressedSize { expected: section.decompressed_data_size, actual: data.len() }));
+        }
+        decompressed.push(data);
+    }
+    Ok(decompressed)
 }
 
 // Parses a DAT file into it's sections with their headers and compressed data.
-fn decompose_into_compressed_sections(dat: &[u8]) -> Vec<CompressedSection<'_>> {
+// Errors are the index of the bad section, and what's wrong with it.
+fn decompose_into_compressed_sections(dat: &[u8]) -> std::result::Result<Vec<CompressedSection<'_>>, (usize, SectionProblem)> {
     let mut sections: Vec<CompressedSection> = Vec::new();
     let mut offset: usize = 0;
     loop {
-        let section = CompressedSection::new(&dat[offset..]);
+        let section = CompressedSection::new(&dat[offset..]).map_err(|problem| (sections.len(), problem))?;
         offset += section.compressed_data_size;
         sections.push(section);
         if offset >= dat.len() {
             break
         }
     }
-    sections
+    Ok(sections)
 }
 
 struct CompressedSection<'a> {
     num_bits_in_first_byte: u8,
-    _checksum: u8,
     decompressed_data_size: usize,
     compressed_data_size: usize, // Includes the 10byte header.
     data: &'a [u8],
 }
 
 impl<'a> CompressedSection<'a> {
-    fn new(data: &'a [u8]) -> CompressedSection<'a> {
+    fn new(data: &'a [u8]) -> std::result::Result<CompressedSection<'a>, SectionProblem> {
+        if data.len() < HEADER_SIZE {
+            return Err(SectionProblem::TruncatedHeader { expected: HEADER_SIZE, actual: data.len() });
+        }
         let num_bits_in_first_byte = data[0];
-        let _checksum = data[1];
+        if num_bits_in_first_byte > 8 { // The checksum doesn't cover this.
+            return Err(SectionProblem::FirstByteBits(num_bits_in_first_byte));
+        }
+        let checksum = data[1];
         let decompressed_data_size: usize = ((data[4] as usize) << 8) + (data[5] as usize);
         let compressed_data_size: usize = ((data[8] as usize) << 8) + (data[9] as usize);
-        let data = &data[10..compressed_data_size];
-        CompressedSection {
+        if compressed_data_size <= HEADER_SIZE || compressed_data_size > data.len() {
+            return Err(SectionProblem::CompressedSize { expected: compressed_data_size, actual: data.len() });
+        }
+        let data = &data[HEADER_SIZE..compressed_data_size];
+        let actual_checksum = data.iter().fold(0, |acc, b| acc ^ b);
+        if actual_checksum != checksum {
+            return Err(SectionProblem::Checksum { expected: checksum, actual: actual_checksum });
+        }
+        Ok(CompressedSection {
             num_bits_in_first_byte,
-            _checksum,
             decompressed_data_size,
             compressed_data_size,
             data,
-        }
+        })
     }
 
-    fn decompress(&self) -> Vec<u8> {
+    fn decompress(&self) -> std::result::Result<Vec<u8>, SectionProblem> {
         // if offset=0, returns the end byte.
         // if offset=1, returns the one just before the end, and so on.
-        fn from_end(vec: &[u8], offset: usize) -> u8 {
-            vec[vec.len() - 1 - offset]
+        fn from_end(vec: &[u8], offset: usize) -> std::result::Result<u8, SectionProblem> {
+            if offset >= vec.len() {
+                return Err(SectionProblem::ReuseBeyondStart { offset, available: vec.len() });
+            }
+            Ok(vec[vec.len() - 1 - offset])
         }
 
         let mut bits = bit_iter_dat::BitIterDat::new(self.data, self.num_bits_in_first_byte);
         let mut decompressed: Vec<u8> = Vec::new();
-        while decompressed.len() < self.decompressed_data_size as usize {
-            match bits.next().unwrap() {
+        while decompressed.len() < self.decompressed_data_size {
+            match bits.next().ok_or(SectionProblem::RanOutOfBits)? {
                 false => {
-                    match bits.next().unwrap() {
+                    match bits.next().ok_or(SectionProblem::RanOutOfBits)? {
                         false => { // 1: some raw bytes
-                            let n1 = bits.next_bit().unwrap();
-                            let n2 = bits.next_bit().unwrap();
-                            let n3 = bits.next_bit().unwrap();
+                            let n1 = bits.next_bit().ok_or(SectionProblem::RanOutOfBits)?;
+                            let n2 = bits.next_bit().ok_or(SectionProblem::RanOutOfBits)?;
+                            let n3 = bits.next_bit().ok_or(SectionProblem::RanOutOfBits)?;
                             let n = (n1 << 2) + (n2 << 1) + n3 + 1;
                             for _ in 0..n {
-                                decompressed.push(bits.next_byte().unwrap());
+                                decompressed.push(bits.next_byte().ok_or(SectionProblem::RanOutOfBits)?);
                             }
                         },
                         true => { // 2: Reuse 2 bytes.
-                            let offset = bits.next_byte().unwrap() as usize;
+                            let offset = bits.next_byte().ok_or(SectionProblem::RanOutOfBits)? as usize;
                             for _ in 0..2 {
-                                let b = from_end(&decompressed, offset);
+                                let b = from_end(&decompressed, offset)?;
                                 decompressed.push(b);
                             }
                         }
                     }
                 },
                 true => {
-                    let b = bits.next().unwrap();
-                    let c = bits.next().unwrap();
+                    let b = bits.next().ok_or(SectionProblem::RanOutOfBits)?;
+                    let c = bits.next().ok_or(SectionProblem::RanOutOfBits)?;
                     match (b, c) {
                         (false, false) => { // 3: reuse 3 bytes.
-                            let m1 = bits.next_bit().unwrap();
-                            let m2 = bits.next_byte().unwrap();
+                            let m1 = bits.next_bit().ok_or(SectionProblem::RanOutOfBits)?;
+                            let m2 = bits.next_byte().ok_or(SectionProblem::RanOutOfBits)?;
                             let offset: usize = ((m1 as usize) << 8) + (m2 as usize);
                             for _ in 0..3 {
-                                let b = from_end(&decompressed, offset);
+                                let b = from_end(&decompressed, offset)?;
                                 decompressed.push(b);
                             }
                         },
                         (false, true) => { // 4: reuse 4 bytes.
-                            let m1 = bits.next_bit().unwrap();
-                            let m2 = bits.next_bit().unwrap();
-                            let m3 = bits.next_byte().unwrap();
+                            let m1 = bits.next_bit().ok_or(SectionProblem::RanOutOfBits)?;
+                            let m2 = bits.next_bit().ok_or(SectionProblem::RanOutOfBits)?;
+                            let m3 = bits.next_byte().ok_or(SectionProblem::RanOutOfBits)?;
                             let offset: usize = ((m1 as usize) << 9) + ((m2 as usize) << 8) + (m3 as usize);
                             for _ in 0..4 {
-                                let b = from_end(&decompressed, offset);
+                                let b = from_end(&decompressed, offset)?;
                                 decompressed.push(b);
                             }
                         },
                         (true, false) => { // 5: reuse N bytes.
-                            let n = bits.next_byte().unwrap();
+                            let n = bits.next_byte().ok_or(SectionProblem::RanOutOfBits)?;
                             let length: u16 = n as u16 + 1;
-                            let m1 = bits.next_bit().unwrap();
-                            let m2 = bits.next_bit().unwrap();
-                            let m3 = bits.next_bit().unwrap();
-                            let m4 = bits.next_bit().unwrap();
-                            let m5 = bits.next_byte().unwrap();
+                            let m1 = bits.next_bit().ok_or(SectionProblem::RanOutOfBits)?;
+                            let m2 = bits.next_bit().ok_or(SectionProblem::RanOutOfBits)?;
+                            let m3 = bits.next_bit().ok_or(SectionProblem::RanOutOfBits)?;
+                            let m4 = bits.next_bit().ok_or(SectionProblem::RanOutOfBits)?;
+                            let m5 = bits.next_byte().ok_or(SectionProblem::RanOutOfBits)?;
                             let offset: usize = ((m1 as usize) << 11) + ((m2 as usize) << 10) + ((m3 as usize) << 9) + ((m4 as usize) << 8) + (m5 as usize);
                             for _ in 0..length {
-                                let b = from_end(&decompressed, offset);
+                                let b = from_end(&decompressed, offset)?;
                                 decompressed.push(b);
                             }
                         },
                         (true, true) => { // 6: many raw bytes.
-                            let n = bits.next_byte().unwrap();
+                            let n = bits.next_byte().ok_or(SectionProblem::RanOutOfBits)?;
                             let length: u16 = n as u16 + 9;
                             for _ in 0..length {
-                                decompressed.push(bits.next_byte().unwrap());
+                                decompressed.push(bits.next_byte().ok_or(SectionProblem::RanOutOfBits)?);
                             }
                         }
                     }
@@ -130,6 +157,51 @@ impl<'a> CompressedSection<'a> {
             }
         }
         decompressed.reverse();
-        decompressed
+        Ok(decompressed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compressor;
+
+    #[test]
+    fn test_corrupt_sections() {
+        let sections: Vec<Vec<u8>> = vec![vec![1, 2, 3, 4, 5, 6, 7, 8, 9], vec![7; 100]];
+        let dat = compressor::compress(&sections).unwrap();
+        assert_eq!(decompress("TEST.DAT", &dat).unwrap(), sections);
+
+        let second_section = dat[9] as usize; // The first section's compressed size is where the second begins.
+        let mut bad_checksum = dat.clone();
+        bad_checksum[second_section + 1] ^= 0xff;
+        let Error::CorruptDat { file, section_index, problem } = decompress("TEST.DAT", &bad_checksum).unwrap_err() else { panic!() };
+        assert_eq!(file, "TEST.DAT");
+        assert_eq!(section_index, 1);
+        assert_eq!(problem, SectionProblem::Checksum { expected: dat[second_section + 1] ^ 0xff, actual: dat[second_section + 1] });
+
+        let truncated = &dat[..dat.len() - 1];
+        let Error::CorruptDat { section_index, problem, .. } = decompress("TEST.DAT", truncated).unwrap_err() else { panic!() };
+        assert_eq!(section_index, 1);
+        assert_eq!(problem, SectionProblem::CompressedSize { expected: dat.len() - second_section, actual: dat.len() - second_section - 1 });
+
+        let Error::CorruptDat { problem, .. } = decompress("TEST.DAT", &dat[..second_section + 4]).unwrap_err() else { panic!() };
+        assert_eq!(problem, SectionProblem::TruncatedHeader { expected: 10, actual: 4 });
+
+        let short = [8, 0, 0, 0, 0, 100, 0, 0, 0, 11, 0]; // Claims 100 bytes from a single byte of bits.
+        let Error::CorruptDat { problem, .. } = decompress("TEST.DAT", &short).unwrap_err() else { panic!() };
+        assert_eq!(problem, SectionProblem::RanOutOfBits);
+
+        let too_many_bits = [9, 0, 0, 0, 0, 100, 0, 0, 0, 11, 0];
+        let Error::CorruptDat { problem, .. } = decompress("TEST.DAT", &too_many_bits).unwrap_err() else { panic!() };
+        assert_eq!(problem, SectionProblem::FirstByteBits(9));
+
+        let no_bits = [0, 0, 0, 0, 0, 100, 0, 0, 0, 11, 0]; // 0 bits skips the only byte.
+        let Error::CorruptDat { problem, .. } = decompress("TEST.DAT", &no_bits).unwrap_err() else { panic!() };
+        assert_eq!(problem, SectionProblem::RanOutOfBits);
+
+        let reuse = [8, 2, 0, 0, 0, 2, 0, 0, 0, 12, 0, 2]; // A 2 byte reuse before anything's been decompressed.
+        let Error::CorruptDat { problem, .. } = decompress("TEST.DAT", &reuse).unwrap_err() else { panic!() };
+        assert_eq!(problem, SectionProblem::ReuseBeyondStart { offset: 0, available: 0 });
     }
 }
@@ -0,0 +1,507 @@
+// Raw deflate compression (RFC 1951) for png.rs. The input is LZ77 matched using hash chains, then
+// each block is written with whichever of stored, fixed Huffman or dynamic Huffman codes is smallest.
+// See: https://datatracker.ietf.org/doc/html/rfc1951
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    Stored, // No compression at all, like before this was written.
+    Fast,
+    #[default]
+    Default,
+    Best,
+}
+
+impl Compression {
+    // How hard to look for matches: (how far along a hash chain to look, a match length that's
+    // good enough to stop looking, whether to check if the next byte starts a better match).
+    fn effort(&self) -> (usize, usize, bool) {
+        match self {
+            Compression::Stored => (0, 0, false),
+            Compression::Fast => (16, 32, false),
+            Compression::Default => (128, 128, true),
+            Compression::Best => (1024, MAX_MATCH, true),
+        }
+    }
+}
+
+const WINDOW_SIZE: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const HASH_BITS: u32 = 15;
+const NO_POSITION: u32 = u32::MAX;
+const BLOCK_TOKENS: usize = 16384; // Blocks are this many literals/matches, so the codes can adapt.
+const MAX_STORED: usize = 0xffff;
+pub const MAX_CODE_BITS: usize = 15;
+const MAX_CODE_LENGTH_BITS: usize = 7;
+pub const END_OF_BLOCK: usize = 256;
+
+pub const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+pub const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+pub const DISTANCE_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+pub const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+// The order the code length code lengths are stored in a dynamic block header.
+pub const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token {
+    Literal(u8),
+    Match { length: usize, distance: usize },
+}
+
+impl Token {
+    fn input_length(&self) -> usize {
+        match self {
+            Token::Literal(_) => 1,
+            Token::Match { length, .. } => *length,
+        }
+    }
+}
+
+fn length_index(length: usize) -> usize {
+    LENGTH_BASE.partition_point(|base| *base as usize <= length) - 1
+}
+
+fn distance_index(distance: usize) -> usize {
+    DISTANCE_BASE.partition_point(|base| *base as usize <= distance) - 1
+}
+
+pub fn compress(input: &[u8], compression: Compression) -> Vec<u8> {
+    let mut writer = BitWriter::default();
+    if compression == Compression::Stored || input.is_empty() {
+        write_stored(&mut writer, input, true);
+        return writer.finish();
+    }
+    let tokens = find_tokens(input, compression);
+    let blocks = tokens.chunks(BLOCK_TOKENS);
+    let last = blocks.len() - 1;
+    let mut start = 0;
+    for (index, block) in blocks.enumerate() {
+        let length: usize = block.iter().map(|t| t.input_length()).sum();
+        write_block(&mut writer, block, &input[start..start + length], index == last);
+        start += length;
+    }
+    writer.finish()
+}
+
+// LZ77:
+
+struct Matcher<'a> {
+    input: &'a [u8],
+    head: Vec<u32>, // Most recent position for each hash.
+    previous: Vec<u32>, // Previous position with the same hash, for each position.
+    max_chain: usize,
+    nice_length: usize,
+}
+
+impl Matcher<'_> {
+    fn hash(&self, position: usize) -> usize {
+        let bytes = &self.input[position..position + MIN_MATCH];
+        let value = (bytes[0] as u32) | ((bytes[1] as u32) << 8) | ((bytes[2] as u32) << 16);
+        (value.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+    }
+
+    fn insert(&mut self, position: usize) {
+        if position + MIN_MATCH > self.input.len() { return }
+        let hash = self.hash(position);
+        self.previous[position] = self.head[hash];
+        self.head[hash] = position as u32;
+    }
+
+    // Returns (length, distance) of the longest earlier match, or a length of 0 if there's none.
+    fn longest_match(&self, position: usize) -> (usize, usize) {
+        if position + MIN_MATCH > self.input.len() { return (0, 0) }
+        let max_length = (self.input.len() - position).min(MAX_MATCH);
+        let mut best = (0, 0);
+        let mut candidate = self.head[self.hash(position)];
+        let mut chain = self.max_chain;
+        while candidate != NO_POSITION && chain > 0 {
+            let earlier = candidate as usize;
+            let distance = position - earlier;
+            if distance > WINDOW_SIZE { break } // The chain only gets further away.
+            if self.input[earlier + best.0] == self.input[position + best.0] { // Quick check it could be longer.
+                let length = self.input[earlier..earlier + max_length].iter()
+                    .zip(&self.input[position..position + max_length])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if length > best.0 {
+                    best = (length, distance);
+                    if length >= self.nice_length || length == max_length { break }
+                }
+            }
+            candidate = self.previous[earlier];
+            chain -= 1;
+        }
+        if best.0 < MIN_MATCH { (0, 0) } else { best }
+    }
+}
+
+fn find_tokens(input: &[u8], compression: Compression) -> Vec<Token> {
+    let (max_chain, nice_length, lazy) = compression.effort();
+    let mut matcher = Matcher {
+        input,
+        head: vec![NO_POSITION; 1 << HASH_BITS],
+        previous: vec![NO_POSITION; input.len()],
+        max_chain,
+        nice_length,
+    };
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut position = 0;
+    while position < input.len() {
+        let (length, distance) = matcher.longest_match(position);
+        matcher.insert(position);
+        if length == 0 {
+            tokens.push(Token::Literal(input[position]));
+            position += 1;
+            continue;
+        }
+        // Lazy matching: if the next byte starts a longer match, this byte is better off as a literal.
+        if lazy && length < nice_length && matcher.longest_match(position + 1).0 > length {
+            tokens.push(Token::Literal(input[position]));
+            position += 1;
+            continue;
+        }
+        tokens.push(Token::Match { length, distance });
+        for covered in position + 1..position + length {
+            matcher.insert(covered);
+        }
+        position += length;
+    }
+    tokens
+}
+
+// Huffman codes:
+
+// Optimal code lengths no longer than max_bits, using the package-merge algorithm.
+// See: https://en.wikipedia.org/wiki/Package-merge_algorithm
+fn code_lengths(frequencies: &[u32], max_bits: usize) -> Vec<u8> {
+    let mut leaves: Vec<(u64, u16)> = frequencies.iter().enumerate()
+        .filter(|(_, frequency)| **frequency > 0)
+        .map(|(symbol, frequency)| (*frequency as u64, symbol as u16))
+        .collect();
+    // Some decoders (and a single symbol's code) need at least two codes.
+    for (symbol, frequency) in frequencies.iter().enumerate() {
+        if leaves.len() >= 2 { break }
+        if *frequency == 0 {
+            leaves.push((1, symbol as u16));
+        }
+    }
+    leaves.sort();
+
+    let mut items: Vec<(u64, Vec<u16>)> = leaves.iter().map(|(weight, symbol)| (*weight, vec![*symbol])).collect();
+    for _ in 1..max_bits {
+        let packages: Vec<(u64, Vec<u16>)> = items.chunks_exact(2)
+            .map(|pair| (pair[0].0 + pair[1].0, [pair[0].1.as_slice(), pair[1].1.as_slice()].concat()))
+            .collect();
+        let mut merged: Vec<(u64, Vec<u16>)> = Vec::with_capacity(leaves.len() + packages.len());
+        let mut leaf_iter = leaves.iter().peekable();
+        let mut package_iter = packages.into_iter().peekable();
+        loop {
+            let take_leaf = match (leaf_iter.peek(), package_iter.peek()) {
+                (Some(leaf), Some(package)) => leaf.0 <= package.0,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (None, None) => break,
+            };
+            if take_leaf {
+                let (weight, symbol) = leaf_iter.next().unwrap();
+                merged.push((*weight, vec![*symbol]));
+            } else {
+                merged.push(package_iter.next().unwrap());
+            }
+        }
+        items = merged;
+    }
+
+    let mut lengths = vec![0u8; frequencies.len()];
+    for (_, symbols) in items.iter().take(2 * leaves.len() - 2) {
+        for symbol in symbols {
+            lengths[*symbol as usize] += 1;
+        }
+    }
+    lengths
+}
+
+// Canonical codes for the lengths, as described in RFC1951 section 3.2.2.
+fn codes_from_lengths(lengths: &[u8]) -> Vec<u16> {
+    let mut length_counts = [0u16; MAX_CODE_BITS + 1];
+    for length in lengths {
+        if *length > 0 {
+            length_counts[*length as usize] += 1;
+        }
+    }
+    let mut next_code = [0u16; MAX_CODE_BITS + 1];
+    let mut code: u16 = 0;
+    for bits in 1..=MAX_CODE_BITS {
+        code = (code + length_counts[bits - 1]) << 1;
+        next_code[bits] = code;
+    }
+    lengths.iter().map(|length| {
+        if *length == 0 { return 0 }
+        let code = next_code[*length as usize];
+        next_code[*length as usize] += 1;
+        code
+    }).collect()
+}
+
+pub fn fixed_lengths() -> (Vec<u8>, Vec<u8>) {
+    let mut literals = vec![8u8; 288];
+    literals[144..256].fill(9);
+    literals[256..280].fill(7);
+    (literals, vec![5u8; 30])
+}
+
+// The literal/length and distance code lengths of a dynamic block header, run length encoded as
+// (code length symbol, extra bits value) pairs: 16 repeats the previous length, 17 and 18 are zeros.
+fn run_length_encode(lengths: &[u8]) -> Vec<(u8, u8)> {
+    let mut encoded: Vec<(u8, u8)> = Vec::new();
+    let mut index = 0;
+    while index < lengths.len() {
+        let length = lengths[index];
+        let run = lengths[index..].iter().take_while(|l| **l == length).count();
+        let mut remaining = run;
+        if length == 0 {
+            while remaining >= 11 {
+                let count = remaining.min(138);
+                encoded.push((18, (count - 11) as u8));
+                remaining -= count;
+            }
+            if remaining >= 3 {
+                encoded.push((17, (remaining - 3) as u8));
+                remaining = 0;
+            }
+        } else {
+            encoded.push((length, 0));
+            remaining -= 1;
+            while remaining >= 3 {
+                let count = remaining.min(6);
+                encoded.push((16, (count - 3) as u8));
+                remaining -= count;
+            }
+        }
+        for _ in 0..remaining {
+            encoded.push((length, 0));
+        }
+        index += run;
+    }
+    encoded
+}
+
+fn code_length_extra_bits(symbol: u8) -> u32 {
+    match symbol {
+        16 => 2,
+        17 => 3,
+        18 => 7,
+        _ => 0,
+    }
+}
+
+// Blocks:
+
+struct DynamicHeader {
+    literal_count: usize, // HLIT + 257.
+    distance_count: usize, // HDIST + 1.
+    code_length_count: usize, // HCLEN + 4.
+    code_length_lengths: Vec<u8>,
+    encoded_lengths: Vec<(u8, u8)>,
+}
+
+impl DynamicHeader {
+    fn new(literal_lengths: &[u8], distance_lengths: &[u8]) -> DynamicHeader {
+        let literal_count = (literal_lengths.iter().rposition(|l| *l > 0).unwrap_or(0) + 1).max(257);
+        let distance_count = distance_lengths.iter().rposition(|l| *l > 0).unwrap_or(0) + 1;
+        let all_lengths = [&literal_lengths[..literal_count], &distance_lengths[..distance_count]].concat();
+        let encoded_lengths = run_length_encode(&all_lengths);
+        let mut frequencies = [0u32; 19];
+        for (symbol, _) in &encoded_lengths {
+            frequencies[*symbol as usize] += 1;
+        }
+        let code_length_lengths = code_lengths(&frequencies, MAX_CODE_LENGTH_BITS);
+        let code_length_count = (CODE_LENGTH_ORDER.iter().rposition(|s| code_length_lengths[*s] > 0).unwrap_or(0) + 1).max(4);
+        DynamicHeader { literal_count, distance_count, code_length_count, code_length_lengths, encoded_lengths }
+    }
+
+    fn bits(&self) -> usize {
+        let encoded: usize = self.encoded_lengths.iter()
+            .map(|(symbol, _)| self.code_length_lengths[*symbol as usize] as usize + code_length_extra_bits(*symbol) as usize)
+            .sum();
+        5 + 5 + 4 + 3 * self.code_length_count + encoded
+    }
+
+    fn write(&self, writer: &mut BitWriter) {
+        writer.write_bits((self.literal_count - 257) as u32, 5);
+        writer.write_bits((self.distance_count - 1) as u32, 5);
+        writer.write_bits((self.code_length_count - 4) as u32, 4);
+        for symbol in CODE_LENGTH_ORDER.iter().take(self.code_length_count) {
+            writer.write_bits(self.code_length_lengths[*symbol] as u32, 3);
+        }
+        let codes = codes_from_lengths(&self.code_length_lengths);
+        for (symbol, extra) in &self.encoded_lengths {
+            writer.write_code(codes[*symbol as usize], self.code_length_lengths[*symbol as usize]);
+            writer.write_bits(*extra as u32, code_length_extra_bits(*symbol));
+        }
+    }
+}
+
+// The bits needed for the tokens and end of block with these code lengths.
+fn data_bits(literal_frequencies: &[u32], distance_frequencies: &[u32], literal_lengths: &[u8], distance_lengths: &[u8]) -> usize {
+    let mut bits = 0;
+    for (symbol, frequency) in literal_frequencies.iter().enumerate() {
+        let extra = if symbol > END_OF_BLOCK { LENGTH_EXTRA[symbol - END_OF_BLOCK - 1] } else { 0 };
+        bits += *frequency as usize * (literal_lengths[symbol] + extra) as usize;
+    }
+    for (symbol, frequency) in distance_frequencies.iter().enumerate() {
+        bits += *frequency as usize * (distance_lengths[symbol] + DISTANCE_EXTRA[symbol]) as usize;
+    }
+    bits
+}
+
+fn write_block(writer: &mut BitWriter, tokens: &[Token], raw: &[u8], is_final: bool) {
+    let mut literal_frequencies = [0u32; 286];
+    let mut distance_frequencies = [0u32; 30];
+    for token in tokens {
+        match token {
+            Token::Literal(byte) => literal_frequencies[*byte as usize] += 1,
+            Token::Match { length, distance } => {
+                literal_frequencies[END_OF_BLOCK + 1 + length_index(*length)] += 1;
+                distance_frequencies[distance_index(*distance)] += 1;
+            },
+        }
+    }
+    literal_frequencies[END_OF_BLOCK] = 1;
+
+    let dynamic_literal_lengths = code_lengths(&literal_frequencies, MAX_CODE_BITS);
+    let dynamic_distance_lengths = code_lengths(&distance_frequencies, MAX_CODE_BITS);
+    let header = DynamicHeader::new(&dynamic_literal_lengths, &dynamic_distance_lengths);
+    let dynamic_bits = header.bits() + data_bits(&literal_frequencies, &distance_frequencies, &dynamic_literal_lengths, &dynamic_distance_lengths);
+    let (fixed_literal_lengths, fixed_distance_lengths) = fixed_lengths();
+    let fixed_bits = data_bits(&literal_frequencies, &distance_frequencies, &fixed_literal_lengths, &fixed_distance_lengths);
+    let stored_bits = (raw.len() + 5 * raw.len().div_ceil(MAX_STORED)) * 8;
+
+    if stored_bits <= fixed_bits.min(dynamic_bits) {
+        write_stored(writer, raw, is_final);
+        return;
+    }
+    writer.write_bits(is_final as u32, 1);
+    if fixed_bits <= dynamic_bits {
+        writer.write_bits(1, 2);
+        write_tokens(writer, tokens, &fixed_literal_lengths, &fixed_distance_lengths);
+    } else {
+        writer.write_bits(2, 2);
+        header.write(writer);
+        write_tokens(writer, tokens, &dynamic_literal_lengths, &dynamic_distance_lengths);
+    }
+}
+
+fn write_tokens(writer: &mut BitWriter, tokens: &[Token], literal_lengths: &[u8], distance_lengths: &[u8]) {
+    let literal_codes = codes_from_lengths(literal_lengths);
+    let distance_codes = codes_from_lengths(distance_lengths);
+    for token in tokens {
+        match token {
+            Token::Literal(byte) => writer.write_code(literal_codes[*byte as usize], literal_lengths[*byte as usize]),
+            Token::Match { length, distance } => {
+                let index = length_index(*length);
+                let symbol = END_OF_BLOCK + 1 + index;
+                writer.write_code(literal_codes[symbol], literal_lengths[symbol]);
+                writer.write_bits((*length - LENGTH_BASE[index] as usize) as u32, LENGTH_EXTRA[index] as u32);
+                let index = distance_index(*distance);
+                writer.write_code(distance_codes[index], distance_lengths[index]);
+                writer.write_bits((*distance - DISTANCE_BASE[index] as usize) as u32, DISTANCE_EXTRA[index] as u32);
+            },
+        }
+    }
+    writer.write_code(literal_codes[END_OF_BLOCK], literal_lengths[END_OF_BLOCK]);
+}
+
+// An uncompressed block looks like [is_final, 2 bytes length, 2 bytes length 1's complement, data].
+fn write_stored(writer: &mut BitWriter, data: &[u8], is_final: bool) {
+    let chunks: Vec<&[u8]> = if data.is_empty() { vec![data] } else { data.chunks(MAX_STORED).collect() };
+    let last = chunks.len() - 1;
+    for (index, chunk) in chunks.iter().enumerate() {
+        writer.write_bits((is_final && index == last) as u32, 1);
+        writer.write_bits(0, 2);
+        writer.align();
+        let length = chunk.len() as u16;
+        writer.output.extend_from_slice(&length.to_le_bytes());
+        writer.output.extend_from_slice(&(!length).to_le_bytes());
+        writer.output.extend_from_slice(chunk);
+    }
+}
+
+// Deflate packs bits from the least significant end of each byte, but Huffman codes go most significant bit first.
+#[derive(Default)]
+struct BitWriter {
+    output: Vec<u8>,
+    buffer: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn write_bits(&mut self, value: u32, count: u32) {
+        self.buffer |= (value as u64) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.output.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    fn write_code(&mut self, code: u16, length: u8) {
+        let reversed = code.reverse_bits() >> (16 - length as u32);
+        self.write_bits(reversed as u32, length as u32);
+    }
+
+    fn align(&mut self) {
+        if self.count > 0 {
+            self.output.push(self.buffer as u8);
+            self.buffer = 0;
+            self.count = 0;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.align();
+        self.output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_code_lengths() {
+        // Fibonacci frequencies would need 20 bit codes without a limit.
+        let mut frequencies = vec![1u32, 1];
+        while frequencies.len() < 22 {
+            frequencies.push(frequencies[frequencies.len() - 1] + frequencies[frequencies.len() - 2]);
+        }
+        let lengths = code_lengths(&frequencies, MAX_CODE_LENGTH_BITS);
+        assert_eq!(*lengths.iter().max().unwrap() as usize, MAX_CODE_LENGTH_BITS);
+        let kraft: f64 = lengths.iter().map(|l| 0.5f64.powi(*l as i32)).sum();
+        assert_eq!(kraft, 1.0);
+        assert_eq!(code_lengths(&[0, 5, 0], MAX_CODE_BITS), vec![1, 1, 0]); // A lone symbol still gets a partner.
+    }
+
+    #[test]
+    fn test_stored() {
+        assert_eq!(compress(&[], Compression::Default), vec![1, 0, 0, 0xff, 0xff]);
+        assert_eq!(compress(&[7, 8], Compression::Stored), vec![1, 2, 0, 0xfd, 0xff, 7, 8]);
+    }
+
+    #[test]
+    fn test_known_vectors() {
+        // The same bytes zlib writes for a fixed Huffman block (strategy Z_FIXED).
+        assert_eq!(compress(b"a", Compression::Default), vec![0x4b, 0x04, 0x00]);
+        assert_eq!(compress(b"Lemmings lemmings", Compression::Default),
+            vec![0xf3, 0x49, 0xcd, 0xcd, 0xcd, 0xcc, 0x4b, 0x2f, 0x56, 0xc8, 0x81, 0x32, 0x00]); // Includes a 7 byte match.
+
+        // A dynamic Huffman block (block type 2), which zlib inflates back to the input.
+        let dynamic = compress(b"bbaaaabacbaaccaaaaabbbaaabaabdcabbaabaadccaaabaabb", Compression::Default);
+        assert_eq!((dynamic[0] >> 1) & 3, 2);
+        assert_eq!(dynamic, vec![
+            0x1d, 0x89, 0x07, 0x0d, 0x00, 0x00, 0x0c, 0xc2, 0xb4, 0x52, 0xe6, 0x5f, 0xc3, 0x38, 0x81, 0xf0,
+            0x40, 0x01, 0x72, 0xdc, 0xd6, 0xca, 0xb6, 0x88, 0xb3, 0x9a, 0xc3, 0xdb, 0xd7, 0xcc, 0x03,
+        ]);
+    }
+}
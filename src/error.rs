@@ -0,0 +1,117 @@
+// The errors this crate returns. Parsers often don't know which file they're reading, so they
+// leave 'file' empty and the loaders fill it in with in_file.
+
+use std::fmt;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    MissingFile(String), // Eg MAIN.DAT.
+    UnknownGame(String), // Describes what was found instead.
+    CorruptDat { file: String, section_index: usize, problem: SectionProblem },
+    Truncated { file: String, offset: usize }, // Ran out of data while reading at this offset.
+    BadLength { file: String, expected: usize, actual: usize },
+    OutOfRange { file: String, offset: usize, length: usize, available: usize }, // Eg an image location past the end of its section.
+    UnknownGraphicSet { level: String, graphic_set: u16 },
+    MissingTerrain { level: String, terrain_id: usize },
+    MissingObject { level: String, object_id: usize },
+    Invalid { file: String, reason: String },
+}
+
+// Why a section of a DAT file could not be read.
+#[derive(Debug, PartialEq)]
+pub enum SectionProblem {
+    TruncatedHeader { expected: usize, actual: usize }, // Sizes in bytes.
+    CompressedSize { expected: usize, actual: usize }, // Declared size vs the bytes remaining in the file.
+    Checksum { expected: u8, actual: u8 },
+    FirstByteBits(u8), // The header's bit count for the first byte, which can't be more than 8.
+    RanOutOfBits,
+    ReuseBeyondStart { offset: usize, available: usize }, // A reuse chunk pointing before the start of the output.
+    DecompressedSize { expected: usize, actual: usize },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    // Fills in the file name, if it isn't already known.
+    pub fn in_file(mut self, name: &str) -> Error {
+        match &mut self {
+            Error::CorruptDat { file, .. } |
+            Error::Truncated { file, .. } |
+            Error::BadLength { file, .. } |
+            Error::OutOfRange { file, .. } |
+            Error::Invalid { file, .. } if file.is_empty() => *file = name.to_string(),
+            _ => {},
+        }
+        self
+    }
+
+    pub fn invalid(reason: impl Into<String>) -> Error {
+        Error::Invalid { file: String::new(), reason: reason.into() }
+    }
+}
+
+// Ensures data[offset..offset+length] exists.
+pub fn check_range(data: &[u8], offset: usize, length: usize) -> Result<()> {
+    if offset.checked_add(length).is_none_or(|end| end > data.len()) {
+        return Err(Error::OutOfRange { file: String::new(), offset, length, available: data.len() });
+    }
+    Ok(())
+}
+
+// The data from offset onwards, eg where an image starts within its section.
+pub fn from_offset(data: &[u8], offset: usize) -> Result<&[u8]> {
+    data.get(offset..).ok_or(Error::OutOfRange { file: String::new(), offset, length: 0, available: data.len() })
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(error) => write!(f, "{}", error),
+            Error::MissingFile(name) => write!(f, "missing {}", name),
+            Error::UnknownGame(found) => write!(f, "unrecognised game data: {}", found),
+            Error::CorruptDat { file, section_index, problem } => {
+                write!(f, "{} section {}: ", file, section_index)?;
+                match problem {
+                    SectionProblem::TruncatedHeader { expected, actual } =>
+                        write!(f, "truncated header, expected {} bytes but only {} remain", expected, actual),
+                    SectionProblem::CompressedSize { expected, actual } =>
+                        write!(f, "compressed size is {} bytes but {} remain", expected, actual),
+                    SectionProblem::Checksum { expected, actual } =>
+                        write!(f, "checksum mismatch, expected 0x{:02x} but got 0x{:02x}", expected, actual),
+                    SectionProblem::FirstByteBits(bits) =>
+                        write!(f, "first byte has {} bits, the most is 8", bits),
+                    SectionProblem::RanOutOfBits =>
+                        write!(f, "ran out of compressed data"),
+                    SectionProblem::ReuseBeyondStart { offset, available } =>
+                        write!(f, "reuses data {} bytes back but only {} have been decompressed", offset + 1, available),
+                    SectionProblem::DecompressedSize { expected, actual } =>
+                        write!(f, "decompressed to {} bytes instead of {}", actual, expected),
+                }
+            },
+            Error::Truncated { file, offset } => write!(f, "{}: truncated at offset 0x{:x}", file, offset),
+            Error::BadLength { file, expected, actual } => write!(f, "{}: expected {} bytes but got {}", file, expected, actual),
+            Error::OutOfRange { file, offset, length, available } =>
+                write!(f, "{}: {} bytes at offset 0x{:x} are past the end ({} bytes)", file, length, offset, available),
+            Error::UnknownGraphicSet { level, graphic_set } => write!(f, "{}: unknown graphic set {}", level, graphic_set),
+            Error::MissingTerrain { level, terrain_id } => write!(f, "{}: missing terrain {}", level, terrain_id),
+            Error::MissingObject { level, object_id } => write!(f, "{}: missing object {}", level, object_id),
+            Error::Invalid { file, reason } => write!(f, "{}: {}", file, reason),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Error {
+        Error::Io(error)
+    }
+}
@@ -1,41 +1,62 @@
-use anyhow::Result;
+use crate::error::Result;
 
 pub struct File {
-    #[allow(dead_code)]
-    pub path: String, // /path/to/file123.ext
-    #[allow(dead_code)]
     pub name: String, // file123.ext
     pub number: u32, // 123
     pub data: Vec<u8>,
 }
 
-// Starts_with and ends_width should be lowercase. Eg 'vgagr' and '.dat' respectively.
-pub fn find(folder: &str, starts_with: &str, ends_width: &str) -> Result<Vec<File>> {
-    find_2(folder, starts_with, None, ends_width)
+// A game's data files, either read from a folder or supplied from memory.
+// Names are matched case-insensitively, as DOS didn't care and the games' files are a mix.
+pub struct DataFiles {
+    files: Vec<File>,
 }
 
-pub fn find_2(folder: &str, starts_with: &str, starts_with_2: Option<&str>, ends_width: &str) -> Result<Vec<File>> {
-    let mut files: Vec<File> = Vec::new();
-    let entries = std::fs::read_dir(folder)?;
-    for entry in entries {
-        let Ok(entry) = entry else { continue };
-        let path = entry.path().to_string_lossy().to_string();
-        let name = entry.file_name().to_string_lossy().to_string();
-        let lower_name = name.to_lowercase();
-        let has_start =
-            if let Some(starts_with_2) = starts_with_2 {
-                lower_name.starts_with(starts_with) ||
-                    lower_name.starts_with(starts_with_2)
-            } else {
-                lower_name.starts_with(starts_with)
-            };
-        if has_start && lower_name.ends_with(ends_width) {
-            let number = number_ignoring_non_digits(&name);
-            let data = std::fs::read(&path)?;
-            files.push(File { path, name, number, data });
+impl DataFiles {
+    pub fn from_folder(folder: &str) -> Result<DataFiles> {
+        let mut files: Vec<(String, Vec<u8>)> = Vec::new();
+        for entry in std::fs::read_dir(folder)? {
+            let Ok(entry) = entry else { continue };
+            if !entry.file_type()?.is_file() { continue }
+            let name = entry.file_name().to_string_lossy().to_string();
+            files.push((name, std::fs::read(entry.path())?));
         }
+        Ok(DataFiles::from_memory(files))
+    }
+
+    // Takes (name, contents) pairs, eg "LEVEL000.DAT".
+    pub fn from_memory(files: Vec<(String, Vec<u8>)>) -> DataFiles {
+        let mut files: Vec<File> = files.into_iter().map(|(name, data)| {
+            let number = number_ignoring_non_digits(&name);
+            File { name, number, data }
+        }).collect();
+        files.sort_by_key(|f| f.name.to_lowercase());
+        DataFiles { files }
+    }
+
+    // Eg 'main.dat'.
+    pub fn get(&self, name: &str) -> Option<&File> {
+        self.files.iter().find(|f| f.name.eq_ignore_ascii_case(name))
+    }
+
+    // Starts_with and ends_width should be lowercase. Eg 'vgagr' and '.dat' respectively.
+    pub fn find(&self, starts_with: &str, ends_width: &str) -> Vec<&File> {
+        self.find_2(starts_with, None, ends_width)
+    }
+
+    pub fn find_2(&self, starts_with: &str, starts_with_2: Option<&str>, ends_width: &str) -> Vec<&File> {
+        self.files.iter().filter(|file| {
+            let lower_name = file.name.to_lowercase();
+            let has_start =
+                if let Some(starts_with_2) = starts_with_2 {
+                    lower_name.starts_with(starts_with) ||
+                        lower_name.starts_with(starts_with_2)
+                } else {
+                    lower_name.starts_with(starts_with)
+                };
+            has_start && lower_name.ends_with(ends_width)
+        }).collect()
     }
-    Ok(files)
 }
 
 fn number_ignoring_non_digits(s: &str) -> u32 {
@@ -43,7 +64,7 @@ fn number_ignoring_non_digits(s: &str) -> u32 {
     for char in s.chars() {
         if char.is_ascii_digit() {
             let digit = char as u32 - '0' as u32;
-            value = value * 10 + digit as u32;
+            value = value * 10 + digit;
         }
     }
     value
@@ -62,4 +83,16 @@ mod tests {
         assert_eq!(number_ignoring_non_digits("foo123bar"), 123);
         assert_eq!(number_ignoring_non_digits("foobar123"), 123);
     }
+
+    #[test]
+    fn test_case_insensitive_names() {
+        let files = DataFiles::from_memory(vec![
+            ("Main.dat".to_string(), vec![1]),
+            ("LEVEL001.DAT".to_string(), vec![2]),
+            ("level000.dat".to_string(), vec![3]),
+        ]);
+        assert_eq!(files.get("MAIN.DAT").unwrap().data, vec![1]);
+        let levels: Vec<u32> = files.find("level", ".dat").iter().map(|f| f.number).collect();
+        assert_eq!(levels, vec![0, 1]);
+    }
 }
@@ -0,0 +1,267 @@
+// Works out which game a folder of data files is from, so the loaders know its layout and level order.
+// The games differ in level file names (DLVEL in Oh No! More Lemmings), MAIN.DAT layout (it has a 5th
+// rating sign, and a second copy of every section) and which grounds exist.
+// A folder that isn't exactly a known game is matched to the closest one with the same layout,
+// or failing that read generically, rather than refused.
+
+use crate::decompressor;
+use crate::error::{Error, Result};
+use crate::file_finder;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameVariant {
+    Lemmings,
+    OhNoMoreLemmings,
+    ChristmasLemmings1991,
+    ChristmasLemmings1992,
+    HolidayLemmings1993,
+    HolidayLemmings1994,
+    // None of the above, eg a fan made game, or a folder with too much added or missing to tell.
+    // Its levels are read in file order.
+    Generic { level_file_prefix: &'static str, rating_signs: usize },
+}
+
+impl GameVariant {
+    pub fn detect(files: &file_finder::DataFiles) -> Result<GameVariant> {
+        Self::identify(files).map(|(variant, _)| variant)
+    }
+
+    // Like detect, plus a warning when the folder isn't exactly one of the known games.
+    // Only a folder with no levels, grounds or MAIN.DAT at all is an error.
+    pub fn identify(files: &file_finder::DataFiles) -> Result<(GameVariant, Option<String>)> {
+        let fingerprint = Fingerprint::of(files)?;
+        if let Some((variant, _)) = KNOWN.iter().find(|(_, known)| fingerprint.matches(known)) {
+            return Ok((*variant, None));
+        }
+        if let Some(variant) = fingerprint.closest() {
+            return Ok((variant, Some(format!("Treating as {}, though the files differ: {}", variant.name(), fingerprint))));
+        }
+        if fingerprint.level_files == 0 && fingerprint.grounds.is_empty() && fingerprint.main_sections.is_empty() {
+            return Err(Error::UnknownGame(fingerprint.to_string()));
+        }
+        let variant = GameVariant::Generic {
+            level_file_prefix: if fingerprint.level_file_prefix.as_deref() == Some("dlvel") { "dlvel" } else { "level" },
+            rating_signs: if fingerprint.main_sections.get(4) == OH_NO_MORE_MAIN_SECTIONS.get(4) { 5 } else { 4 },
+        };
+        Ok((variant, Some(format!("Unrecognised game, so its levels are in file order: {}", fingerprint))))
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            GameVariant::Lemmings => "Lemmings",
+            GameVariant::OhNoMoreLemmings => "Oh No! More Lemmings",
+            GameVariant::ChristmasLemmings1991 => "Christmas Lemmings 1991",
+            GameVariant::ChristmasLemmings1992 => "Christmas Lemmings 1992",
+            GameVariant::HolidayLemmings1993 => "Holiday Lemmings 1993",
+            GameVariant::HolidayLemmings1994 => "Holiday Lemmings 1994",
+            GameVariant::Generic { .. } => "an unrecognised game",
+        }
+    }
+
+    // Lowercase, eg 'dlvel' for DLVEL000.DAT.
+    pub fn level_file_prefix(&self) -> &'static str {
+        match self {
+            GameVariant::OhNoMoreLemmings => "dlvel",
+            GameVariant::Generic { level_file_prefix, .. } => level_file_prefix,
+            _ => "level",
+        }
+    }
+
+    // How many rating signs are in MAIN.DAT, eg Fun, Tricky, Taxing and Mayhem.
+    pub fn rating_signs(&self) -> usize {
+        match self {
+            GameVariant::OhNoMoreLemmings => 5,
+            GameVariant::Generic { rating_signs, .. } => *rating_signs,
+            _ => 4,
+        }
+    }
+}
+
+// What a folder of data files looks like, eg the level file names and MAIN.DAT section sizes.
+#[derive(Debug, PartialEq)]
+pub struct Fingerprint {
+    pub level_file_prefix: Option<String>, // Eg 'level', if there are any level files.
+    pub level_files: usize,
+    pub grounds: Vec<u32>, // Eg [0, 2] for GROUND0O.DAT and GROUND2O.DAT.
+    pub specials: usize,
+    pub has_oddtable: bool,
+    pub has_greet: bool,
+    pub main_sections: Vec<usize>, // Decompressed sizes, empty if there's no MAIN.DAT.
+}
+
+struct Known {
+    level_file_prefix: &'static str,
+    level_files: usize,
+    grounds: &'static [u32],
+    specials: usize,
+    has_oddtable: bool,
+    has_greet: bool,
+    main_sections: &'static [usize],
+}
+
+const MAIN_SECTIONS: [usize; 7] = [21104, 388, 8384, 61968, 36080, 758, 8224];
+// Section 4 has an extra rating sign (972 bytes), and the 7 sections are stored twice.
+const OH_NO_MORE_MAIN_SECTIONS: [usize; 13] = [21104, 388, 8384, 61968, 37052, 758, 8224, 388, 8384, 61968, 37052, 758, 8224];
+
+const KNOWN: [(GameVariant, Known); 6] = [
+    (GameVariant::Lemmings, Known {
+        level_file_prefix: "level", level_files: 10, grounds: &[0, 1, 2, 3, 4], specials: 4,
+        has_oddtable: true, has_greet: false, main_sections: &MAIN_SECTIONS,
+    }),
+    (GameVariant::OhNoMoreLemmings, Known {
+        level_file_prefix: "dlvel", level_files: 13, grounds: &[0, 1, 2, 3], specials: 4,
+        has_oddtable: false, has_greet: false, main_sections: &OH_NO_MORE_MAIN_SECTIONS,
+    }),
+    (GameVariant::ChristmasLemmings1991, Known {
+        level_file_prefix: "level", level_files: 1, grounds: &[0, 2], specials: 0,
+        has_oddtable: false, has_greet: false, main_sections: &MAIN_SECTIONS,
+    }),
+    (GameVariant::ChristmasLemmings1992, Known {
+        level_file_prefix: "level", level_files: 1, grounds: &[2], specials: 0,
+        has_oddtable: false, has_greet: false, main_sections: &MAIN_SECTIONS,
+    }),
+    (GameVariant::HolidayLemmings1993, Known {
+        level_file_prefix: "level", level_files: 4, grounds: &[1, 2], specials: 0,
+        has_oddtable: false, has_greet: true, main_sections: &MAIN_SECTIONS,
+    }),
+    (GameVariant::HolidayLemmings1994, Known {
+        level_file_prefix: "level", level_files: 8, grounds: &[1, 2], specials: 0,
+        has_oddtable: false, has_greet: true, main_sections: &MAIN_SECTIONS,
+    }),
+];
+
+impl Fingerprint {
+    pub fn of(files: &file_finder::DataFiles) -> Result<Fingerprint> {
+        let levels = files.find("level", ".dat");
+        let dlvels = files.find("dlvel", ".dat");
+        let (level_file_prefix, level_files) = match (levels.len(), dlvels.len()) {
+            (0, 0) => (None, 0),
+            (count, 0) => (Some("level".to_string()), count),
+            (0, count) => (Some("dlvel".to_string()), count),
+            _ => (None, levels.len() + dlvels.len()), // Both kinds, which no game has.
+        };
+        let mut grounds: Vec<u32> = files.find("ground", ".dat").iter().map(|f| f.number).collect();
+        grounds.sort();
+        let main_sections = match files.get("main.dat") {
+            Some(file) => decompressor::decompress(&file.name, &file.data)?.iter().map(|s| s.len()).collect(),
+            None => Vec::new(),
+        };
+        Ok(Fingerprint {
+            level_file_prefix,
+            level_files,
+            grounds,
+            specials: files.find("vgaspec", ".dat").len(),
+            has_oddtable: files.get("oddtable.dat").is_some(),
+            has_greet: files.get("greet.dat").is_some(),
+            main_sections,
+        })
+    }
+
+    fn matches(&self, known: &Known) -> bool {
+        self.level_file_prefix.as_deref() == Some(known.level_file_prefix) &&
+            self.level_files == known.level_files &&
+            self.grounds == known.grounds &&
+            self.specials == known.specials &&
+            self.has_oddtable == known.has_oddtable &&
+            self.has_greet == known.has_greet &&
+            self.main_sections == known.main_sections
+    }
+
+    // A folder with a pack added, or GREET.DAT missing, still has the layout of the game it came from:
+    // its level file names, and MAIN.DAT's section count and section 4 (which holds the rating signs).
+    fn has_layout_of(&self, known: &Known) -> bool {
+        self.level_file_prefix.as_deref() == Some(known.level_file_prefix) &&
+            self.main_sections.len() == known.main_sections.len() &&
+            self.main_sections.get(4) == known.main_sections.get(4)
+    }
+
+    // How many of the other details agree.
+    fn likeness(&self, known: &Known) -> usize {
+        [
+            self.level_files == known.level_files,
+            self.grounds == known.grounds,
+            self.specials == known.specials,
+            self.has_oddtable == known.has_oddtable,
+            self.has_greet == known.has_greet,
+        ].iter().filter(|same| **same).count()
+    }
+
+    // The known game with the same layout that's most alike, the earlier one in KNOWN on a tie.
+    fn closest(&self) -> Option<GameVariant> {
+        KNOWN.iter().rev()
+            .filter(|(_, known)| self.has_layout_of(known))
+            .max_by_key(|(_, known)| self.likeness(known))
+            .map(|(variant, _)| *variant)
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} files, grounds {:?}, {} specials, {}, {}, MAIN.DAT sections {:?}",
+            self.level_files,
+            self.level_file_prefix.as_deref().unwrap_or("level").to_uppercase(),
+            self.grounds,
+            self.specials,
+            if self.has_oddtable { "ODDTABLE.DAT" } else { "no ODDTABLE.DAT" },
+            if self.has_greet { "GREET.DAT" } else { "no GREET.DAT" },
+            self.main_sections)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_bundled_games() {
+        for (folder, variant) in [
+            ("lemmings", GameVariant::Lemmings), ("ohnomore", GameVariant::OhNoMoreLemmings),
+            ("christmas91", GameVariant::ChristmasLemmings1991), ("christmas92", GameVariant::ChristmasLemmings1992),
+            ("holidays93", GameVariant::HolidayLemmings1993), ("holidays94", GameVariant::HolidayLemmings1994),
+        ] {
+            let files = file_finder::DataFiles::from_folder(&format!("data/{}", folder)).unwrap();
+            assert_eq!(GameVariant::detect(&files).unwrap(), variant, "{}", folder);
+        }
+        let files = file_finder::DataFiles::from_memory(vec![("LEVEL000.DAT".to_string(), Vec::new())]);
+        let (variant, warning) = GameVariant::identify(&files).unwrap();
+        assert_eq!(variant, GameVariant::Generic { level_file_prefix: "level", rating_signs: 4 });
+        assert!(warning.unwrap().ends_with(": 1 LEVEL files, grounds [], 0 specials, no ODDTABLE.DAT, no GREET.DAT, MAIN.DAT sections []"));
+        let Err(Error::UnknownGame(_)) = GameVariant::detect(&file_finder::DataFiles::from_memory(Vec::new())) else { panic!() };
+    }
+
+    fn folder_with(folder: &str, keep: impl Fn(&str) -> bool, extra: &[(&str, &str)]) -> file_finder::DataFiles {
+        let mut files: Vec<(String, Vec<u8>)> = std::fs::read_dir(folder).unwrap().map(|entry| {
+            let path = entry.unwrap().path();
+            (path.file_name().unwrap().to_string_lossy().to_string(), std::fs::read(&path).unwrap())
+        }).filter(|(name, _)| keep(name)).collect();
+        for (name, copy_of) in extra {
+            files.push((name.to_string(), std::fs::read(format!("{}/{}", folder, copy_of)).unwrap()));
+        }
+        file_finder::DataFiles::from_memory(files)
+    }
+
+    #[test]
+    fn test_detect_changed_folders() {
+        let files = folder_with("data/holidays94", |name| name != "GREET.DAT", &[]);
+        let (variant, warning) = GameVariant::identify(&files).unwrap();
+        assert_eq!(variant, GameVariant::HolidayLemmings1994);
+        assert!(warning.unwrap().starts_with("Treating as Holiday Lemmings 1994"));
+
+        let files = folder_with("data/lemmings", |_| true, &[("LEVEL010.DAT", "LEVEL009.DAT")]);
+        let (variant, warning) = GameVariant::identify(&files).unwrap();
+        assert_eq!(variant, GameVariant::Lemmings);
+        assert!(warning.is_some());
+        let levels = crate::levels_loader::load(&files, variant).unwrap();
+        assert_eq!(levels.iter().filter(|l| l.position.is_some()).count(), 120);
+        assert_eq!(levels.len(), 128);
+
+        // Without MAIN.DAT there's nothing to tell the games apart, so it's read in file order.
+        let files = folder_with("data/ohnomore", |name| name != "Main.dat", &[]);
+        let (variant, _) = GameVariant::identify(&files).unwrap();
+        assert_eq!(variant, GameVariant::Generic { level_file_prefix: "dlvel", rating_signs: 4 });
+        let levels = crate::levels_loader::load(&files, variant).unwrap();
+        assert_eq!(levels.len(), 100);
+        assert_eq!(levels[99].position.unwrap().title(), "Level 100");
+    }
+}
@@ -0,0 +1,45 @@
+// GREET.DAT is the message shown before the title screen in the holiday editions.
+// Despite the name it isn't compressed: it's plain DOS text with CRLF line endings,
+// terminated by a Ctrl-Z, laid out for the 40 column text screen.
+
+use crate::file_finder;
+use crate::error::{Error, Result};
+
+const END_OF_FILE: u8 = 0x1a; // Ctrl-Z.
+
+pub struct Greet {
+    pub lines: Vec<String>, // Including blank lines, which position the text on screen.
+}
+
+impl Greet {
+    pub fn parse(data: &[u8]) -> Result<Greet> {
+        let end = data.iter().position(|b| *b == END_OF_FILE).unwrap_or(data.len());
+        let Ok(text) = std::str::from_utf8(&data[..end]) else { return Err(Error::Invalid { file: "GREET.DAT".to_string(), reason: "not text".to_string() }) };
+        let lines = text.lines().map(|l| l.to_string()).collect();
+        Ok(Greet { lines })
+    }
+
+    // Errors if there is no GREET.DAT, which is normal for everything but the holiday editions.
+    pub fn load(files: &file_finder::DataFiles) -> Result<Greet> {
+        let Some(file) = files.get("greet.dat") else { return Err(Error::MissingFile("GREET.DAT".to_string())) };
+        Greet::parse(&file.data)
+    }
+
+    pub fn text(&self) -> String {
+        self.lines.iter().map(|l| format!("{}\n", l)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_greet() {
+        let greet = Greet::load(&file_finder::DataFiles::from_folder("data/holidays94").unwrap()).unwrap();
+        assert_eq!(greet.lines.len(), 21);
+        assert_eq!(greet.lines[2], "  Welcome to the special 1994 Holiday");
+        assert!(greet.text().ends_with("Press a key to continue\n"));
+        assert!(Greet::load(&file_finder::DataFiles::from_folder("data/lemmings").unwrap()).is_err());
+    }
+}
@@ -1,11 +1,19 @@
 // This is for parsing lemmings GROUND files:
 // https://www.camanis.net/lemmings/files/docs/lemmings_vgagrx_dat_groundxo_dat_file_format.txt
+// Unlike the .LVL file format, WORDs in groundXo.dat are stored little-endian (camanis.net).
+
+use crate::error::{Error, Result};
+use crate::reader::Reader;
+use crate::vga_colour::{colour_upgrade, read_rgb, write_rgb};
 
 // A 'ground' represents the metadata for a graphics set eg 'hell' or 'pink'.
 pub struct Ground {
     pub object_info: [ObjectInfo; 16],
     pub terrain_info: [TerrainInfo; 64],
-    pub palette: [u32; 16], // 0xrrggbbaa.
+    pub ega_palettes: [u8; 24], // Custom, standard and preview, 8 entries each, as stored.
+    pub palette: [u32; 16], // 0xrrggbbaa. Entries 8-15 are the ground's custom VGA colours, and 7 must be a copy of 8.
+    pub standard_palette: [u32; 8], // VGA, the standard part of the palette as stored.
+    pub preview_palette: [u32; 8], // VGA, for the level preview.
 }
 
 #[derive(Default, Debug, Clone)]
@@ -19,6 +27,8 @@ pub struct ObjectInfo {
     pub height: usize,
     pub animation_frame_data_size: u16,
     pub mask_offset_from_image: u16,
+    pub unknown1: u16,
+    pub unknown2: u16,
     pub trigger_left: u16,
     pub trigger_top: u16,
     pub trigger_width: u8,
@@ -26,6 +36,7 @@ pub struct ObjectInfo {
     pub trigger_effect_id: u8, // 0=none, 1=lemming exits, 4=trigger trap, 5=drown, 6=disintegrate, 7=one way wall left, 8=one way right, 9=steel
     pub animation_frames_base_loc: u16,
     pub preview_image_index: u16,
+    pub unknown3: u16,
     pub trap_sound_effect_id: u8,
 }
 
@@ -35,6 +46,7 @@ pub struct TerrainInfo {
     pub height: usize,
     pub image_loc: usize,
     pub mask_loc: usize,
+    pub unknown: u16,
 }
 
 impl Default for Ground {
@@ -42,78 +54,125 @@ impl Default for Ground {
         Ground {
             object_info: Default::default(),
             terrain_info: [Default::default(); 64], // Default only auto-derives up to 32 element arrays.
+            ega_palettes: Default::default(),
             palette: Default::default(),
+            standard_palette: Default::default(),
+            preview_palette: Default::default(),
         }
     }
 }
 
+const GROUND_SIZE: usize = 1056;
+
 impl Ground {
     /// Parses a ground file.
-    pub fn parse(data: &[u8]) -> Ground {
-        assert!(data.len() == 1056);
+    pub fn parse(data: &[u8]) -> Result<Ground> {
+        if data.len() != GROUND_SIZE {
+            return Err(Error::BadLength { file: String::new(), expected: GROUND_SIZE, actual: data.len() });
+        }
         let mut ground = Ground::default();
-        let mut data_iter = data.into_iter();
+        let mut reader = Reader::new(data);
         for i in 0..16 {
-            ground.object_info[i].animation_flags = read_u16(&mut data_iter);
-            ground.object_info[i].start_animation_frame_index = *data_iter.next().unwrap();
-            ground.object_info[i].frame_count = *data_iter.next().unwrap();
-            ground.object_info[i].width = *data_iter.next().unwrap() as usize;
-            ground.object_info[i].height = *data_iter.next().unwrap() as usize;
-            ground.object_info[i].animation_frame_data_size = read_u16(&mut data_iter);
-            ground.object_info[i].mask_offset_from_image = read_u16(&mut data_iter);
-            let _unknown1 = read_u16(&mut data_iter);
-            let _unknown2 = read_u16(&mut data_iter);
-            ground.object_info[i].trigger_left = read_u16(&mut data_iter);
-            ground.object_info[i].trigger_top = read_u16(&mut data_iter);
-            ground.object_info[i].trigger_width = *data_iter.next().unwrap();
-            ground.object_info[i].trigger_height = *data_iter.next().unwrap();
-            ground.object_info[i].trigger_effect_id = *data_iter.next().unwrap();
-            ground.object_info[i].animation_frames_base_loc = read_u16(&mut data_iter);
-            ground.object_info[i].preview_image_index = read_u16(&mut data_iter);
-            let _unknown3 = read_u16(&mut data_iter);
-            ground.object_info[i].trap_sound_effect_id = *data_iter.next().unwrap();
+            ground.object_info[i].animation_flags = reader.u16_le()?;
+            ground.object_info[i].start_animation_frame_index = reader.u8()?;
+            ground.object_info[i].frame_count = reader.u8()?;
+            ground.object_info[i].width = reader.u8()? as usize;
+            ground.object_info[i].height = reader.u8()? as usize;
+            ground.object_info[i].animation_frame_data_size = reader.u16_le()?;
+            ground.object_info[i].mask_offset_from_image = reader.u16_le()?;
+            ground.object_info[i].unknown1 = reader.u16_le()?;
+            ground.object_info[i].unknown2 = reader.u16_le()?;
+            ground.object_info[i].trigger_left = reader.u16_le()?;
+            ground.object_info[i].trigger_top = reader.u16_le()?;
+            ground.object_info[i].trigger_width = reader.u8()?;
+            ground.object_info[i].trigger_height = reader.u8()?;
+            ground.object_info[i].trigger_effect_id = reader.u8()?;
+            ground.object_info[i].animation_frames_base_loc = reader.u16_le()?;
+            ground.object_info[i].preview_image_index = reader.u16_le()?;
+            ground.object_info[i].unknown3 = reader.u16_le()?;
+            ground.object_info[i].trap_sound_effect_id = reader.u8()?;
         }
         ground.object_info[0].is_exit = true;
         ground.object_info[1].is_entrance = true;
         for i in 0..64 {
-            ground.terrain_info[i].width = *data_iter.next().unwrap() as usize;
-            ground.terrain_info[i].height = *data_iter.next().unwrap() as usize;
-            ground.terrain_info[i].image_loc = read_u16(&mut data_iter) as usize;
-            ground.terrain_info[i].mask_loc = read_u16(&mut data_iter) as usize;
-            let _unknown = read_u16(&mut data_iter);
-        }
-        for _ in 0..24 {
-            let _ = *data_iter.next().unwrap(); // ega.
+            ground.terrain_info[i].width = reader.u8()? as usize;
+            ground.terrain_info[i].height = reader.u8()? as usize;
+            ground.terrain_info[i].image_loc = reader.u16_le()? as usize;
+            ground.terrain_info[i].mask_loc = reader.u16_le()? as usize;
+            ground.terrain_info[i].unknown = reader.u16_le()?;
         }
+        ground.ega_palettes.copy_from_slice(reader.bytes(24)?);
         let mut upper_palette: [u32; 8] = [0; 8];
-        for i in 0..8 {
-            upper_palette[i] = read_rgb(&mut data_iter);
+        for colour in upper_palette.iter_mut() {
+            *colour = read_rgb(&mut reader)?;
         }
         ground.palette = extend_palette(upper_palette);
-        for _ in 0..16 { // vga standard/preview.
-            let _ = *data_iter.next().unwrap(); // r.
-            let _ = *data_iter.next().unwrap(); // g.
-            let _ = *data_iter.next().unwrap(); // b.
+        for colour in ground.standard_palette.iter_mut() {
+            *colour = read_rgb(&mut reader)?;
         }
-        ground
+        for colour in ground.preview_palette.iter_mut() {
+            *colour = read_rgb(&mut reader)?;
+        }
+        Ok(ground)
     }
-}
 
-// Unlike the .LVL file format, WORDs in groundXo.dat are stored little-endian (camanis.net).
-fn read_u16<'a, I>(data: &mut I) -> u16
-where I: Iterator<Item = &'a u8> {
-    let little = *data.next().unwrap();
-    let big = *data.next().unwrap();
-    return ((big as u16) << 8) + (little as u16);
+    // The inverse of parse, eg after changing a trigger area or rebuilding the graphics with ground_pack.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut data: Vec<u8> = Vec::with_capacity(GROUND_SIZE);
+        for (i, object) in self.object_info.iter().enumerate() {
+            let (Ok(width), Ok(height)) = (u8::try_from(object.width), u8::try_from(object.height)) else {
+                return Err(Error::invalid(format!("object {} is {}x{}, the most is 255x255", i, object.width, object.height)));
+            };
+            write_u16_le(&mut data, object.animation_flags);
+            data.push(object.start_animation_frame_index);
+            data.push(object.frame_count);
+            data.push(width);
+            data.push(height);
+            write_u16_le(&mut data, object.animation_frame_data_size);
+            write_u16_le(&mut data, object.mask_offset_from_image);
+            write_u16_le(&mut data, object.unknown1);
+            write_u16_le(&mut data, object.unknown2);
+            write_u16_le(&mut data, object.trigger_left);
+            write_u16_le(&mut data, object.trigger_top);
+            data.push(object.trigger_width);
+            data.push(object.trigger_height);
+            data.push(object.trigger_effect_id);
+            write_u16_le(&mut data, object.animation_frames_base_loc);
+            write_u16_le(&mut data, object.preview_image_index);
+            write_u16_le(&mut data, object.unknown3);
+            data.push(object.trap_sound_effect_id);
+        }
+        for (i, terrain) in self.terrain_info.iter().enumerate() {
+            let (Ok(width), Ok(height)) = (u8::try_from(terrain.width), u8::try_from(terrain.height)) else {
+                return Err(Error::invalid(format!("terrain {} is {}x{}, the most is 255x255", i, terrain.width, terrain.height)));
+            };
+            let (Ok(image_loc), Ok(mask_loc)) = (u16::try_from(terrain.image_loc), u16::try_from(terrain.mask_loc)) else {
+                return Err(Error::invalid(format!("terrain {} is past the 64KB a section can hold", i)));
+            };
+            data.push(width);
+            data.push(height);
+            write_u16_le(&mut data, image_loc);
+            write_u16_le(&mut data, mask_loc);
+            write_u16_le(&mut data, terrain.unknown);
+        }
+        data.extend_from_slice(&self.ega_palettes);
+        if self.palette[7] != self.palette[8] { // Only 8 is stored, so 7 would be lost.
+            return Err(Error::invalid(format!("palette colour 7 is {:08x}, but must match colour 8, {:08x}", self.palette[7], self.palette[8])));
+        }
+        for colour in self.palette[8..].iter().chain(&self.standard_palette).chain(&self.preview_palette) {
+            write_rgb(&mut data, *colour);
+        }
+        Ok(data)
+    }
 }
 
-// Upgrades a 6-bit colour to 8, while still allowing 100% black and white.
-fn colour_upgrade(six: u8) -> u8 {
-    if six == 0 { 0 } else { (six << 2) + 3 }
+fn write_u16_le(data: &mut Vec<u8>, value: u16) {
+    data.push(value as u8);
+    data.push((value >> 8) as u8);
 }
 
 // Extends an upper palette to the full one.
-fn extend_palette(upper_palette: [u32; 8]) -> [u32; 16] {
+pub fn extend_palette(upper_palette: [u32; 8]) -> [u32; 16] {
     fn rgba_from_docs(rgb: u32) -> u32 {
         let r6: u8 = (rgb >> 16) as u8;
         let g6: u8 = (rgb >> 8) as u8; // 'as u8' simply truncates the red bits.
@@ -121,7 +180,7 @@ fn extend_palette(upper_palette: [u32; 8]) -> [u32; 16] {
         let r8: u8 = colour_upgrade(r6);
         let g8: u8 = colour_upgrade(g6);
         let b8: u8 = colour_upgrade(b6);
-        return ((r8 as u32) << 24) + ((g8 as u32) << 16) + ((b8 as u32) << 8) + 0xff;
+        ((r8 as u32) << 24) + ((g8 as u32) << 16) + ((b8 as u32) << 8) + 0xff
     }
     [
         rgba_from_docs(0x000000), // black.
@@ -143,15 +202,29 @@ fn extend_palette(upper_palette: [u32; 8]) -> [u32; 16] {
     ]
 }
 
-// Read 3 RGB bytes, converting to 0-255 RGBA format
-// Source file: (0x3F, 0x00, 0x00) gives you the brightest red you can get (camanis.net)
-fn read_rgb<'a, I>(data: &mut I) -> u32
-where I: Iterator<Item = &'a u8> {
-    let r6 = *data.next().unwrap();
-    let g6 = *data.next().unwrap();
-    let b6 = *data.next().unwrap();
-    let r8: u8 = colour_upgrade(r6);
-    let g8: u8 = colour_upgrade(g6);
-    let b8: u8 = colour_upgrade(b6);
-    return ((r8 as u32) << 24) + ((g8 as u32) << 16) + ((b8 as u32) << 8) + 0xff;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_finder;
+
+    #[test]
+    fn test_rewrite_original_grounds() {
+        for folder in ["lemmings", "ohnomore", "christmas91", "christmas92", "holidays93", "holidays94"] {
+            let files = file_finder::DataFiles::from_folder(&format!("data/{}", folder)).unwrap();
+            for file in files.find("ground", ".dat") {
+                let ground = Ground::parse(&file.data).unwrap();
+                assert_eq!(ground.to_bytes().unwrap(), file.data, "{} {}", folder, file.name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_unstorable_palette() {
+        let files = file_finder::DataFiles::from_folder("data/lemmings").unwrap();
+        let mut ground = Ground::parse(&files.find("ground", ".dat")[0].data).unwrap();
+        ground.palette[7] = !ground.palette[8];
+        assert!(matches!(ground.to_bytes(), Err(Error::Invalid { .. })));
+        ground.palette[7] = ground.palette[8];
+        assert!(ground.to_bytes().is_ok());
+    }
 }
@@ -0,0 +1,115 @@
+// This is for building VGAGRx.DAT files for custom graphic sets, the inverse of grounds_loader.
+// Terrain is laid out in id order, each image followed by its mask, like the originals. Objects are in
+// id order too, with each frame's planes followed by its mask. Images are indexed into the ground's palette.
+// https://www.camanis.net/lemmings/files/docs/lemmings_vgagrx_dat_groundxo_dat_file_format.txt
+
+use crate::compressor;
+use crate::error::{Error, Result};
+use crate::grounds_loader::GroundWithImages;
+
+pub fn file_name(ground_number: u32) -> String {
+    format!("VGAGR{}.DAT", ground_number)
+}
+
+// Converts a location to the 16 bit word it's stored as in the ground.
+fn location(value: usize, what: &str) -> Result<u16> {
+    u16::try_from(value).map_err(|_| Error::invalid(format!("{} at {} is past the 64KB a section can hold", what, value)))
+}
+
+// Returns the uncompressed terrain and object sections, updating the ground's terrain and object
+// info to point into them. Missing terrain and objects get zero sizes.
+pub fn build_sections(ground: &mut GroundWithImages) -> Result<[Vec<u8>; 2]> {
+    let mut terrain_section: Vec<u8> = Vec::new();
+    for (id, info) in ground.ground.terrain_info.iter_mut().enumerate() {
+        let Some(image) = ground.terrain.get(&id) else {
+            info.width = 0;
+            info.height = 0;
+            info.image_loc = 0;
+            info.mask_loc = 0;
+            continue;
+        };
+        let (planes, mask) = image.to_4bpp_plus_mask()?;
+        info.width = image.width;
+        info.height = image.height;
+        info.image_loc = location(terrain_section.len(), &format!("terrain {}", id))? as usize;
+        // The originals save space by pointing the mask at the 4th plane when they're the same.
+        let fourth_plane = planes.len() - mask.len();
+        let shares_fourth_plane = (image.width * image.height) % 8 == 0 && planes[fourth_plane..] == mask[..];
+        terrain_section.extend(planes);
+        if shares_fourth_plane {
+            info.mask_loc = location(info.image_loc + fourth_plane, &format!("terrain {} mask", id))? as usize;
+        } else {
+            info.mask_loc = location(terrain_section.len(), &format!("terrain {} mask", id))? as usize;
+            terrain_section.extend(mask);
+        }
+    }
+
+    let mut object_section: Vec<u8> = Vec::new();
+    for (id, info) in ground.ground.object_info.iter_mut().enumerate() {
+        let Some(animation) = ground.objects.get(&id) else {
+            info.width = 0;
+            info.height = 0;
+            info.frame_count = 0;
+            info.animation_frames_base_loc = 0;
+            info.mask_offset_from_image = 0;
+            info.animation_frame_data_size = 0;
+            continue;
+        };
+        let Ok(frame_count) = u8::try_from(animation.frames.len()) else {
+            return Err(Error::invalid(format!("object {} has {} frames, the most is 255", id, animation.frames.len())));
+        };
+        info.width = animation.width;
+        info.height = animation.height;
+        info.frame_count = frame_count;
+        info.animation_frames_base_loc = location(object_section.len(), &format!("object {}", id))?;
+        for index in 0..animation.frames.len() {
+            let (planes, mask) = animation.frame_to_4bpp_plus_mask(index)?;
+            info.mask_offset_from_image = location(planes.len(), &format!("object {} mask", id))?;
+            info.animation_frame_data_size = location(planes.len() + mask.len(), &format!("object {} frame", id))?;
+            object_section.extend(planes);
+            object_section.extend(mask);
+        }
+    }
+    Ok([terrain_section, object_section])
+}
+
+// The compressed VGAGRx.DAT. Save the ground too with Ground::to_bytes, as its terrain and object info will have changed.
+pub fn build(ground: &mut GroundWithImages) -> Result<Vec<u8>> {
+    compressor::compress(&build_sections(ground)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decompressor;
+    use crate::file_finder;
+    use crate::grounds_loader;
+
+    #[test]
+    fn test_rebuild_original_grounds() {
+        let files = file_finder::DataFiles::from_folder("data/lemmings").unwrap();
+        let mut grounds = grounds_loader::load(&files).unwrap();
+        for (number, ground) in grounds.iter_mut() {
+            let original = files.get(&file_name(*number).to_lowercase()).unwrap();
+            let original_sections = decompressor::decompress(&original.name, &original.data).unwrap();
+            let data = build(ground).unwrap();
+            let sections = decompressor::decompress(&file_name(*number), &data).unwrap();
+            assert_eq!(sections[0], original_sections[0], "ground {}", number); // The objects are in a different order.
+
+            // Load it back with the updated ground.
+            let rebuilt = file_finder::DataFiles::from_memory(vec![
+                (file_name(*number), data),
+                (format!("GROUND{}O.DAT", number), ground.ground.to_bytes().unwrap()),
+            ]);
+            let reloaded = grounds_loader::load(&rebuilt).unwrap().remove(number).unwrap();
+            assert_eq!(reloaded.terrain.len(), ground.terrain.len());
+            for (id, image) in &ground.terrain {
+                assert_eq!((&reloaded.terrain[id].indices, &reloaded.terrain[id].mask), (&image.indices, &image.mask));
+            }
+            assert_eq!(reloaded.objects.len(), ground.objects.len());
+            for (id, animation) in &ground.objects {
+                assert_eq!((&reloaded.objects[id].frames, &reloaded.objects[id].masks), (&animation.frames, &animation.masks));
+            }
+        }
+    }
+}
@@ -3,33 +3,36 @@ use crate::ground;
 use crate::image;
 use crate::decompressor;
 use std::collections::HashMap;
-use anyhow::Result;
+use crate::error::{Error, Result};
 
-pub fn load(path: &str) -> Result<HashMap<u32, GroundWithImages>> {
-    let mut meta = load_metadata(path)?;
+pub fn load(files: &file_finder::DataFiles) -> Result<HashMap<u32, GroundWithImages>> {
+    let mut meta = load_metadata(files)?;
     let mut all = HashMap::<u32, GroundWithImages>::new();
-    for file in file_finder::find(path, "vgagr", ".dat")? {
+    for file in files.find("vgagr", ".dat") {
         let Some(ground) = meta.remove(&file.number) else { continue };
-        let vgagr = decompressor::decompress(&file.data);
+        let vgagr = decompressor::decompress(&file.name, &file.data)?;
+        let [terrain_section, object_section, ..] = vgagr.as_slice() else {
+            return Err(Error::Invalid { file: file.name.clone(), reason: format!("expected 2 sections but got {}", vgagr.len()) });
+        };
 
         // Load the terrain imagery.
-        let mut terrain = HashMap::<usize, image::Image>::new();
+        let mut terrain = HashMap::<usize, image::IndexedImage>::new();
         for (i, info) in ground.terrain_info.iter().enumerate() {
             if info.width == 0 || info.height == 0 { continue }
-            let image = image::Image::parse_4bpp_plus_mask(&vgagr[0], info.width, info.height,
-                info.image_loc, info.mask_loc, &ground.palette);
+            let image = image::IndexedImage::parse_4bpp_plus_mask(terrain_section, info.width, info.height,
+                info.image_loc, info.mask_loc, &ground.palette).map_err(|e| e.in_file(&file.name))?;
             terrain.insert(i, image);
         }
         
         // Load the objects.
-        let mut objects = HashMap::<usize, image::Animation>::new();
+        let mut objects = HashMap::<usize, image::IndexedAnimation>::new();
         for (i, object) in ground.object_info.iter().enumerate() {
             if object.width == 0 || object.height == 0 || object.frame_count == 0 { continue }
-            let animation = image::Animation::parse_4bpp_plus_mask(&vgagr[1], object.width, object.height,
+            let animation = image::IndexedAnimation::parse_4bpp_plus_mask(object_section, object.width, object.height,
                 object.frame_count as usize,
                 object.animation_frames_base_loc as usize,
                 object.animation_frames_base_loc as usize + object.mask_offset_from_image as usize,
-                &ground.palette, object.animation_frame_data_size as usize);
+                &ground.palette, object.animation_frame_data_size as usize).map_err(|e| e.in_file(&file.name))?;
             objects.insert(i, animation);
         }
 
@@ -40,16 +43,15 @@ pub fn load(path: &str) -> Result<HashMap<u32, GroundWithImages>> {
 
 pub struct GroundWithImages {
     pub ground: ground::Ground, 
-    pub terrain: HashMap<usize, image::Image>,
-    pub objects: HashMap<usize, image::Animation>,
+    pub terrain: HashMap<usize, image::IndexedImage>,
+    pub objects: HashMap<usize, image::IndexedAnimation>,
 }
 
 // Loads all grounds' metadata (graphic sets).
-fn load_metadata(path: &str) -> Result<HashMap<u32, ground::Ground>> {
-    let files = file_finder::find(path, "ground", ".dat")?;
+fn load_metadata(files: &file_finder::DataFiles) -> Result<HashMap<u32, ground::Ground>> {
     let mut all = HashMap::<u32, ground::Ground>::new();
-    for file in files {
-        let ground = ground::Ground::parse(&file.data);
+    for file in files.find("ground", ".dat") {
+        let ground = ground::Ground::parse(&file.data).map_err(|e| e.in_file(&file.name))?;
         all.insert(file.number, ground);
     }
     Ok(all)
@@ -1,6 +1,8 @@
 use crate::bit_iter_ms_first;
+use crate::error::{Error, Result, check_range};
 use crate::png;
 
+// RGBA, eg a rendered level.
 #[derive(Default)]
 pub struct Image {
     pub bitmap: Vec<u32>,
@@ -14,6 +16,26 @@ pub struct Animation {
     pub height: usize,
 }
 
+// Graphics as the game stores them: palette indices, plus a mask for terrain and objects.
+// Convert to RGBA when needed with to_image. Swap palettes by replacing the palette.
+#[derive(Clone, Default)]
+pub struct IndexedImage {
+    pub indices: Vec<u8>,
+    pub palette: Vec<u32>, // 0xrrggbbaa.
+    pub mask: Option<Vec<bool>>, // True where the pixel is drawn. None if every pixel is.
+    pub width: usize,
+    pub height: usize,
+}
+
+#[derive(Clone, Default)]
+pub struct IndexedAnimation {
+    pub frames: Vec<Vec<u8>>, // Indices for each frame.
+    pub palette: Vec<u32>,
+    pub masks: Option<Vec<Vec<bool>>>, // One per frame.
+    pub width: usize,
+    pub height: usize,
+}
+
 pub struct Mask {
     pub frames: Vec<Vec<u8>>, // 1 means take a pixel out, 0 means leave alone.
     pub width: usize,
@@ -30,176 +52,298 @@ impl Mask {
         let animation = Animation { frames, width: self.width, height: self.height };
         animation.as_apng()
     }
+
+    // A palette APNG where index 1 marks the pixels taken out.
+    pub fn as_indexed_apng(&self) -> Vec<u8> {
+        let frames: Vec<Vec<u8>> = self.frames.iter().map(|frame|
+            frame.iter().map(|pixel| (*pixel != 0) as u8).collect()
+        ).collect();
+        png::indexed_apng_data(self.width as u32, self.height as u32, &frames, &[0, 0x888888ff], png::Compression::Default)
+    }
 }
 
 impl Image {
+    // Reads any PNG, eg one that was exported then edited.
+    pub fn from_png(data: &[u8]) -> Result<Image> {
+        png::read_png(data)
+    }
+
+    pub fn as_png(&self) -> Vec<u8> {
+        png::png_data(self.width as u32, self.height as u32, &self.bitmap, png::Compression::Default)
+    }
+}
+
+impl Animation {
+    pub fn from_apng(data: &[u8]) -> Result<Animation> {
+        png::read_apng(data)
+    }
+
+    pub fn as_apng(&self) -> Vec<u8> {
+        png::apng_data(self.width as u32, self.height as u32, &self.frames, png::Compression::Default)
+    }
+}
+
+impl IndexedImage {
     /// Parses where 0=transparent, 1=white.
-    pub fn parse_1bpp(data: &[u8], width: usize, height: usize) -> Image {
+    pub fn parse_1bpp(data: &[u8], width: usize, height: usize) -> Result<IndexedImage> {
         let pixels = width * height;
-        let mut plane = bit_iter_ms_first::iterate(data);
-        let mut bitmap: Vec<u32> = Vec::with_capacity(pixels);
-        for _ in 0..pixels {
-            let bit = plane.next().unwrap();
-            bitmap.push(if bit==0 { 0 } else { 0xffffffff } );
-        }
-        Image { bitmap, width, height }
+        check_bits(data, 0, pixels)?;
+        let indices: Vec<u8> = bit_iter_ms_first::iterate(data).take(pixels).collect();
+        Ok(IndexedImage { indices, palette: vec![0, 0xffffffff], mask: None, width, height })
+    }
+
+    pub fn parse_2bpp(data: &[u8], width: usize, height: usize, palette: &[u32; 16]) -> Result<IndexedImage> {
+        let indices = parse_2bpp_frame(data, width, height, 0)?;
+        Ok(IndexedImage { indices, palette: palette.to_vec(), mask: None, width, height })
     }
 
-    pub fn parse_2bpp(data: &[u8], width: usize, height: usize, palette: &[u32; 16]) -> Image {
-        let bitmap = parse_2bpp_frame(data, width, height, 0, &palette);
-        Image { bitmap, width, height }
+    pub fn parse_3bpp(data: &[u8], width: usize, height: usize, palette: &[u32; 16]) -> Result<IndexedImage> {
+        let indices = parse_3bpp_frame(data, width, height, 0)?;
+        Ok(IndexedImage { indices, palette: palette.to_vec(), mask: None, width, height })
     }
 
-    pub fn parse_3bpp(data: &[u8], width: usize, height: usize, palette: &[u32; 16]) -> Image {
-        let bitmap = parse_3bpp_frame(data, width, height, 0, &palette);
-        Image { bitmap, width, height }
+    pub fn parse_4bpp(data: &[u8], width: usize, height: usize, palette: &[u32; 16]) -> Result<IndexedImage> {
+        let indices = parse_4bpp_frame(data, width, height, 0)?;
+        Ok(IndexedImage { indices, palette: palette.to_vec(), mask: None, width, height })
     }
 
-    pub fn parse_4bpp(data: &[u8], width: usize, height: usize, palette: &[u32; 16]) -> Image {
-        let bitmap = parse_4bpp_frame(data, width, height, 0, &palette);
-        Image { bitmap, width, height }
+    pub fn parse_4bpp_plus_mask(data: &[u8], width: usize, height: usize, image_loc: usize, mask_loc: usize, palette: &[u32; 16]) -> Result<IndexedImage> {
+        let indices = parse_4bpp_frame(data, width, height, image_loc * 8)?;
+        let mask = parse_mask_frame(data, width, height, mask_loc * 8)?;
+        Ok(IndexedImage { indices, palette: palette.to_vec(), mask: Some(mask), width, height })
     }
 
-    pub fn parse_4bpp_plus_mask(data: &[u8], width: usize, height: usize, image_loc: usize, mask_loc: usize, palette: &[u32; 16]) -> Image {
-        let bitmap = parse_4bpp_plus_mask_frame(data, width, height, image_loc, mask_loc, palette);
-        Image { bitmap, width, height }
+    // The reverse of parse_4bpp_plus_mask: the 4 bit-planes, then the mask, which is all drawn if there isn't one.
+    pub fn to_4bpp_plus_mask(&self) -> Result<(Vec<u8>, Vec<u8>)> {
+        encode_4bpp_plus_mask_frame(&self.indices, self.mask.as_deref())
+    }
+
+    // 0xrrggbbaa for each pixel, with masked out pixels transparent.
+    pub fn rgba(&self) -> Vec<u32> {
+        colours(&self.indices, self.mask.as_deref(), &self.palette)
+    }
+
+    pub fn to_image(&self) -> Image {
+        Image { bitmap: self.rgba(), width: self.width, height: self.height }
+    }
+
+    // The reverse of to_image: each pixel takes the nearest palette colour, and mostly transparent pixels are masked out.
+    // Only the first 255 colours are used, leaving an index for masked out pixels in indexed PNGs.
+    pub fn from_image(image: &Image, palette: &[u32]) -> IndexedImage {
+        let palette = &palette[..palette.len().min(255)];
+        let indices: Vec<u8> = image.bitmap.iter().map(|colour| nearest_colour(*colour, palette)).collect();
+        let mask: Vec<bool> = image.bitmap.iter().map(|colour| colour & 0xff >= 0x80).collect();
+        let mask = if mask.iter().all(|drawn| *drawn) { None } else { Some(mask) };
+        IndexedImage { indices, palette: palette.to_vec(), mask, width: image.width, height: image.height }
     }
 
     pub fn as_png(&self) -> Vec<u8> {
-        png::png_data(self.width as u32, self.height as u32, &self.bitmap)
+        self.to_image().as_png()
+    }
+
+    // A palette PNG with the original indices.
+    pub fn as_indexed_png(&self) -> Vec<u8> {
+        let indices = png_indices(&self.indices, self.mask.as_deref(), &self.palette);
+        let palette = png_palette(&self.palette, self.mask.is_some());
+        png::indexed_png_data(self.width as u32, self.height as u32, &indices, &palette, png::Compression::Default)
     }
 }
 
-impl Animation {
-    pub fn parse_4bpp_plus_mask(data: &[u8], width: usize, height: usize, frame_count: usize, image_loc: usize, mask_loc: usize, palette: &[u32; 16], stride: usize) -> Animation {
-        let mut frames: Vec<Vec<u32>> = Vec::new();
+impl IndexedAnimation {
+    #[allow(clippy::too_many_arguments)]
+    pub fn parse_4bpp_plus_mask(data: &[u8], width: usize, height: usize, frame_count: usize, image_loc: usize, mask_loc: usize, palette: &[u32; 16], stride: usize) -> Result<IndexedAnimation> {
+        let mut frames: Vec<Vec<u8>> = Vec::new();
+        let mut masks: Vec<Vec<bool>> = Vec::new();
         for i in 0..frame_count {
             let offset = stride * i;
-            let frame = parse_4bpp_plus_mask_frame(data, width, height, offset + image_loc, offset + mask_loc, palette);
-            frames.push(frame);
+            frames.push(parse_4bpp_frame(data, width, height, (offset + image_loc) * 8)?);
+            masks.push(parse_mask_frame(data, width, height, (offset + mask_loc) * 8)?);
         }
-        Animation { frames, width, height }
+        Ok(IndexedAnimation { frames, palette: palette.to_vec(), masks: Some(masks), width, height })
     }
 
-    fn parse_4bpp(data: &[u8], width: usize, height: usize, frame_count: usize, palette: &[u32; 16]) -> Animation {
-        let mut frames: Vec<Vec<u32>> = Vec::new();
+    pub fn parse(data: &[u8], width: usize, height: usize, frame_count: usize, palette: &[u32; 16], bpp: u8) -> Result<IndexedAnimation> {
+        let parse_frame = match bpp {
+            2 => parse_2bpp_frame,
+            3 => parse_3bpp_frame,
+            4 => parse_4bpp_frame,
+            _ => return Err(Error::invalid(format!("unsupported bpp {}", bpp))),
+        };
+        let mut frames: Vec<Vec<u8>> = Vec::new();
         for i in 0..frame_count {
-            let offset = i * width * height * 4;
-            let frame = parse_4bpp_frame(data, width, height, offset, palette);
-            frames.push(frame);
+            let offset = i * width * height * bpp as usize;
+            frames.push(parse_frame(data, width, height, offset)?);
         }
-        Animation { frames, width, height }
+        Ok(IndexedAnimation { frames, palette: palette.to_vec(), masks: None, width, height })
     }
 
-    fn parse_3bpp(data: &[u8], width: usize, height: usize, frame_count: usize, palette: &[u32; 16]) -> Animation {
-        let mut frames: Vec<Vec<u32>> = Vec::new();
-        for i in 0..frame_count {
-            let offset = i * width * height * 3;
-            let frame = parse_3bpp_frame(data, width, height, offset, palette);
-            frames.push(frame);
-        }
-        Animation { frames, width, height }
+    // The reverse of parse_4bpp_plus_mask, for one frame.
+    pub fn frame_to_4bpp_plus_mask(&self, index: usize) -> Result<(Vec<u8>, Vec<u8>)> {
+        let mask = self.masks.as_ref().map(|masks| masks[index].as_slice());
+        encode_4bpp_plus_mask_frame(&self.frames[index], mask)
     }
 
-    fn parse_2bpp(data: &[u8], width: usize, height: usize, frame_count: usize, palette: &[u32; 16]) -> Animation {
-        let mut frames: Vec<Vec<u32>> = Vec::new();
-        for i in 0..frame_count {
-            let offset = i * width * height * 2;
-            let frame = parse_2bpp_frame(data, width, height, offset, palette);
-            frames.push(frame);
-        }
-        Animation { frames, width, height }
-    }
-    
-    pub fn parse(data: &[u8], width: usize, height: usize, frames: usize, palette: &[u32; 16], bpp: u8) -> Animation {
-        if bpp == 2 {
-            Self::parse_2bpp(data, width, height, frames, palette)
-        } else if bpp == 3 {
-            Self::parse_3bpp(data, width, height, frames, palette)
-        } else if bpp == 4 {
-            Self::parse_4bpp(data, width, height, frames, palette)
-        } else {
-            panic!("Unsupported BPP")
-        }
+    pub fn frame_rgba(&self, index: usize) -> Vec<u32> {
+        let mask = self.masks.as_ref().and_then(|masks| masks.get(index)).map(|mask| mask.as_slice());
+        colours(&self.frames[index], mask, &self.palette)
+    }
+
+    pub fn to_animation(&self) -> Animation {
+        let frames = (0..self.frames.len()).map(|i| self.frame_rgba(i)).collect();
+        Animation { frames, width: self.width, height: self.height }
     }
 
     pub fn as_apng(&self) -> Vec<u8> {
-        png::apng_data(self.width as u32, self.height as u32, &self.frames)
+        self.to_animation().as_apng()
+    }
+
+    // A palette APNG with the original indices.
+    pub fn as_indexed_apng(&self) -> Vec<u8> {
+        let frames: Vec<Vec<u8>> = self.frames.iter().enumerate().map(|(i, frame)| {
+            let mask = self.masks.as_ref().map(|masks| masks[i].as_slice());
+            png_indices(frame, mask, &self.palette)
+        }).collect();
+        let palette = png_palette(&self.palette, self.masks.is_some());
+        png::indexed_apng_data(self.width as u32, self.height as u32, &frames, &palette, png::Compression::Default)
     }
 }
 
 // Helpers used for both images and animations:
-pub fn parse_4bpp_plus_mask_frame(data: &[u8], width: usize, height: usize, image_loc: usize, mask_loc: usize, palette: &[u32; 16]) -> Vec<u32> {
-    let image_data: &[u8] = &data[image_loc..];
-    let mask_data: &[u8] = &data[mask_loc..];
+
+// Indices past the end of the palette, eg after swapping in a shorter one, are transparent.
+fn colours(indices: &[u8], mask: Option<&[bool]>, palette: &[u32]) -> Vec<u32> {
+    let colour = |index: &u8| palette.get(*index as usize).copied().unwrap_or(0);
+    match mask {
+        Some(mask) => indices.iter().zip(mask).map(|(index, drawn)| if *drawn { colour(index) } else { 0 }).collect(),
+        None => indices.iter().map(colour).collect(),
+    }
+}
+
+// Index of the palette entry closest to the colour, ignoring alpha.
+fn nearest_colour(colour: u32, palette: &[u32]) -> u8 {
+    let channel = |c: u32, shift: u32| ((c >> shift) & 0xff) as i32;
+    let distance = |entry: u32| -> i32 {
+        [24, 16, 8].iter().map(|shift| (channel(colour, *shift) - channel(entry, *shift)).pow(2)).sum()
+    };
+    (0..palette.len()).min_by_key(|index| distance(palette[*index])).unwrap_or(0) as u8
+}
+
+// PNGs have no separate mask, so masked out pixels get an extra transparent palette entry.
+fn png_indices(indices: &[u8], mask: Option<&[bool]>, palette: &[u32]) -> Vec<u8> {
+    let Some(mask) = mask else { return indices.to_vec() };
+    let masked_index = palette.len() as u8;
+    indices.iter().zip(mask).map(|(index, drawn)| if *drawn { *index } else { masked_index }).collect()
+}
+
+fn png_palette(palette: &[u32], masked: bool) -> Vec<u32> {
+    let mut palette = palette.to_vec();
+    if masked {
+        palette.push(0);
+    }
+    palette
+}
+
+// Ensures there are enough bits for the planes, so the bit iterators below can't run dry.
+// Returns the data from the byte the bits start in, and the offset within it, so the iterators skip less.
+fn check_bits(data: &[u8], offset_bits: usize, bits: usize) -> Result<(&[u8], usize)> {
+    let start = offset_bits / 8;
+    check_range(data, start, (offset_bits + bits).div_ceil(8) - start)?;
+    Ok((&data[start..], offset_bits % 8))
+}
+
+// 1 bit per pixel, where 0 means the pixel isn't drawn.
+fn parse_mask_frame(data: &[u8], width: usize, height: usize, offset_bits: usize) -> Result<Vec<bool>> {
     let pixels = width * height;
-    let mut plane_0 = bit_iter_ms_first::iterate(image_data);
-    let mut plane_1 = bit_iter_ms_first::iterate(image_data).skip(pixels);
-    let mut plane_2 = bit_iter_ms_first::iterate(image_data).skip(pixels * 2);
-    let mut plane_3 = bit_iter_ms_first::iterate(image_data).skip(pixels * 3);
-    let mut mask_iter = bit_iter_ms_first::iterate(mask_data);
-    let mut bitmap: Vec<u32> = Vec::with_capacity(pixels);
-    for _ in 0..pixels {
-        let colour_index =
-            plane_0.next().unwrap() +
-            (plane_1.next().unwrap() << 1) +
-            (plane_2.next().unwrap() << 2) +
-            (plane_3.next().unwrap() << 3);
-        let colour = palette[colour_index as usize];
-        let masked_colour: u32 = if mask_iter.next().unwrap() == 0 { 0 } else { colour };
-        bitmap.push(masked_colour);
+    let (data, offset_bits) = check_bits(data, offset_bits, pixels)?;
+    Ok(bit_iter_ms_first::iterate(data).skip(offset_bits).take(pixels).map(|bit| bit != 0).collect())
+}
+
+// Returns the planes and the mask, each packed the way parse_4bpp_frame and parse_mask_frame read them.
+fn encode_4bpp_plus_mask_frame(indices: &[u8], mask: Option<&[bool]>) -> Result<(Vec<u8>, Vec<u8>)> {
+    if let Some(index) = indices.iter().find(|index| **index > 15) {
+        return Err(Error::invalid(format!("palette index {} doesn't fit in 4 bits", index)));
     }
-    bitmap
+    let planes = bit_iter_ms_first::pack((0..4).flat_map(|plane| indices.iter().map(move |index| (index >> plane) & 1 != 0)));
+    let mask = match mask {
+        Some(mask) => bit_iter_ms_first::pack(mask.iter().copied()),
+        None => bit_iter_ms_first::pack(indices.iter().map(|_| true)),
+    };
+    Ok((planes, mask))
 }
 
 // For images, offset_bits should be 0; for animations should be frame_index * pixels * BPP;
-fn parse_4bpp_frame(data: &[u8], width: usize, height: usize, offset_bits: usize, palette: &[u32; 16]) -> Vec<u32> {
+fn parse_4bpp_frame(data: &[u8], width: usize, height: usize, offset_bits: usize) -> Result<Vec<u8>> {
     let pixels = width * height;
+    let (data, offset_bits) = check_bits(data, offset_bits, pixels * 4)?;
     let mut plane_0 = bit_iter_ms_first::iterate(data).skip(offset_bits);
     let mut plane_1 = bit_iter_ms_first::iterate(data).skip(offset_bits + pixels);
     let mut plane_2 = bit_iter_ms_first::iterate(data).skip(offset_bits + pixels * 2);
     let mut plane_3 = bit_iter_ms_first::iterate(data).skip(offset_bits + pixels * 3);
-    let mut bitmap: Vec<u32> = Vec::with_capacity(pixels);
+    let mut indices: Vec<u8> = Vec::with_capacity(pixels);
     for _ in 0..pixels {
         let colour_index =
             plane_0.next().unwrap() +
             (plane_1.next().unwrap() << 1) +
             (plane_2.next().unwrap() << 2) +
             (plane_3.next().unwrap() << 3);
-        let colour = palette[colour_index as usize];
-        bitmap.push(colour);
+        indices.push(colour_index);
     }
-    bitmap
+    Ok(indices)
 }
 
-fn parse_3bpp_frame(data: &[u8], width: usize, height: usize, offset_bits: usize, palette: &[u32; 16]) -> Vec<u32> {
+fn parse_3bpp_frame(data: &[u8], width: usize, height: usize, offset_bits: usize) -> Result<Vec<u8>> {
     let pixels = width * height;
+    let (data, offset_bits) = check_bits(data, offset_bits, pixels * 3)?;
     let mut plane_0 = bit_iter_ms_first::iterate(data).skip(offset_bits);
     let mut plane_1 = bit_iter_ms_first::iterate(data).skip(offset_bits + pixels);
     let mut plane_2 = bit_iter_ms_first::iterate(data).skip(offset_bits + pixels * 2);
-    let mut bitmap: Vec<u32> = Vec::with_capacity(pixels);
+    let mut indices: Vec<u8> = Vec::with_capacity(pixels);
     for _ in 0..pixels {
         let colour_index =
             plane_0.next().unwrap() +
             (plane_1.next().unwrap() << 1) +
             (plane_2.next().unwrap() << 2);
-        let colour = palette[colour_index as usize];
-        bitmap.push(colour);
+        indices.push(colour_index);
     }
-    bitmap
+    Ok(indices)
 }
 
-fn parse_2bpp_frame(data: &[u8], width: usize, height: usize, offset_bits: usize, palette: &[u32; 16]) -> Vec<u32> {
+fn parse_2bpp_frame(data: &[u8], width: usize, height: usize, offset_bits: usize) -> Result<Vec<u8>> {
     let pixels = width * height;
+    let (data, offset_bits) = check_bits(data, offset_bits, pixels * 2)?;
     let mut plane_0 = bit_iter_ms_first::iterate(data).skip(offset_bits);
     let mut plane_1 = bit_iter_ms_first::iterate(data).skip(offset_bits + pixels);
-    let mut bitmap: Vec<u32> = Vec::with_capacity(pixels);
+    let mut indices: Vec<u8> = Vec::with_capacity(pixels);
     for _ in 0..pixels {
         let colour_index =
             plane_0.next().unwrap() +
             (plane_1.next().unwrap() << 1);
-        let colour = palette[colour_index as usize];
-        bitmap.push(colour);
+        indices.push(colour_index);
+    }
+    Ok(indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mask_and_palette_swap() {
+        // 8x1 pixels: 4 planes of 1 byte, then a mask drawing only the first 4 pixels.
+        let data = [0b1111_0000, 0b1100_0000, 0, 0, 0b1111_0000];
+        let mut palette = [0u32; 16];
+        palette[1] = 0x111111ff;
+        palette[3] = 0x333333ff;
+        let mut image = IndexedImage::parse_4bpp_plus_mask(&data, 8, 1, 0, 4, &palette).unwrap();
+        assert_eq!(image.indices, vec![3, 3, 1, 1, 0, 0, 0, 0]);
+        assert_eq!(image.rgba()[..5], [0x333333ff, 0x333333ff, 0x111111ff, 0x111111ff, 0]);
+        image.palette[3] = 0xff0000ff;
+        assert_eq!(image.rgba()[0], 0xff0000ff);
+        assert!(IndexedImage::parse_4bpp_plus_mask(&data, 8, 1, 0, 5, &palette).is_err());
+        // Indices a shorter palette doesn't have are transparent rather than a panic.
+        image.palette.truncate(2);
+        assert_eq!(image.rgba()[..3], [0, 0, 0x111111ff]);
+        let empty = IndexedImage::from_image(&image.to_image(), &[]);
+        assert!(empty.rgba().iter().all(|colour| *colour == 0));
     }
-    bitmap
 }
@@ -0,0 +1,214 @@
+// Raw deflate decompression (RFC 1951) for reading PNGs, the reverse of deflate.rs.
+// Codes are decoded a bit at a time using the counts of each code length, like zlib's 'puff'.
+// See: https://datatracker.ietf.org/doc/html/rfc1951
+
+use crate::deflate::{CODE_LENGTH_ORDER, DISTANCE_BASE, DISTANCE_EXTRA, END_OF_BLOCK, LENGTH_BASE, LENGTH_EXTRA, MAX_CODE_BITS, fixed_lengths};
+use crate::error::{Error, Result};
+
+pub fn decompress(input: &[u8]) -> Result<Vec<u8>> {
+    let mut reader = BitReader { data: input, offset: 0, buffer: 0, count: 0 };
+    let mut output: Vec<u8> = Vec::new();
+    loop {
+        let is_final = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => read_stored(&mut reader, &mut output)?,
+            1 => {
+                let (literals, distances) = fixed_lengths();
+                read_codes(&mut reader, &mut output, &Huffman::new(&literals)?, &Huffman::new(&distances)?)?;
+            },
+            2 => {
+                let (literals, distances) = read_dynamic_header(&mut reader)?;
+                read_codes(&mut reader, &mut output, &literals, &distances)?;
+            },
+            _ => return Err(Error::invalid("deflate block type 3 is reserved")),
+        }
+        if is_final {
+            return Ok(output);
+        }
+    }
+}
+
+// Bits are read least significant first.
+struct BitReader<'a> {
+    data: &'a [u8],
+    offset: usize, // Next byte to load into the buffer.
+    buffer: u32,
+    count: u32,
+}
+
+impl BitReader<'_> {
+    fn bits(&mut self, count: u32) -> Result<u32> {
+        while self.count < count {
+            let Some(byte) = self.data.get(self.offset) else {
+                return Err(Error::Truncated { file: String::new(), offset: self.offset });
+            };
+            self.buffer |= (*byte as u32) << self.count;
+            self.offset += 1;
+            self.count += 8;
+        }
+        let value = self.buffer & ((1u32 << count) - 1);
+        self.buffer >>= count;
+        self.count -= count;
+        Ok(value)
+    }
+
+    // Drops the bits left in the current byte, for stored blocks.
+    fn align(&mut self) {
+        self.buffer = 0;
+        self.count = 0;
+    }
+
+    fn bytes(&mut self, count: usize) -> Result<&[u8]> {
+        let Some(bytes) = self.data.get(self.offset..).and_then(|rest| rest.get(..count)) else {
+            return Err(Error::Truncated { file: String::new(), offset: self.offset });
+        };
+        self.offset += count;
+        Ok(bytes)
+    }
+}
+
+// Canonical Huffman codes: how many codes there are of each length, and the symbols in code order.
+struct Huffman {
+    counts: [u16; MAX_CODE_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Huffman> {
+        let mut counts = [0u16; MAX_CODE_BITS + 1];
+        for length in lengths {
+            counts[*length as usize] += 1;
+        }
+        counts[0] = 0;
+        // Check no length has more codes than it can fit. Incomplete codes are allowed, eg a lone distance code.
+        let mut left: i32 = 1;
+        for count in counts.iter().skip(1) {
+            left = (left << 1) - *count as i32;
+            if left < 0 {
+                return Err(Error::invalid("deflate code lengths are oversubscribed"));
+            }
+        }
+        let mut symbols: Vec<u16> = Vec::with_capacity(lengths.len());
+        for length in 1..=MAX_CODE_BITS {
+            for (symbol, l) in lengths.iter().enumerate() {
+                if *l as usize == length {
+                    symbols.push(symbol as u16);
+                }
+            }
+        }
+        Ok(Huffman { counts, symbols })
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<usize> {
+        let mut code: i32 = 0; // The bits so far.
+        let mut first: i32 = 0; // The first code of this length.
+        let mut index: i32 = 0; // Where this length's symbols start.
+        for count in self.counts.iter().skip(1) {
+            code |= reader.bits(1)? as i32;
+            let count = *count as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize] as usize);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(Error::invalid("invalid deflate code"))
+    }
+}
+
+fn read_stored(reader: &mut BitReader, output: &mut Vec<u8>) -> Result<()> {
+    reader.align();
+    let header = reader.bytes(4)?;
+    let length = header[0] as u16 + ((header[1] as u16) << 8);
+    let complement = header[2] as u16 + ((header[3] as u16) << 8);
+    if length != !complement {
+        return Err(Error::invalid("deflate stored block length doesn't match its complement"));
+    }
+    output.extend_from_slice(reader.bytes(length as usize)?);
+    Ok(())
+}
+
+fn read_dynamic_header(reader: &mut BitReader) -> Result<(Huffman, Huffman)> {
+    let literal_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_length_count = reader.bits(4)? as usize + 4;
+    let mut code_length_lengths = [0u8; 19];
+    for symbol in CODE_LENGTH_ORDER.iter().take(code_length_count) {
+        code_length_lengths[*symbol] = reader.bits(3)? as u8;
+    }
+    let code_length_codes = Huffman::new(&code_length_lengths)?;
+
+    // The literal/length and distance code lengths are run length encoded together.
+    let mut lengths: Vec<u8> = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let symbol = code_length_codes.decode(reader)?;
+        let (length, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                let Some(previous) = lengths.last() else {
+                    return Err(Error::invalid("deflate header repeats a code length before the first"));
+                };
+                (*previous, 3 + reader.bits(2)? as usize)
+            },
+            17 => (0, 3 + reader.bits(3)? as usize),
+            _ => (0, 11 + reader.bits(7)? as usize),
+        };
+        if lengths.len() + repeat > literal_count + distance_count {
+            return Err(Error::invalid("deflate header has too many code lengths"));
+        }
+        lengths.extend(std::iter::repeat_n(length, repeat));
+    }
+    if lengths[END_OF_BLOCK] == 0 {
+        return Err(Error::invalid("deflate block has no end code"));
+    }
+    Ok((Huffman::new(&lengths[..literal_count])?, Huffman::new(&lengths[literal_count..])?))
+}
+
+fn read_codes(reader: &mut BitReader, output: &mut Vec<u8>, literals: &Huffman, distances: &Huffman) -> Result<()> {
+    loop {
+        let symbol = literals.decode(reader)?;
+        if symbol < END_OF_BLOCK {
+            output.push(symbol as u8);
+            continue;
+        }
+        if symbol == END_OF_BLOCK {
+            return Ok(());
+        }
+        let index = symbol - END_OF_BLOCK - 1;
+        if index >= LENGTH_BASE.len() {
+            return Err(Error::invalid(format!("invalid deflate length symbol {}", symbol)));
+        }
+        let length = LENGTH_BASE[index] as usize + reader.bits(LENGTH_EXTRA[index] as u32)? as usize;
+        let index = distances.decode(reader)?;
+        if index >= DISTANCE_BASE.len() {
+            return Err(Error::invalid(format!("invalid deflate distance symbol {}", index)));
+        }
+        let distance = DISTANCE_BASE[index] as usize + reader.bits(DISTANCE_EXTRA[index] as u32)? as usize;
+        if distance > output.len() {
+            return Err(Error::invalid(format!("deflate match reaches {} bytes back but only {} have been decompressed", distance, output.len())));
+        }
+        let start = output.len() - distance;
+        for i in 0..length { // Byte by byte, as a match may overlap what it's copying.
+            output.push(output[start + i]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deflate::{Compression, compress};
+
+    #[test]
+    fn test_round_trip() {
+        let mut input: Vec<u8> = b"Lemmings lemmings LEMMINGS ".repeat(1000);
+        input.extend((0..70000u32).map(|i| (i.wrapping_mul(i) / 7) as u8)); // Longer than a stored block.
+        for compression in [Compression::Stored, Compression::Fast, Compression::Default, Compression::Best] {
+            assert_eq!(decompress(&compress(&input, compression)).unwrap(), input, "{:?}", compression);
+        }
+        assert_eq!(decompress(&compress(&[], Compression::Default)).unwrap(), Vec::<u8>::new());
+        let compressed = compress(&input, Compression::Default);
+        assert!(decompress(&compressed[..compressed.len() / 2]).is_err());
+    }
+}
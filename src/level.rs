@@ -1,7 +1,7 @@
-use std::slice::Iter;
-use anyhow::{Result, bail};
+use crate::error::{Error, Result};
+use crate::reader::Reader;
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone)]
 pub struct Skills {
     pub climbers: u16, // 2 bytes each, only lower byte is used, max 0x00FA
     pub floaters: u16,
@@ -13,7 +13,7 @@ pub struct Skills {
     pub diggers: u16,
 }
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone)]
 pub struct Globals {
     pub release_rate: u16, // 0x0000 is slowest, 0x00FA is fastest
     pub num_of_lemmings: u16, // maximum 0x0072
@@ -23,26 +23,28 @@ pub struct Globals {
     pub start_screen_xpos: u16, // 0x0000 to 0x04F0.  is rounded to nearest multiple of 8.
     pub normal_graphic_set: u16, // AKA ground. 0x0000 is dirt, 0x0001 is fire, 0x0002 is squasher, 0x0003 is pillar, 0x0004 is crystal, 0x0005 is brick, 0x0006 is rock, 0x0007 is snow and 0x0008 is bubble.
     pub extended_graphic_set: u16, // Apparently ignored in windows version.
+    pub unused: u16, // Normally 0x0000, but not always.
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub enum ObjectModifier {
     #[default] Normal, // Draw full graphic, 0
     MustHaveTerrainUnderneathToBeVisible, // 40
     DoNotOverwriteExistingTerrain, // 80
+    Other(u8), // Anything else (eg C0), drawn as normal.
 }
 
 impl ObjectModifier {
     pub fn is_do_not_overwrite_existing_terrain(&self) -> bool {
-        if let ObjectModifier::DoNotOverwriteExistingTerrain = self { true } else { false }
+        matches!(self, ObjectModifier::DoNotOverwriteExistingTerrain)
     }
 
     pub fn is_must_have_terrain_underneath_to_be_visible(&self) -> bool {
-        if let ObjectModifier::MustHaveTerrainUnderneathToBeVisible = self { true } else { false }
+        matches!(self, ObjectModifier::MustHaveTerrainUnderneathToBeVisible)
     }
 }
 
-#[derive(Default, Debug)]
+#[derive(Debug, Clone)]
 pub struct Object {
     pub x: i32, // Normalised to 0.
         // In file:
@@ -64,13 +66,22 @@ pub struct Object {
 	   // draw full graphic.
 
     pub is_upside_down: bool, // can be 8F (display graphic upside-down) or 0F (display graphic normally)
+
+    pub display_bits: u8, // The rest of that byte as read, so it writes back the same. 0F in every bundled level.
+}
+
+impl Default for Object {
+    fn default() -> Self {
+        Object { x: 0, y: 0, obj_id: 0, modifier: ObjectModifier::default(), is_upside_down: false, display_bits: 0x0f }
+    }
 }
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone)]
 pub struct Terrain {
     pub do_not_overwrite_existing_terrain: bool,
     pub is_upside_down: bool,
-    pub remove_terrain: bool,
+    pub remove_terrain: bool, // If 'do not overwrite' is also set, the renderer only honors that.
+    pub unknown_flag: bool, // The lowest flag bit, set on a handful of pieces.
     pub x: isize, // Normalised.
         // In file: min 0x0000, max 0x063F.  0x0000 = -16, 0x0008 = -8, 0x0010 = 0, 0x063f = 1583.
     pub y: isize, // Normalised. 
@@ -79,37 +90,59 @@ pub struct Terrain {
     pub terrain_id: usize,
 }
 
-#[derive(Default, Debug)]
-#[allow(dead_code)]
+#[derive(Default, Debug, Clone)]
 pub struct SteelArea {
     pub x: isize, // Normalised.
         // In file: min 0x000, max 0xC78.  0x000 = -16, 0x008 = -12,
         // 0x010 = -8, 0x018 = -4, ... , 0xC78 = 1580.
         // note: each hex value represents 4 pixels.
-    pub y: isize, // Normalised.
+    pub y: isize, // In pixels.
         // In file: min 0x00, max 0x27. 0x00 = 0, 0x01 = 4, 0x02 = 8, ... , 0x27 = 156
         // note: each hex value represents 4 pixels
     pub width: u8, // 0-F, each value represents 4 pixels, 0=4, 1=8, 7=32
     pub height: u8,
 }
 
-#[derive(Default, Debug)]
+// Slots are kept in file order, with None for empty ones, so the level can be written back exactly.
+#[derive(Default, Debug, Clone)]
 pub struct Level {
     pub globals: Globals,
-    pub objects: Vec<Object>, // Up to 32
-    pub terrain: Vec<Terrain>, // Up to 400
-    pub steel: Vec<SteelArea>, // Up to 32
-    pub name: String,
+    pub objects: Vec<Option<Object>>, // Up to 32
+    pub terrain: Vec<Option<Terrain>>, // Up to 400
+    pub steel: Vec<Option<SteelArea>>, // Up to 32
+    pub name: String, // As stored, padded with spaces to 32 characters. Use title() to display.
+}
+
+const OBJECT_SLOTS: usize = 32;
+const TERRAIN_SLOTS: usize = 400;
+const STEEL_SLOTS: usize = 32;
+const NAME_LENGTH: usize = 32;
+
+impl Level {
+    pub fn title(&self) -> &str {
+        self.name.trim()
+    }
 }
 
 impl ObjectModifier {
     fn from_lvl(lvl: u8) -> ObjectModifier {
         if lvl == 0x80 {
-            return ObjectModifier::DoNotOverwriteExistingTerrain;
+            ObjectModifier::DoNotOverwriteExistingTerrain
         } else if lvl == 0x40 {
-            return ObjectModifier::MustHaveTerrainUnderneathToBeVisible;
+            ObjectModifier::MustHaveTerrainUnderneathToBeVisible
+        } else if lvl == 0 {
+            ObjectModifier::Normal
         } else {
-            return ObjectModifier::Normal;
+            ObjectModifier::Other(lvl)
+        }
+    }
+
+    fn to_lvl(&self) -> u8 {
+        match self {
+            ObjectModifier::Normal => 0,
+            ObjectModifier::MustHaveTerrainUnderneathToBeVisible => 0x40,
+            ObjectModifier::DoNotOverwriteExistingTerrain => 0x80,
+            ObjectModifier::Other(lvl) => *lvl,
         }
     }
 }
@@ -117,126 +150,242 @@ impl ObjectModifier {
 fn string_from_vec(vec: Vec<u8>) -> Result<String> {
     match String::from_utf8(vec).ok() {
         Some(t) => Ok(t),
-        None => bail!("Bad string"),
-    }
-}
-
-// Exposes the 'next' as a result so you can use '?'.
-fn read_u8(data: &mut Iter<u8>) -> Result<u8> {
-    match data.next() {
-        Some(t) => Ok(*t),
-        None => bail!("No data remaining"),
+        None => Err(Error::invalid("level name isn't text")),
     }
 }
 
 // Unlike the GROUND file format, WORDs in LVL are stored big-endian (camanis.net).
-fn read_u16(data: &mut Iter<u8>) -> Result<u16> {
-    let big = read_u8(data)?;
-    let little = read_u8(data)?;
-    Ok(((big as u16) << 8) + (little as u16))
-}
 
 /// Decompresses all the sections from a compressed dat file.
 /// Returns a vec of sections. Each section is a vec of its data.
 pub fn parse(data: &[u8]) -> Result<Level> {
     if data.len() != 2048 {
-        bail!("Wrong length");
+        return Err(Error::BadLength { file: String::new(), expected: 2048, actual: data.len() });
     }
     let mut level: Level = Default::default();
-    let mut data_iter = data.into_iter();
+    let mut reader = Reader::new(data);
 
     // Globals.
-    level.globals.release_rate = read_u16(&mut data_iter)?;
-    level.globals.num_of_lemmings = read_u16(&mut data_iter)?;
-    level.globals.num_to_rescue = read_u16(&mut data_iter)?;
-    level.globals.time_limit = read_u16(&mut data_iter)?;
-    level.globals.skills.climbers = read_u16(&mut data_iter)?;
-    level.globals.skills.floaters = read_u16(&mut data_iter)?;
-    level.globals.skills.bombers = read_u16(&mut data_iter)?;
-    level.globals.skills.blockers = read_u16(&mut data_iter)?;
-    level.globals.skills.builders = read_u16(&mut data_iter)?;
-    level.globals.skills.bashers = read_u16(&mut data_iter)?;
-    level.globals.skills.miners = read_u16(&mut data_iter)?;
-    level.globals.skills.diggers = read_u16(&mut data_iter)?;
-    level.globals.start_screen_xpos = read_u16(&mut data_iter)?;
-    level.globals.normal_graphic_set = read_u16(&mut data_iter)?;
-    level.globals.extended_graphic_set = read_u16(&mut data_iter)?;
-    let _unused = read_u16(&mut data_iter)?;
+    level.globals.release_rate = reader.u16_be()?;
+    level.globals.num_of_lemmings = reader.u16_be()?;
+    level.globals.num_to_rescue = reader.u16_be()?;
+    level.globals.time_limit = reader.u16_be()?;
+    level.globals.skills.climbers = reader.u16_be()?;
+    level.globals.skills.floaters = reader.u16_be()?;
+    level.globals.skills.bombers = reader.u16_be()?;
+    level.globals.skills.blockers = reader.u16_be()?;
+    level.globals.skills.builders = reader.u16_be()?;
+    level.globals.skills.bashers = reader.u16_be()?;
+    level.globals.skills.miners = reader.u16_be()?;
+    level.globals.skills.diggers = reader.u16_be()?;
+    level.globals.start_screen_xpos = reader.u16_be()?;
+    level.globals.normal_graphic_set = reader.u16_be()?;
+    level.globals.extended_graphic_set = reader.u16_be()?;
+    level.globals.unused = reader.u16_be()?;
 
     // Objects.
+    // Ids the game doesn't have (16 and up) are kept so the level writes back the same; the renderers skip them.
     for _ in 0..32 {
-        let ix = read_u16(&mut data_iter)? as i16; // Will convert eg 0xfff8 to -24;
-        let iy = read_u16(&mut data_iter)? as i16;
-        let id = read_u16(&mut data_iter)?;
-        let ma = read_u8(&mut data_iter)?;
-        let mb = read_u8(&mut data_iter)?;
-        let is_bad = (ix==0 && iy==0 && id==0) || id>=16;
-        if !is_bad {
-            level.objects.push(Object {
+        let ix = reader.u16_be()? as i16; // Will convert eg 0xfff8 to -24;
+        let iy = reader.u16_be()? as i16;
+        let id = reader.u16_be()?;
+        let ma = reader.u8()?;
+        let mb = reader.u8()?;
+        let is_empty = ix==0 && iy==0 && id==0 && ma==0 && mb==0;
+        if is_empty {
+            level.objects.push(None);
+        } else {
+            level.objects.push(Some(Object {
                 x: ix as i32,
                 y: iy as i32,
                 obj_id: id as usize,
                 modifier: ObjectModifier::from_lvl(ma),
-                is_upside_down: mb == 0x8f,
-            });
+                is_upside_down: mb & 0x80 == 0x80,
+                display_bits: mb & 0x7f,
+            }));
         }
     }
 
     // Terrain.
+    // Likewise ids 64 and up are kept, and skipped when rendering.
     for _ in 0..400 {
-        let a = read_u8(&mut data_iter)?; // significant nibble = flags, other = x.
-        let b = read_u8(&mut data_iter)?; // x. 
-        let c = read_u8(&mut data_iter)?; // First 8 of 9 bits of y.
-        let d = read_u8(&mut data_iter)?; // Another bit of y, and terrain id.
+        let a = reader.u8()?; // significant nibble = flags, other = x.
+        let b = reader.u8()?; // x. 
+        let c = reader.u8()?; // First 8 of 9 bits of y.
+        let d = reader.u8()?; // Another bit of y, and terrain id.
         let terrain_id = d & 0x7f;
-        let is_bad = (a==0xff && b==0xff && c==0xff && d==0xff) || terrain_id>=64;
-        if !is_bad {
+        let is_empty = a==0xff && b==0xff && c==0xff && d==0xff;
+        if is_empty {
+            level.terrain.push(None);
+        } else {
             let x: u16 = (((a & 0xf) as u16) << 8) + (b as u16);
             let y_bits: u16 = ((c as u16) << 1) + ((d >> 7) as u16);
             let y_2s_comp: u16 = if y_bits & 0x100 == 0 { y_bits } else { y_bits | 0xfe00 };
             let y_i: i16 = y_2s_comp as i16;
             let flags: u8 = a >> 4;
-            let do_not_overwrite_existing_terrain = (flags & 8) == 8;
-            let remove_terrain = (flags & 2) == 2;
-            let is_upside_down = (flags & 4) > 0;
-            level.terrain.push(Terrain {
-                do_not_overwrite_existing_terrain,
-                is_upside_down,
-                remove_terrain: remove_terrain && !do_not_overwrite_existing_terrain, // If both flags are on, only honor 'do not overwrite'.
+            level.terrain.push(Some(Terrain {
+                do_not_overwrite_existing_terrain: (flags & 8) == 8,
+                is_upside_down: (flags & 4) == 4,
+                remove_terrain: (flags & 2) == 2,
+                unknown_flag: (flags & 1) == 1,
                 x: x as isize,
                 y: y_i as isize - 4,
                 terrain_id: terrain_id as usize,
-            });
+            }));
         }
     }
 
     // Steel.
     for _ in 0..32 {
-        let a = read_u8(&mut data_iter)?; // First 8 of 9 bits of x.
-        let b = read_u8(&mut data_iter)?; // Last x bit, y.
-        let c = read_u8(&mut data_iter)?; // Area.
-        let d = read_u8(&mut data_iter)?; // Unused, pity after all that packing!
-        let is_bad = a==0 && b==0 && c==0 && d==0;
-        if !is_bad {
+        let a = reader.u8()?; // First 8 of 9 bits of x.
+        let b = reader.u8()?; // Last x bit, y.
+        let c = reader.u8()?; // Area.
+        let d = reader.u8()?; // Unused, pity after all that packing!
+        let is_empty = a==0 && b==0 && c==0 && d==0;
+        if is_empty {
+            level.steel.push(None);
+        } else {
             let x: u16 = ((a as u16) << 1) + ((b >> 7) as u16);
             let y: u8 = b & 0x7f;
-            level.steel.push(SteelArea {
+            level.steel.push(Some(SteelArea {
                 x: (x as isize),
-                y: (y as isize) * 4 - 4, // TODO test this makes the steel in the correct place?
+                y: (y as isize) * 4, // Per the docs' example, 00 9F 52 00 is at y 124, so there's no offset.
                 width: c >> 4,
                 height: c & 0xf,
-            });
+            }));
         }
     }
 
     // Name.
     let mut str_raw: Vec<u8> = Vec::with_capacity(32);
     for _ in 0..32 {
-        let byte = read_u8(&mut data_iter)?;
+        let byte = reader.u8()?;
         str_raw.push(byte);
     }
-    let raw_name = string_from_vec(str_raw)?;
-    level.name = raw_name.trim().to_string();
+    level.name = string_from_vec(str_raw)?;
 
     Ok(level)
 }
+
+fn write_u16(data: &mut Vec<u8>, value: u16) {
+    data.push((value >> 8) as u8);
+    data.push(value as u8);
+}
+
+impl Level {
+    // The inverse of parse. Missing trailing slots are written as empty, and a short name is padded with spaces.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        if self.objects.len() > OBJECT_SLOTS {
+            return Err(Error::invalid(format!("too many objects: {}", self.objects.len())));
+        }
+        if self.terrain.len() > TERRAIN_SLOTS {
+            return Err(Error::invalid(format!("too many terrain pieces: {}", self.terrain.len())));
+        }
+        if self.steel.len() > STEEL_SLOTS {
+            return Err(Error::invalid(format!("too many steel areas: {}", self.steel.len())));
+        }
+        if !self.name.is_ascii() || self.name.len() > NAME_LENGTH {
+            return Err(Error::invalid(format!("name must be up to {} ascii characters: {}", NAME_LENGTH, self.name)));
+        }
+        let mut data: Vec<u8> = Vec::with_capacity(2048);
+
+        // Globals.
+        let globals = &self.globals;
+        let skills = &globals.skills;
+        for value in [
+            globals.release_rate, globals.num_of_lemmings, globals.num_to_rescue, globals.time_limit,
+            skills.climbers, skills.floaters, skills.bombers, skills.blockers,
+            skills.builders, skills.bashers, skills.miners, skills.diggers,
+            globals.start_screen_xpos, globals.normal_graphic_set, globals.extended_graphic_set, globals.unused,
+        ] {
+            write_u16(&mut data, value);
+        }
+
+        // Objects.
+        for i in 0..OBJECT_SLOTS {
+            match self.objects.get(i) {
+                Some(Some(object)) => {
+                    write_u16(&mut data, object.x as i16 as u16);
+                    write_u16(&mut data, object.y as i16 as u16);
+                    write_u16(&mut data, object.obj_id as u16);
+                    data.push(object.modifier.to_lvl());
+                    data.push(if object.is_upside_down { 0x80 } else { 0 } | object.display_bits);
+                },
+                _ => data.extend_from_slice(&[0; 8]),
+            }
+        }
+
+        // Terrain.
+        for i in 0..TERRAIN_SLOTS {
+            match self.terrain.get(i) {
+                Some(Some(terrain)) => {
+                    let flags: u8 =
+                        if terrain.do_not_overwrite_existing_terrain { 8 } else { 0 } |
+                        if terrain.is_upside_down { 4 } else { 0 } |
+                        if terrain.remove_terrain { 2 } else { 0 } |
+                        if terrain.unknown_flag { 1 } else { 0 };
+                    let x = terrain.x as u16 & 0xfff;
+                    let y_bits = (terrain.y + 4) as u16 & 0x1ff;
+                    data.push((flags << 4) | (x >> 8) as u8);
+                    data.push(x as u8);
+                    data.push((y_bits >> 1) as u8);
+                    data.push(((y_bits & 1) << 7) as u8 | (terrain.terrain_id as u8 & 0x7f));
+                },
+                _ => data.extend_from_slice(&[0xff; 4]),
+            }
+        }
+
+        // Steel.
+        for i in 0..STEEL_SLOTS {
+            match self.steel.get(i) {
+                Some(Some(steel)) => {
+                    let x = steel.x as u16 & 0x1ff;
+                    let y = (steel.y / 4) as u8 & 0x7f;
+                    data.push((x >> 1) as u8);
+                    data.push(((x & 1) << 7) as u8 | y);
+                    data.push((steel.width << 4) | (steel.height & 0xf));
+                    data.push(0);
+                },
+                _ => data.extend_from_slice(&[0; 4]),
+            }
+        }
+
+        // Name.
+        data.extend_from_slice(self.name.as_bytes());
+        data.resize(2048, b' ');
+
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decompressor;
+
+    #[test]
+    fn test_round_trip_bundled_levels() {
+        for folder in std::fs::read_dir("data").unwrap() {
+            for entry in std::fs::read_dir(folder.unwrap().path()).unwrap() {
+                let path = entry.unwrap().path();
+                let name = path.file_name().unwrap().to_string_lossy().to_lowercase();
+                if !name.starts_with("level") && !name.starts_with("dlvel") { continue }
+                for section in decompressor::decompress(&name, &std::fs::read(&path).unwrap()).unwrap() {
+                    let level = parse(&section).unwrap();
+                    assert_eq!(level.to_bytes().unwrap(), section, "{} {}", path.display(), level.title());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_trip_unusual_object() {
+        let mut section = decompressor::decompress("LEVEL000.DAT", &std::fs::read("data/lemmings/LEVEL000.DAT").unwrap()).unwrap().remove(0);
+        section[32 + 5] = 20; // First object's id, beyond what the game has.
+        section[32 + 7] = 0x8b; // And its display byte.
+        let level = parse(&section).unwrap();
+        let object = level.objects[0].as_ref().unwrap();
+        assert_eq!((object.obj_id, object.is_upside_down, object.display_bits), (20, true, 0x0b));
+        assert_eq!(level.to_bytes().unwrap(), section);
+    }
+}
@@ -0,0 +1,139 @@
+// The order each game presents its levels in, eg 'Tricky 12', mapped to where the level is stored.
+// The original Lemmings keeps this table in the executable rather than the data files, so it is
+// rebuilt here from the game's own level list. The other games simply play their packs in file order.
+
+use crate::game_variant::GameVariant;
+use crate::level_pack::LEVELS_PER_FILE;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Position {
+    pub rating_index: usize, // Eg 0 for Fun.
+    pub rating: &'static str,
+    pub number: usize, // Starts at 1, like in the game.
+}
+
+impl Position {
+    pub fn title(&self) -> String {
+        format!("{} {}", self.rating, self.number)
+    }
+}
+
+// Where a level comes from: a base level (file number * 8 + section), or an ODDTABLE.DAT entry
+// which reuses the layout of the base level with the same index.
+#[derive(Debug, Clone, Copy)]
+enum Source {
+    Base(usize),
+    Odd(usize),
+}
+use Source::{Base, Odd};
+
+pub struct Order {
+    pub ratings: &'static [&'static str],
+    levels_per_rating: usize,
+    table: Option<&'static [[Source; 30]; 4]>, // None means file order.
+}
+
+impl Order {
+    // Finds the position of the level stored at this file and section (and oddtable entry, if any).
+    pub fn position(&self, file_number: u32, section_index: usize, oddtable_entry: Option<usize>) -> Option<Position> {
+        let stored_index = file_number as usize * LEVELS_PER_FILE + section_index;
+        let Some(table) = self.table else {
+            // File order: each rating takes the next levels_per_rating stored levels.
+            if oddtable_entry.is_some() { return None }
+            let rating_index = stored_index / self.levels_per_rating;
+            let rating = self.ratings.get(rating_index)?;
+            return Some(Position { rating_index, rating, number: stored_index % self.levels_per_rating + 1 });
+        };
+        for (rating_index, rating) in self.ratings.iter().enumerate() {
+            for (index, source) in table[rating_index].iter().enumerate() {
+                let is_match = match (source, oddtable_entry) {
+                    (Base(base), None) => *base == stored_index,
+                    (Odd(entry), Some(odd)) => *entry == odd && *entry == stored_index,
+                    _ => false,
+                };
+                if is_match {
+                    return Some(Position { rating_index, rating, number: index + 1 });
+                }
+            }
+        }
+        None
+    }
+}
+
+const LEMMINGS: Order = Order {
+    ratings: &["Fun", "Tricky", "Taxing", "Mayhem"],
+    levels_per_rating: 30,
+    table: Some(&LEMMINGS_TABLE),
+};
+
+// Fun 1 to Mayhem 30 as the DOS game lists them, each matched by title to the stored level or ODDTABLE.DAT
+// entry with that title. There's no exe offset to point to, as the table was rebuilt rather than dumped,
+// so levels_loader's test_positions_of_bundled_levels pins all 120 titles in order.
+// Every stored level and used oddtable entry appears exactly once.
+const LEMMINGS_TABLE: [[Source; 30]; 4] = [
+    [ // Fun.
+        Base(73), Base(77), Base(78), Base(74), Base(75), Base(76), Base(79), Odd(6), Odd(10), Odd(26),
+        Odd(34), Odd(7), Odd(14), Odd(15), Odd(18), Odd(20), Odd(23), Odd(35), Odd(41), Odd(51),
+        Odd(47), Odd(48), Odd(38), Odd(57), Odd(33), Odd(49), Odd(66), Odd(53), Odd(68), Base(11),
+    ],
+    [ // Tricky.
+        Base(0), Odd(55), Odd(17), Odd(24), Odd(25), Odd(27), Odd(28), Odd(39), Odd(50), Odd(59),
+        Odd(63), Odd(64), Odd(67), Base(2), Odd(73), Odd(74), Odd(75), Odd(76), Odd(77), Odd(79),
+        Base(3), Base(5), Base(6), Base(7), Base(8), Base(9), Base(10), Base(12), Base(13), Base(16),
+    ],
+    [ // Taxing.
+        Base(18), Base(19), Base(20), Base(21), Base(22), Base(23), Base(24), Base(25), Base(26), Base(27),
+        Base(28), Base(29), Base(30), Base(31), Base(1), Base(32), Base(33), Base(34), Base(35), Base(36),
+        Base(37), Base(38), Base(39), Base(40), Base(41), Base(42), Base(43), Base(44), Base(17), Base(14),
+    ],
+    [ // Mayhem.
+        Base(45), Base(46), Base(47), Base(48), Base(49), Base(50), Base(51), Base(52), Base(53), Base(54),
+        Base(55), Base(56), Base(57), Base(58), Base(59), Base(60), Base(61), Base(62), Base(63), Base(64),
+        Base(15), Base(4), Base(65), Base(66), Base(67), Base(68), Base(69), Base(70), Base(71), Base(72),
+    ],
+];
+
+const OH_NO_MORE_LEMMINGS: Order = Order {
+    ratings: &["Tame", "Crazy", "Wild", "Wicked", "Havoc"],
+    levels_per_rating: 20,
+    table: None,
+};
+
+// The Christmas demos' MAIN.DAT has the four Oh No! More Lemmings signs after Tame, one for each stored level.
+const CHRISTMAS_LEMMINGS: Order = Order {
+    ratings: &["Crazy", "Wild", "Wicked", "Havoc"],
+    levels_per_rating: 1,
+    table: None,
+};
+
+// LEVEL003.DAT has 7 extra sections that repeat its last 7 levels; they aren't part of any rating.
+const HOLIDAY_LEMMINGS_1993: Order = Order {
+    ratings: &["Flurry", "Blitz"],
+    levels_per_rating: 16,
+    table: None,
+};
+
+// Frost and Hail are new, Flurry and Blitz are the 1993 levels (see GREET.DAT), including the 7 repeats.
+const HOLIDAY_LEMMINGS_1994: Order = Order {
+    ratings: &["Frost", "Hail", "Flurry", "Blitz"],
+    levels_per_rating: 16,
+    table: None,
+};
+
+// Every stored level in one list, for games we don't know the ratings of.
+const GENERIC: Order = Order {
+    ratings: &["Level"],
+    levels_per_rating: usize::MAX,
+    table: None,
+};
+
+pub fn for_variant(variant: GameVariant) -> &'static Order {
+    match variant {
+        GameVariant::Lemmings => &LEMMINGS,
+        GameVariant::OhNoMoreLemmings => &OH_NO_MORE_LEMMINGS,
+        GameVariant::ChristmasLemmings1991 | GameVariant::ChristmasLemmings1992 => &CHRISTMAS_LEMMINGS,
+        GameVariant::HolidayLemmings1993 => &HOLIDAY_LEMMINGS_1993,
+        GameVariant::HolidayLemmings1994 => &HOLIDAY_LEMMINGS_1994,
+        GameVariant::Generic { .. } => &GENERIC,
+    }
+}
@@ -0,0 +1,58 @@
+// This is for building LEVELxxx.DAT files, the inverse of levels_loader.
+
+use crate::compressor;
+use crate::level;
+use crate::error::Result;
+
+pub const LEVELS_PER_FILE: usize = 8; // Like the originals.
+
+pub fn file_name(file_number: usize) -> String {
+    format!("LEVEL{:03}.DAT", file_number)
+}
+
+// Groups the levels in order into compressed packs. The returned vec index is the file number.
+pub fn build(levels: &[&level::Level]) -> Result<Vec<Vec<u8>>> {
+    let mut files: Vec<Vec<u8>> = Vec::new();
+    for chunk in levels.chunks(LEVELS_PER_FILE) {
+        let mut sections: Vec<Vec<u8>> = Vec::with_capacity(chunk.len());
+        for level in chunk {
+            sections.push(level.to_bytes()?);
+        }
+        files.push(compressor::compress(&sections)?);
+    }
+    Ok(files)
+}
+
+// Writes the packs into a folder as LEVEL000.DAT, LEVEL001.DAT, etc.
+pub fn save(folder: &str, levels: &[&level::Level]) -> Result<()> {
+    for (file_number, data) in build(levels)?.iter().enumerate() {
+        std::fs::write(format!("{}/{}", folder, file_name(file_number)), data)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decompressor;
+    use crate::file_finder;
+    use crate::game_variant::GameVariant;
+    use crate::levels_loader;
+
+    #[test]
+    fn test_rebuild_original_packs() {
+        let files = file_finder::DataFiles::from_folder("data/lemmings").unwrap();
+        let mut loaded = levels_loader::load(&files, GameVariant::Lemmings).unwrap();
+        levels_loader::sort_by_file(&mut loaded);
+        let levels: Vec<&level::Level> = loaded.iter().filter(|l| l.oddtable_entry.is_none()).map(|l| &l.level).collect();
+        let built = build(&levels).unwrap();
+        let originals = files.find("level", ".dat");
+        assert_eq!(built.len(), originals.len());
+        for (file_number, (data, original)) in built.iter().zip(originals.iter()).enumerate() {
+            assert_eq!(original.number as usize, file_number);
+            let name = file_name(file_number);
+            assert_eq!(decompressor::decompress(&name, data).unwrap(),
+                decompressor::decompress(&original.name, &original.data).unwrap(), "{}", name);
+        }
+    }
+}
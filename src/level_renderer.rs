@@ -1,6 +1,9 @@
+use crate::error::{Error, Result};
 use crate::image;
 use crate::level;
 use crate::grounds_loader;
+use crate::special;
+use crate::png;
 use std::cmp;
 use std::collections::HashMap;
 
@@ -8,6 +11,40 @@ const SPECIAL_WIDTH: usize = 960;
 const SPECIAL_LEFT_X: isize = 320;
 const LEVEL_BACKGROUND: u32 = 0x000000ff;
 const LEVEL_HEIGHT: isize = 160;
+const LEVEL_WIDTH: isize = 1600; // The game's whole playfield, from the file's x of 0.
+const TERRAIN_IDS: usize = 64; // Higher ids turn up in a few junk slots, and are skipped like the game does.
+const OBJECT_IDS: usize = 16;
+const OVERLAY_ALPHA: u32 = 0x60; // Out of 0xff, for the inside of the overlay boxes. Their outlines are solid.
+const STEEL_COLOUR: u32 = 0xc0c0c0ff;
+const MAX_ANIMATION_FRAMES: usize = 256; // Only reached by custom objects with unusual loop lengths.
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Canvas {
+    #[default]
+    Cropped, // To the terrain's extent, or the special background.
+    Full, // The game's whole 1600 pixel playfield, so pixels line up with the level file and between levels.
+}
+
+// Where the image's pixels are in the level. Level coordinates are as Level stores them, eg Object::x.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PixelMapping {
+    pub left_x: isize, // The level x of the image's first column.
+    pub width: usize,
+    pub height: usize,
+}
+
+impl PixelMapping {
+    pub fn to_level(&self, pixel_x: usize, pixel_y: usize) -> (isize, isize) {
+        (pixel_x as isize + self.left_x, pixel_y as isize)
+    }
+
+    // None if it's off the image.
+    pub fn to_pixel(&self, level_x: isize, level_y: isize) -> Option<(usize, usize)> {
+        let pixel_x = level_x - self.left_x;
+        if pixel_x < 0 || pixel_x >= self.width as isize || level_y < 0 || level_y >= self.height as isize { return None }
+        Some((pixel_x as usize, level_y as usize))
+    }
+}
 
 // #[derive(Debug, Copy, Clone)]
 struct LevelSize {
@@ -20,31 +57,54 @@ impl LevelSize {
         self.max_x - self.min_x
     }
 
-    fn from_level(level: &level::Level, grounds: &HashMap<u32, grounds_loader::GroundWithImages>) -> LevelSize {
+    fn from_level(level: &level::Level, ground: &grounds_loader::GroundWithImages, canvas: Canvas) -> Result<LevelSize> {
+        if canvas == Canvas::Full {
+            return Ok(LevelSize { min_x: 0, max_x: LEVEL_WIDTH })
+        }
         if level.globals.extended_graphic_set != 0 {
-            return LevelSize {
+            return Ok(LevelSize {
                 min_x: SPECIAL_LEFT_X,
                 max_x: SPECIAL_LEFT_X + SPECIAL_WIDTH as isize,
-            }
+            })
         }
         let mut size = LevelSize {
-            min_x: std::isize::MAX,
-            max_x: std::isize::MIN,
+            min_x: isize::MAX,
+            max_x: isize::MIN,
         };
-        let ground = &grounds[&(level.globals.normal_graphic_set as u32)];
-        for terrain in level.terrain.iter() {
-            let width = ground.ground.terrain_info[terrain.terrain_id as usize].width as isize;
+        for terrain in level.terrain.iter().flatten() {
+            let Some(sprite) = terrain_sprite(level, ground, terrain.terrain_id)? else { continue };
+            let width = sprite.width as isize;
             size.min_x = cmp::min(size.min_x, terrain.x);
             size.max_x = cmp::max(size.max_x, terrain.x + width);
         }
-        size
+        if size.min_x > size.max_x { // No terrain to crop to, eg a level that's all objects.
+            return Ok(LevelSize { min_x: 0, max_x: LEVEL_WIDTH })
+        }
+        Ok(size)
     }
 }
 
+fn terrain_sprite<'a>(level: &level::Level, ground: &'a grounds_loader::GroundWithImages, terrain_id: usize) -> Result<Option<&'a image::IndexedImage>> {
+    if terrain_id >= TERRAIN_IDS { return Ok(None) }
+    match ground.terrain.get(&terrain_id) {
+        Some(sprite) => Ok(Some(sprite)),
+        None => Err(Error::MissingTerrain { level: level.title().to_string(), terrain_id }),
+    }
+}
+
+fn object_animation<'a>(level: &level::Level, ground: &'a grounds_loader::GroundWithImages, object_id: usize) -> Result<Option<&'a image::IndexedAnimation>> {
+    if object_id >= OBJECT_IDS { return Ok(None) }
+    match ground.objects.get(&object_id) {
+        Some(animation) => Ok(Some(animation)),
+        None => Err(Error::MissingObject { level: level.title().to_string(), object_id }),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 fn draw(sprite: &[u32],
         sprite_width: isize, sprite_height: isize,
         x: isize, y: isize,
-        canvas: &mut Vec<u32>, canvas_width: isize, canvas_height: isize,
+        canvas: &mut [u32], canvas_width: isize, canvas_height: isize,
         do_not_overwrite_existing_terrain: bool,
         is_upside_down: bool,
         remove_terrain: bool,
@@ -75,19 +135,17 @@ fn draw(sprite: &[u32],
                 canvas_offset += 1;
                 continue;
             }
-            if do_not_overwrite_existing_terrain {
-                if canvas[canvas_offset as usize] != LEVEL_BACKGROUND { // Skip the 'paint' if there's existing terrain.
-                    sprite_offset += 1;
-                    canvas_offset += 1;
-                    continue;
-                }
+            if do_not_overwrite_existing_terrain
+                && canvas[canvas_offset as usize] != LEVEL_BACKGROUND { // Skip the 'paint' if there's existing terrain.
+                sprite_offset += 1;
+                canvas_offset += 1;
+                continue;
             }
-            if must_have_terrain_underneath_to_be_visible {
-                if canvas[canvas_offset as usize] == LEVEL_BACKGROUND { // Skip the 'paint' if there's no existing terrain.
-                    sprite_offset += 1;
-                    canvas_offset += 1;
-                    continue;
-                }
+            if must_have_terrain_underneath_to_be_visible
+                && canvas[canvas_offset as usize] == LEVEL_BACKGROUND { // Skip the 'paint' if there's no existing terrain.
+                sprite_offset += 1;
+                canvas_offset += 1;
+                continue;
             }
             let pixel = sprite[sprite_offset as usize];
             if pixel != 0 {
@@ -101,50 +159,362 @@ fn draw(sprite: &[u32],
     }
 }
 
-pub fn render(
+// Draws the terrain, or the special background, onto the level's background colour.
+fn render_terrain(
     level: &level::Level,
-    grounds: &HashMap<u32, grounds_loader::GroundWithImages>,
-    specials: &HashMap<u32, image::Image>,
-) -> image::Image {
-    let size = LevelSize::from_level(level, grounds);
+    ground: &grounds_loader::GroundWithImages,
+    specials: &HashMap<u32, special::Special>,
+    canvas: Canvas,
+) -> Result<(LevelSize, Vec<u32>)> {
+    let size = LevelSize::from_level(level, ground, canvas)?;
     let width = size.width();
     let height = LEVEL_HEIGHT;
     let pixels = width * height;
     let mut bitmap = vec![LEVEL_BACKGROUND; pixels as usize];
-    let ground = &grounds[&(level.globals.normal_graphic_set as u32)];
     if level.globals.extended_graphic_set == 0 {
-        for terrain in level.terrain.iter() {
-            let sprite = &ground.terrain[&terrain.terrain_id];
-            draw(&sprite.bitmap,
+        for terrain in level.terrain.iter().flatten() {
+            let Some(sprite) = terrain_sprite(level, ground, terrain.terrain_id)? else { continue };
+            draw(&sprite.rgba(),
                 sprite.width as isize, sprite.height as isize,
-                (terrain.x - size.min_x) as isize, terrain.y,
+                terrain.x - size.min_x, terrain.y,
                 &mut bitmap,
-                width as isize, height as isize,
+                width, height,
                 terrain.do_not_overwrite_existing_terrain,
                 terrain.is_upside_down,
-                terrain.remove_terrain,
+                terrain.remove_terrain && !terrain.do_not_overwrite_existing_terrain, // If both flags are on, only honor 'do not overwrite'.
                 false);
         }
     } else {
-        let special = &specials[&(level.globals.extended_graphic_set as u32 - 1)];
-        bitmap.copy_from_slice(&special.bitmap);
+        let graphic_set = level.globals.extended_graphic_set;
+        let Some(special) = specials.get(&(graphic_set as u32 - 1)) else {
+            return Err(Error::UnknownGraphicSet { level: level.title().to_string(), graphic_set });
+        };
+        draw(&special.image.rgba(),
+            special.image.width as isize, special.image.height as isize,
+            SPECIAL_LEFT_X - size.min_x, 0,
+            &mut bitmap,
+            width, height,
+            false, false, false, false);
     }
-    for object in level.objects.iter() {
-        let anim = &ground.objects[&object.obj_id];
-        draw(&anim.frames[0],
+    Ok((size, bitmap))
+}
+
+// Draws the objects at the given tick. Looping objects show frame tick % their frame count, triggered ones stay at rest.
+fn draw_objects(
+    level: &level::Level,
+    ground: &grounds_loader::GroundWithImages,
+    size: &LevelSize,
+    bitmap: &mut [u32],
+    tick: usize,
+) -> Result<()> {
+    for object in level.objects.iter().flatten() {
+        let Some(anim) = object_animation(level, ground, object.obj_id)? else { continue };
+        let frame = if is_looping(ground, object.obj_id) { tick % anim.frames.len().max(1) } else { 0 };
+        draw(&anim.frame_rgba(frame),
             anim.width as isize, anim.height as isize,
             object.x as isize - size.min_x, object.y as isize,
-            &mut bitmap,
-            width as isize, height as isize,
+            bitmap,
+            size.width(), LEVEL_HEIGHT,
             object.modifier.is_do_not_overwrite_existing_terrain(),
             object.is_upside_down,
             false,
             object.modifier.is_must_have_terrain_underneath_to_be_visible());
     }
-    let image = image::Image {
+    Ok(())
+}
+
+// Bit 0 of the animation flags is set for triggered animations, eg traps and the entrance.
+fn is_looping(ground: &grounds_loader::GroundWithImages, object_id: usize) -> bool {
+    ground.ground.object_info[object_id].animation_flags & 1 == 0
+}
+
+fn ground_for<'a>(level: &level::Level, grounds: &'a HashMap<u32, grounds_loader::GroundWithImages>) -> Result<&'a grounds_loader::GroundWithImages> {
+    let graphic_set = level.globals.normal_graphic_set;
+    grounds.get(&(graphic_set as u32))
+        .ok_or_else(|| Error::UnknownGraphicSet { level: level.title().to_string(), graphic_set })
+}
+
+pub fn render(
+    level: &level::Level,
+    grounds: &HashMap<u32, grounds_loader::GroundWithImages>,
+    specials: &HashMap<u32, special::Special>,
+    canvas: Canvas,
+) -> Result<image::Image> {
+    let ground = ground_for(level, grounds)?;
+    let (size, mut bitmap) = render_terrain(level, ground, specials, canvas)?;
+    draw_objects(level, ground, &size, &mut bitmap, 0)?;
+    Ok(image::Image {
+        bitmap,
+        width: size.width() as usize,
+        height: LEVEL_HEIGHT as usize,
+    })
+}
+
+// Where render's pixels are in the level, eg to place things on the image by their level coordinates.
+pub fn pixel_mapping(
+    level: &level::Level,
+    grounds: &HashMap<u32, grounds_loader::GroundWithImages>,
+    canvas: Canvas,
+) -> Result<PixelMapping> {
+    let size = LevelSize::from_level(level, ground_for(level, grounds)?, canvas)?;
+    Ok(PixelMapping { left_x: size.min_x, width: size.width() as usize, height: LEVEL_HEIGHT as usize })
+}
+
+// Like render, but with the objects animating. It runs until every looping object is back at its first
+// frame, ie the lowest common multiple of their frame counts, with MAX_ANIMATION_FRAMES as a backstop for
+// odd custom sets. Frames are drawn on demand rather than held, see AnimatedLevel::as_apng.
+pub fn render_animated<'a>(
+    level: &'a level::Level,
+    grounds: &'a HashMap<u32, grounds_loader::GroundWithImages>,
+    specials: &HashMap<u32, special::Special>,
+    canvas: Canvas,
+) -> Result<AnimatedLevel<'a>> {
+    let ground = ground_for(level, grounds)?;
+    let (size, terrain) = render_terrain(level, ground, specials, canvas)?;
+    let mut frame_count: usize = 1;
+    for object in level.objects.iter().flatten() {
+        let Some(anim) = object_animation(level, ground, object.obj_id)? else { continue };
+        if is_looping(ground, object.obj_id) && !anim.frames.is_empty() {
+            frame_count = cmp::min(lowest_common_multiple(frame_count, anim.frames.len()), MAX_ANIMATION_FRAMES);
+        }
+    }
+    Ok(AnimatedLevel { level, ground, size, terrain, frame_count })
+}
+
+pub struct AnimatedLevel<'a> {
+    level: &'a level::Level,
+    ground: &'a grounds_loader::GroundWithImages,
+    size: LevelSize,
+    terrain: Vec<u32>, // Shared by every frame, the objects are drawn over a copy.
+    pub frame_count: usize,
+}
+
+impl AnimatedLevel<'_> {
+    pub fn width(&self) -> usize {
+        self.size.width() as usize
+    }
+
+    pub fn height(&self) -> usize {
+        LEVEL_HEIGHT as usize
+    }
+
+    pub fn frame(&self, tick: usize) -> Result<Vec<u32>> {
+        let mut bitmap = self.terrain.clone();
+        draw_objects(self.level, self.ground, &self.size, &mut bitmap, tick)?;
+        Ok(bitmap)
+    }
+
+    // Each frame is compressed as soon as it's drawn, so only one is held at a time.
+    pub fn as_apng(&self) -> Result<Vec<u8>> {
+        let mut writer = png::ApngWriter::new(self.width() as u32, self.height() as u32, self.frame_count, png::Compression::Default);
+        for tick in 0..self.frame_count {
+            writer.add_frame(&self.frame(tick)?);
+        }
+        Ok(writer.finish())
+    }
+}
+
+// Like render, with translucent boxes over each object's trigger area, coloured by trigger_colour, and the steel areas.
+pub fn render_overlay(
+    level: &level::Level,
+    grounds: &HashMap<u32, grounds_loader::GroundWithImages>,
+    specials: &HashMap<u32, special::Special>,
+    canvas: Canvas,
+) -> Result<image::Image> {
+    let ground = ground_for(level, grounds)?;
+    let (size, mut bitmap) = render_terrain(level, ground, specials, canvas)?;
+    draw_objects(level, ground, &size, &mut bitmap, 0)?;
+    let width = size.width();
+    for steel in level.steel.iter().flatten() {
+        // The file's steel x is in 4 pixel units from -16, which lines up with terrain and object x as they're stored.
+        draw_box(steel.x * 4 - size.min_x, steel.y, (steel.width as isize + 1) * 4, (steel.height as isize + 1) * 4,
+            STEEL_COLOUR, &mut bitmap, width);
+    }
+    for object in level.objects.iter().flatten() {
+        if object_animation(level, ground, object.obj_id)?.is_none() { continue }
+        let info = &ground.ground.object_info[object.obj_id];
+        let Some(colour) = trigger_colour(info.trigger_effect_id) else { continue };
+        // The game tracks triggers on a 4 pixel grid, so the object's position is rounded down first (camanis.net).
+        // Widths and heights of 0 mean 256. Upside down objects keep their trigger areas where they were.
+        let x = (object.x as isize).div_euclid(4) * 4 + info.trigger_left as isize * 4;
+        let y = (object.y as isize).div_euclid(4) * 4 + info.trigger_top as isize * 4 - 4;
+        let trigger_width = if info.trigger_width == 0 { 256 } else { info.trigger_width as isize };
+        let trigger_height = if info.trigger_height == 0 { 256 } else { info.trigger_height as isize };
+        draw_box(x - size.min_x, y, trigger_width * 4, trigger_height * 4, colour, &mut bitmap, width);
+    }
+    Ok(image::Image {
         bitmap,
         width: width as usize,
-        height: height as usize,
-    };
-    image
+        height: LEVEL_HEIGHT as usize,
+    })
+}
+
+// The overlay colour for an ObjectInfo::trigger_effect_id, or None for no effect.
+pub fn trigger_colour(effect_id: u8) -> Option<u32> {
+    match effect_id {
+        0 => None,
+        1 => Some(0x00ff00ff), // Exit, green.
+        4 => Some(0xff0000ff), // Trap, red.
+        5 => Some(0x0080ffff), // Drown, blue.
+        6 => Some(0xff8000ff), // Disintegrate, orange.
+        7 => Some(0xffff00ff), // One way wall left, yellow.
+        8 => Some(0x00ffffff), // One way wall right, cyan.
+        9 => Some(STEEL_COLOUR),
+        _ => Some(0xff00ffff), // Unknown, magenta.
+    }
+}
+
+// Blends a box over the canvas, with a solid outline so small ones still show.
+fn draw_box(x: isize, y: isize, box_width: isize, box_height: isize, colour: u32, canvas: &mut [u32], canvas_width: isize) {
+    for pixel_y in cmp::max(y, 0)..cmp::min(y + box_height, LEVEL_HEIGHT) {
+        for pixel_x in cmp::max(x, 0)..cmp::min(x + box_width, canvas_width) {
+            let is_edge = pixel_x == x || pixel_y == y || pixel_x == x + box_width - 1 || pixel_y == y + box_height - 1;
+            let pixel = &mut canvas[(pixel_y * canvas_width + pixel_x) as usize];
+            *pixel = if is_edge { colour } else { blend(*pixel, colour, OVERLAY_ALPHA) };
+        }
+    }
+}
+
+// Mixes colour over an opaque pixel, alpha out of 0xff.
+fn blend(pixel: u32, colour: u32, alpha: u32) -> u32 {
+    let mut blended: u32 = 0xff;
+    for shift in [24, 16, 8] {
+        let under = (pixel >> shift) & 0xff;
+        let over = (colour >> shift) & 0xff;
+        blended |= ((over * alpha + under * (0xff - alpha)) / 0xff) << shift;
+    }
+    blended
+}
+
+fn lowest_common_multiple(a: usize, b: usize) -> usize {
+    fn greatest_common_divisor(a: usize, b: usize) -> usize {
+        if b == 0 { a } else { greatest_common_divisor(b, a % b) }
+    }
+    a / greatest_common_divisor(a, b) * b
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_finder;
+    use crate::game_variant;
+    use crate::levels_loader;
+    use crate::specials_loader;
+
+    #[test]
+    fn test_animated_and_overlay_match_static() {
+        let files = file_finder::DataFiles::from_folder("data/lemmings").unwrap();
+        let variant = game_variant::GameVariant::detect(&files).unwrap();
+        let grounds = grounds_loader::load(&files).unwrap();
+        let specials = specials_loader::load(&files).unwrap();
+        let levels = levels_loader::load(&files, variant).unwrap();
+        for loaded in levels.iter().take(8) {
+            let level = &loaded.level;
+            let image = render(level, &grounds, &specials, Canvas::Cropped).unwrap();
+            let animation = render_animated(level, &grounds, &specials, Canvas::Cropped).unwrap();
+            assert_eq!((animation.width(), animation.height()), (image.width, image.height));
+            assert_eq!(animation.frame(0).unwrap(), image.bitmap, "{}", level.title());
+            assert!(animation.frame_count <= MAX_ANIMATION_FRAMES);
+
+            // The overlay only adds to the render.
+            let overlay = render_overlay(level, &grounds, &specials, Canvas::Cropped).unwrap();
+            assert_eq!((overlay.width, overlay.height), (image.width, image.height));
+            assert_ne!(overlay.bitmap, image.bitmap, "{}", level.title()); // Every level has an exit.
+
+            // Every looping object is back at its first frame once the animation wraps.
+            let ground = ground_for(level, &grounds).unwrap();
+            for object in level.objects.iter().flatten() {
+                let Some(anim) = object_animation(level, ground, object.obj_id).unwrap() else { continue };
+                if is_looping(ground, object.obj_id) && animation.frame_count < MAX_ANIMATION_FRAMES {
+                    assert!(animation.frame_count.is_multiple_of(anim.frames.len()), "{}", level.title());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_no_terrain_uses_the_playfield() {
+        let files = file_finder::DataFiles::from_folder("data/lemmings").unwrap();
+        let grounds = grounds_loader::load(&files).unwrap();
+        let specials = specials_loader::load(&files).unwrap();
+        let mut level = level::Level::default();
+        level.terrain.push(None);
+        level.terrain.push(Some(level::Terrain { terrain_id: TERRAIN_IDS, ..Default::default() })); // Skipped.
+        level.objects.push(Some(level::Object { x: 100, y: 50, ..Default::default() }));
+        let image = render(&level, &grounds, &specials, Canvas::Cropped).unwrap();
+        assert_eq!((image.width, image.height), (LEVEL_WIDTH as usize, LEVEL_HEIGHT as usize));
+        assert_eq!(pixel_mapping(&level, &grounds, Canvas::Cropped).unwrap().left_x, 0);
+        assert!(image.bitmap.iter().any(|pixel| *pixel != LEVEL_BACKGROUND)); // The exit.
+    }
+
+    #[test]
+    fn test_overlay_boxes() {
+        let files = file_finder::DataFiles::from_folder("data/lemmings").unwrap();
+        let grounds = grounds_loader::load(&files).unwrap();
+        let specials = specials_loader::load(&files).unwrap();
+        let mut level = level::Level::default();
+        level.objects.push(Some(level::Object { x: 203, y: 50, ..Default::default() })); // An exit, off the 4 pixel grid.
+        level.steel.push(Some(level::SteelArea { x: 150, y: 100, width: 1, height: 2 }));
+        let image = render(&level, &grounds, &specials, Canvas::Cropped).unwrap();
+        let overlay = render_overlay(&level, &grounds, &specials, Canvas::Cropped).unwrap();
+        let min_x = pixel_mapping(&level, &grounds, Canvas::Cropped).unwrap().left_x;
+        let pixel = |x: isize, y: isize| overlay.bitmap[(y * overlay.width as isize + x) as usize];
+
+        // The steel box: 8x12 pixels, with a solid outline and a blended inside.
+        let (steel_x, steel_y) = (150 * 4 - min_x, 100);
+        for (x, y) in [(steel_x, steel_y), (steel_x + 7, steel_y), (steel_x, steel_y + 11), (steel_x + 7, steel_y + 11)] {
+            assert_eq!(pixel(x, y), STEEL_COLOUR);
+        }
+        assert_ne!(pixel(steel_x - 1, steel_y), STEEL_COLOUR);
+        assert_ne!(pixel(steel_x + 8, steel_y + 12), STEEL_COLOUR);
+        let inside = ((steel_y + 1) * overlay.width as isize + steel_x + 1) as usize;
+        assert_eq!(overlay.bitmap[inside], blend(image.bitmap[inside], STEEL_COLOUR, OVERLAY_ALPHA));
+
+        // The exit's trigger, from the object rounded down to 200, 48.
+        let info = &grounds[&0].ground.object_info[0];
+        let exit = trigger_colour(info.trigger_effect_id).unwrap();
+        assert_eq!(exit, 0x00ff00ff);
+        let trigger_x = 200 + info.trigger_left as isize * 4 - min_x;
+        let trigger_y = 48 + info.trigger_top as isize * 4 - 4;
+        let (trigger_width, trigger_height) = (info.trigger_width as isize * 4, info.trigger_height as isize * 4);
+        assert_eq!(pixel(trigger_x, trigger_y), exit);
+        assert_eq!(pixel(trigger_x + trigger_width - 1, trigger_y + trigger_height - 1), exit);
+        assert_ne!(pixel(trigger_x - 1, trigger_y), exit);
+        assert_ne!(pixel(trigger_x, trigger_y - 1), exit);
+
+        // A colour for each effect, all different.
+        assert_eq!(trigger_colour(0), None);
+        let colours: Vec<u32> = [1, 4, 5, 6, 7, 8, 9, 2].iter().map(|id| trigger_colour(*id).unwrap()).collect();
+        for (i, colour) in colours.iter().enumerate() {
+            assert!(!colours[i + 1..].contains(colour), "{:08x}", colour);
+        }
+    }
+
+    #[test]
+    fn test_full_canvas_contains_cropped() {
+        let files = file_finder::DataFiles::from_folder("data/lemmings").unwrap();
+        let variant = game_variant::GameVariant::detect(&files).unwrap();
+        let grounds = grounds_loader::load(&files).unwrap();
+        let specials = specials_loader::load(&files).unwrap();
+        let levels = levels_loader::load(&files, variant).unwrap();
+        for loaded in levels.iter().step_by(10) {
+            let level = &loaded.level;
+            let cropped = render(level, &grounds, &specials, Canvas::Cropped).unwrap();
+            let full = render(level, &grounds, &specials, Canvas::Full).unwrap();
+            let mapping = pixel_mapping(level, &grounds, Canvas::Cropped).unwrap();
+            let full_mapping = pixel_mapping(level, &grounds, Canvas::Full).unwrap();
+            assert_eq!((full.width, full.height), (LEVEL_WIDTH as usize, LEVEL_HEIGHT as usize));
+            assert_eq!((mapping.width, mapping.height), (cropped.width, cropped.height));
+            assert_eq!(full_mapping.left_x, 0);
+
+            // Each cropped pixel is in the full one, wherever the mapping puts it. Some terrain is past the playfield.
+            for y in 0..cropped.height {
+                for x in 0..cropped.width {
+                    let (level_x, level_y) = mapping.to_level(x, y);
+                    let Some((full_x, full_y)) = full_mapping.to_pixel(level_x, level_y) else { continue };
+                    assert_eq!(cropped.bitmap[y * cropped.width + x], full.bitmap[full_y * full.width + full_x], "{}", level.title());
+                }
+            }
+        }
+    }
 }
@@ -1,17 +1,109 @@
 use crate::file_finder;
+use crate::game_variant::GameVariant;
 use crate::level;
+use crate::level_order;
+use crate::level_pack::LEVELS_PER_FILE;
 use crate::decompressor;
-use anyhow::Result;
+use crate::oddtable;
+use crate::error::Result;
 
-pub fn load(path: &str) -> Result<Vec<level::Level>> {
-    let mut all = Vec::<level::Level>::new();
-    for file in file_finder::find_2(path, "level", Some("dlvel"), ".dat")? {
-        let sections = decompressor::decompress(&file.data);
-        for section in sections {
-            let level = level::parse(&section)?;
-            all.push(level);
+pub struct LoadedLevel {
+    pub level: level::Level,
+    pub file_number: u32, // Eg 3 for LEVEL003.DAT.
+    pub section_index: usize, // Which level within that file.
+    pub oddtable_entry: Option<usize>, // Set if the globals and name come from ODDTABLE.DAT.
+    pub position: Option<level_order::Position>, // Eg Tricky 12. None for unused sections.
+}
+
+// Loads every level in the game's order, including the oddtable variations of the base layouts.
+pub fn load(files: &file_finder::DataFiles, variant: GameVariant) -> Result<Vec<LoadedLevel>> {
+    let mut all = Vec::<LoadedLevel>::new();
+    for file in files.find(variant.level_file_prefix(), ".dat") {
+        let sections = decompressor::decompress(&file.name, &file.data)?;
+        for (section_index, section) in sections.iter().enumerate() {
+            let level = level::parse(section).map_err(|e| e.in_file(&file.name))?;
+            all.push(LoadedLevel { level, file_number: file.number, section_index, oddtable_entry: None, position: None });
         }
     }
-    all.sort_by(|a, b| (&a.name).cmp(&b.name));
+    for odd in oddtable::load(files)? {
+        let file_number = (odd.entry / LEVELS_PER_FILE) as u32;
+        let section_index = odd.entry % LEVELS_PER_FILE;
+        let Some(base) = all.iter().find(|l| l.file_number == file_number && l.section_index == section_index) else { continue };
+        let level = odd.apply(&base.level);
+        all.push(LoadedLevel { level, file_number, section_index, oddtable_entry: Some(odd.entry), position: None });
+    }
+    let order = level_order::for_variant(variant);
+    for loaded in all.iter_mut() {
+        loaded.position = order.position(loaded.file_number, loaded.section_index, loaded.oddtable_entry);
+    }
+    // In the game's order, then any unused sections by name.
+    all.sort_by(|a, b| (a.position.is_none(), a.position, a.level.title()).cmp(&(b.position.is_none(), b.position, b.level.title())));
     Ok(all)
 }
+
+// Puts levels back in the order they were stored, eg to rebuild the packs as they were.
+// Oddtable variations sort after their base level.
+pub fn sort_by_file(levels: &mut [LoadedLevel]) {
+    levels.sort_by_key(|l| (l.file_number, l.section_index, l.oddtable_entry));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_positions_of_bundled_levels() {
+        for (folder, positioned, unpositioned) in [
+            ("lemmings", 120, 0), ("ohnomore", 100, 0), ("christmas91", 4, 0),
+            ("christmas92", 4, 0), ("holidays93", 32, 7), ("holidays94", 64, 7),
+        ] {
+            let files = file_finder::DataFiles::from_folder(&format!("data/{}", folder)).unwrap();
+            let levels = load(&files, GameVariant::detect(&files).unwrap()).unwrap();
+            let mut positions: Vec<level_order::Position> = levels.iter().filter_map(|l| l.position).collect();
+            positions.dedup();
+            assert_eq!(positions.len(), positioned, "{}", folder);
+            assert_eq!(levels.len() - positioned, unpositioned, "{}", folder);
+        }
+        let lemmings = load(&file_finder::DataFiles::from_folder("data/lemmings").unwrap(), GameVariant::Lemmings).unwrap();
+        let titles: Vec<&str> = lemmings.iter().map(|l| l.level.title()).collect();
+        assert_eq!(titles, [
+            // Fun.
+            "Just dig!", "Only floaters can survive this", "Tailor-made for blockers", "Now use miners and climbers",
+            "You need bashers this time", "A task for blockers and bombers", "Builders will help you here",
+            "Not as complicated as it looks", "As long as you try your best", "Smile if you love lemmings",
+            "Keep your hair on Mr. Lemming", "Patience", "We all fall down", "Origins and Lemmings",
+            "Don't let your eyes deceive you", "Don't do anything too hasty", "Easy when you know how",
+            "Let's block and blow", "Take good care of my Lemmings", "We are now at LEMCON ONE", "Konbanwa Lemming san",
+            "Lemmings Lemmings everywhere", "Let's be careful out there", "Nightmare on Lem street",
+            "I've lost that Lemming feeling", "If only they could fly", "Lock up your Lemmings", "worra lorra lemmings",
+            "You Live and Lem", "A Beast of a level",
+            // Tricky.
+            "This should be a doddle!", "We all fall down", "A ladder would be handy", "Here's one I prepared earlier",
+            "Careless clicking costs lives", "Lemmingology", "Been there, seen it, done it",
+            "Lemming sanctuary in sight", "They just keep on coming", "There's a lot of them about",
+            "Lemmings in the attic", "Bitter Lemming", "Lemming Drops", "MENACING !!", "Ozone friendly Lemmings",
+            "No added colours or Lemmings", "Luvly Jubly", "Diet Lemmingaid", "It's Lemmingentry Watson",
+            "Postcard from Lemmingland", "One way digging to freedom", "All the 6`s ........",
+            "Turn around young lemmings!", "From The Boundary Line", "Tightrope City", "Cascade",
+            "I have a cunning plan", "The Island of the Wicker people", "Lost something?", "Rainbow Island",
+            // Taxing.
+            "If at first you don`t succeed..", "Watch out, there`s traps about", "Heaven can wait (we hope!!!!)",
+            "Lend a helping hand....", "The Prison!", "Compression Method 1", "Every Lemming for himself!!!",
+            "The Art Gallery", "Perseverance", "Izzie Wizzie lemmings get busy", "The ascending pillar scenario",
+            "Livin` On The Edge", "Upsidedown World", "Hunt the Nessy....", "What an AWESOME level",
+            "Mary Poppins` land", "X marks the spot", "Tribute to M.C.Escher", "Bomboozal", "Walk the web rope",
+            "Feel the heat!", "Come on over to my place", "King of the castle", "Take a running jump.....",
+            "Follow the leader...", "Triple Trouble", "Call in the bomb squad", "POOR WEE CREATURES!",
+            "How do I dig up the way?", "We all fall down",
+            // Mayhem.
+            "Steel Works", "The Boiler Room", "It`s hero time!", "The Crossroads", "Down, along, up. In that order",
+            "One way or another", "Poles Apart", "Last one out is a rotten egg!", "Curse of the Pharaohs",
+            "Pillars of Hercules", "We all fall down", "The Far Side", "The Great Lemming Caper", "Pea Soup",
+            "The Fast Food Kitchen...", "Just a Minute...", "Stepping Stones", "And then there were four....",
+            "Time to get up!", "With a twist of lemming please", "The Crankshaft", "A BeastII of a level",
+            "Going up.......", "All or Nothing", "Have a nice day!", "The Steel Mines of Kessel",
+            "Just a Minute (Part Two)", "Mind the step.....", "Save Me", "Rendezvous at the Mountain",
+        ]);
+        assert!(lemmings.iter().enumerate().all(|(i, l)| l.position.map(|p| p.rating_index * 30 + p.number - 1) == Some(i)));
+    }
+}
@@ -0,0 +1,41 @@
+// Decodes (and re-encodes) the data files of DOS Lemmings and its sequels.
+// Start with file_finder::DataFiles, from a folder or from memory, detect its GameVariant, then use the loaders.
+
+mod bit_iter_dat;
+mod bit_iter_ms_first;
+mod bit_writer_dat;
+mod deflate;
+mod inflate;
+mod reader;
+mod vga_colour;
+pub mod error;
+pub mod compressor;
+pub mod decompressor;
+pub mod ground;
+pub mod grounds_loader;
+pub mod ground_pack;
+pub mod image;
+pub mod png;
+pub mod quantise;
+pub mod level;
+pub mod levels_loader;
+pub mod level_order;
+pub mod oddtable;
+pub mod level_pack;
+pub mod file_finder;
+pub mod game_variant;
+pub mod level_renderer;
+pub mod special;
+pub mod specials_loader;
+pub mod maindat;
+pub mod pc_speaker;
+pub mod greet;
+
+pub use error::{Error, Result};
+pub use file_finder::DataFiles;
+pub use game_variant::GameVariant;
+pub use grounds_loader::GroundWithImages;
+pub use ground::Ground;
+pub use image::{Image, Animation, IndexedImage, IndexedAnimation, Mask};
+pub use level::Level;
+pub use maindat::MainDat;
@@ -1,152 +1,399 @@
-mod bit_iter_dat;
-mod bit_iter_ms_first;
-mod decompressor;
-mod ground;
-mod grounds_loader;
-mod image;
-mod png;
-mod level;
-mod levels_loader;
-mod file_finder;
-mod level_renderer;
-mod special;
-mod specials_loader;
-mod maindat;
-
-use anyhow::Result;
+use digger_decoder::{DataFiles, GameVariant, IndexedAnimation, IndexedImage, Mask, decompressor, greet, grounds_loader, level_renderer, levels_loader, maindat, specials_loader};
+use anyhow::{Result, bail};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+type Grounds = HashMap<u32, digger_decoder::GroundWithImages>;
+
+const USAGE: &str = "Usage:
+digger-decoder <command> <path> [options]
+
+Commands:
+  extract <folder>        Exports levels, grounds, main.dat and the greeting (the default).
+  render-levels <folder>  Exports the level images and output_levels.txt only.
+  info <folder>           Lists what's in the data files without writing anything.
+  dump-sections <file>    Writes each decompressed section of a DAT file as a .bin.
+
+Options:
+  --out <dir>      Where to write, instead of the current folder.
+  --dry-run        Lists the files that would be written.
+  --indexed        Writes palette PNGs with the game's original colour indices (not level renders).
+  --animated       Also writes each level as an APNG with its objects animating.
+  --overlay        Also writes each level with its trigger areas and steel boxed in.
+  --full-width     Renders levels on the game's whole 1600 pixel playfield, instead of cropping to the terrain.
+  --only <assets>  Comma separated, any of: levels, grounds, main, greet.
+  --level <text>   Only levels whose name or position contains this, eg 'tricky 1'. Repeatable.
+  --ground <n>     Only this ground, eg 0 for GROUND0O.DAT. Repeatable.
+
+Eg: digger-decoder extract data/lemmings --out out --only levels --level fun";
 
 fn main() -> Result<()> {
     println!("-=[ Digger Decoder ]=-");
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() < 2 {
-        println!("Usage:");
-        println!("digger-decoder data/lemmings");
-    } else {
-        decode(&args[1])?;
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let Some(command) = args.first() else {
+        println!("{}", USAGE);
+        return Ok(());
+    };
+    match command.as_str() {
+        "extract" => extract(&Options::parse(&args[1..])?),
+        "render-levels" => render_levels(&Options::parse(&args[1..])?),
+        "info" => info(&Options::parse(&args[1..])?),
+        "dump-sections" => dump_sections(&Options::parse(&args[1..])?),
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            Ok(())
+        },
+        _ => extract(&Options::parse(&args)?), // Eg 'digger-decoder data/lemmings', as before there were commands.
+    }
+}
+
+#[derive(Debug, Default)]
+struct Options {
+    path: String,
+    out: PathBuf,
+    dry_run: bool,
+    indexed: bool,
+    animated: bool,
+    overlay: bool,
+    full_width: bool,
+    only: Vec<String>, // Empty means everything.
+    levels: Vec<String>, // Lowercased.
+    grounds: Vec<u32>,
+}
+
+const ASSETS: [&str; 4] = ["levels", "grounds", "main", "greet"];
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Options> {
+        let mut options = Options { out: PathBuf::from("."), ..Default::default() };
+        let mut path: Option<String> = None;
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            let mut value = || match iter.next() {
+                Some(value) => Ok(value.clone()),
+                None => Err(anyhow::anyhow!("{} needs a value", arg)),
+            };
+            match arg.as_str() {
+                "--out" => options.out = PathBuf::from(value()?),
+                "--dry-run" => options.dry_run = true,
+                "--indexed" => options.indexed = true,
+                "--animated" => options.animated = true,
+                "--overlay" => options.overlay = true,
+                "--full-width" => options.full_width = true,
+                "--only" => {
+                    for asset in value()?.split(',') {
+                        if !ASSETS.contains(&asset) {
+                            bail!("Unknown asset '{}', expected one of: {}", asset, ASSETS.join(", "));
+                        }
+                        options.only.push(asset.to_string());
+                    }
+                },
+                "--level" => options.levels.push(value()?.to_lowercase()),
+                "--ground" => {
+                    let value = value()?;
+                    let Ok(ground) = value.parse() else { bail!("--ground needs a number, not '{}'", value) };
+                    options.grounds.push(ground);
+                },
+                _ if arg.starts_with("--") => bail!("Unknown option {}", arg),
+                _ if path.is_some() => bail!("Unexpected argument {}", arg),
+                _ => path = Some(arg.clone()),
+            }
+        }
+        let Some(path) = path else { bail!("Missing path\n\n{}", USAGE) };
+        options.path = path;
+        Ok(options)
+    }
+
+    fn wants(&self, asset: &str) -> bool {
+        self.only.is_empty() || self.only.iter().any(|a| a == asset)
+    }
+
+    fn wants_level(&self, title: &str, position: &str) -> bool {
+        let title = title.to_lowercase();
+        let position = position.to_lowercase();
+        self.levels.is_empty() || self.levels.iter().any(|l| title.contains(l.as_str()) || position.contains(l.as_str()))
+    }
+
+    fn wants_ground(&self, ground: u32) -> bool {
+        self.grounds.is_empty() || self.grounds.contains(&ground)
+    }
+
+    fn canvas(&self) -> level_renderer::Canvas {
+        if self.full_width { level_renderer::Canvas::Full } else { level_renderer::Canvas::Cropped }
+    }
+
+    fn png(&self, image: &IndexedImage) -> Vec<u8> {
+        if self.indexed { image.as_indexed_png() } else { image.as_png() }
+    }
+
+    fn apng(&self, animation: &IndexedAnimation) -> Vec<u8> {
+        if self.indexed { animation.as_indexed_apng() } else { animation.as_apng() }
+    }
+
+    fn mask_apng(&self, mask: &Mask) -> Vec<u8> {
+        if self.indexed { mask.as_indexed_apng() } else { mask.as_apng() }
+    }
+
+    // Writes into the output folder, or just lists the file for a dry run.
+    fn write(&self, name: impl AsRef<Path>, data: impl AsRef<[u8]>) -> Result<()> {
+        let path = self.out.join(name);
+        if self.dry_run {
+            println!("{}", path.display());
+            return Ok(());
+        }
+        std::fs::create_dir_all(&self.out)?;
+        std::fs::write(path, data)?;
+        Ok(())
+    }
+}
+
+// The name used in file names and listings, eg 'Tricky 12', or the load order for unrecognised games.
+fn position_name(loaded: &levels_loader::LoadedLevel, index: usize) -> String {
+    match loaded.position {
+        Some(position) => position.title(),
+        None => format!("{}", index),
+    }
+}
+
+fn describe(loaded: &levels_loader::LoadedLevel, index: usize) -> String {
+    let oddtable = match loaded.oddtable_entry {
+        Some(entry) => format!(", oddtable entry {}", entry),
+        None => String::new(),
+    };
+    format!("{}: {} (file {} section {}{})",
+        position_name(loaded, index), loaded.level.title(), loaded.file_number, loaded.section_index, oddtable)
+}
+
+// Reads the data files and works out which game they're from.
+fn open(path: &str) -> Result<(DataFiles, GameVariant)> {
+    let files = DataFiles::from_folder(path)?;
+    let (variant, warning) = GameVariant::identify(&files)?;
+    println!("Detected {}", variant.name());
+    if let Some(warning) = warning {
+        eprintln!("Warning: {}", warning);
+    }
+    Ok((files, variant))
+}
+
+fn extract(options: &Options) -> Result<()> {
+    let (files, variant) = open(&options.path)?;
+    if options.wants("levels") || options.wants("grounds") {
+        println!("Loading grounds...");
+        let grounds = grounds_loader::load(&files)?;
+        if options.wants("levels") {
+            export_levels(&files, variant, &grounds, options)?;
+        }
+        if options.wants("grounds") {
+            export_grounds(&grounds, options)?;
+        }
+    }
+    if options.wants("main") {
+        export_main(&files, variant, options)?;
+    }
+    if options.wants("greet") {
+        export_greet(&files, options)?;
     }
     Ok(())
 }
 
-fn decode(path: &str) -> Result<()> {
-    println!("Loading main...");
-    let maindat = maindat::MainDat::load(path)?;
+fn render_levels(options: &Options) -> Result<()> {
+    let (files, variant) = open(&options.path)?;
     println!("Loading grounds...");
-    let grounds = grounds_loader::load(path)?;
+    let grounds = grounds_loader::load(&files)?;
+    export_levels(&files, variant, &grounds, options)
+}
+
+fn export_levels(files: &DataFiles, variant: GameVariant, grounds: &Grounds, out: &Options) -> Result<()> {
     println!("Loading specials...");
-    let specials = specials_loader::load(path)?;
+    let specials = specials_loader::load(files)?;
     println!("Loading levels...");
-    let levels = levels_loader::load(path)?;
+    let levels = levels_loader::load(files, variant)?;
 
     println!("Exporting levels...");
-    for (i, level) in levels.iter().enumerate() {
-        let image = level_renderer::render(&level, &grounds, &specials);
-        let png = image.as_png();
-        let safe_name = file_safe_string(&level.name);
-        let name = format!("output_level{}_{}.static.png", i, safe_name);
-        std::fs::write(name, png)?;
+    let mut metadata = String::new();
+    for (i, loaded) in levels.iter().enumerate() {
+        let level = &loaded.level;
+        let position = position_name(loaded, i);
+        if !out.wants_level(level.title(), &position) { continue }
+        let image = level_renderer::render(level, grounds, &specials, out.canvas())?;
+        let name = format!("output_level_{}_{}.static.png", file_safe_string(&position), file_safe_string(level.title()));
+        out.write(name, image.as_png())?; // Renders mix ground and VGASPEC palettes, so they stay RGBA.
+        if out.animated {
+            let animation = level_renderer::render_animated(level, grounds, &specials, out.canvas())?;
+            let name = format!("output_level_{}_{}.animation.png", file_safe_string(&position), file_safe_string(level.title()));
+            out.write(name, animation.as_apng()?)?;
+        }
+        if out.overlay {
+            let overlay = level_renderer::render_overlay(level, grounds, &specials, out.canvas())?;
+            let name = format!("output_level_{}_{}.overlay.png", file_safe_string(&position), file_safe_string(level.title()));
+            out.write(name, overlay.as_png())?;
+        }
+        let mapping = level_renderer::pixel_mapping(level, grounds, out.canvas())?;
+        metadata += &format!("{}, image x 0 is level x {}\n", describe(loaded, i), mapping.left_x);
     }
+    out.write("output_levels.txt", metadata)
+}
 
+fn export_grounds(grounds: &Grounds, out: &Options) -> Result<()> {
     println!("Exporting grounds...");
     for (gi, ground) in grounds {
-        for (oi, o) in ground.objects {
-            std::fs::write(
+        if !out.wants_ground(*gi) { continue }
+        for (oi, o) in &ground.objects {
+            out.write(
                 format!("output_ground{}_object{}.animation.png", gi, oi),
-                o.as_apng())?;
+                out.apng(o))?;
         }
-        for (ti, t) in ground.terrain {
-            std::fs::write(
+        for (ti, t) in &ground.terrain {
+            out.write(
                 format!("output_ground{}_terrain{}.static.png", gi, ti),
-                t.as_png())?;
+                out.png(t))?;
         }
     }
+    Ok(())
+}
 
+fn export_main(files: &DataFiles, variant: GameVariant, out: &Options) -> Result<()> {
+    println!("Loading main...");
+    let maindat = maindat::MainDat::load(files, variant)?;
     println!("Exporting main...");
-    std::fs::write("output_main_lemming_walking_right.animation.png", maindat.lemming_animations.walking_right.as_apng())?;
-    std::fs::write("output_main_lemming_jumping_right.animation.png", maindat.lemming_animations.jumping_right.as_apng())?;
-    std::fs::write("output_main_lemming_walking_left.animation.png", maindat.lemming_animations.walking_left.as_apng())?;
-    std::fs::write("output_main_lemming_jumping_left.animation.png", maindat.lemming_animations.jumping_left.as_apng())?;
-    std::fs::write("output_main_lemming_digging.animation.png", maindat.lemming_animations.digging.as_apng())?;
-    std::fs::write("output_main_lemming_climbing_right.animation.png", maindat.lemming_animations.climbing_right.as_apng())?;
-    std::fs::write("output_main_lemming_climbing_left.animation.png", maindat.lemming_animations.climbing_left.as_apng())?;
-    std::fs::write("output_main_lemming_drowning.animation.png", maindat.lemming_animations.drowning.as_apng())?;
-    std::fs::write("output_main_lemming_post_climb_right.animation.png", maindat.lemming_animations.post_climb_right.as_apng())?;
-    std::fs::write("output_main_lemming_post_climb_left.animation.png", maindat.lemming_animations.post_climb_left.as_apng())?;
-    std::fs::write("output_main_lemming_brick_laying_right.animation.png", maindat.lemming_animations.brick_laying_right.as_apng())?;
-    std::fs::write("output_main_lemming_brick_laying_left.animation.png", maindat.lemming_animations.brick_laying_left.as_apng())?;
-    std::fs::write("output_main_lemming_bashing_right.animation.png", maindat.lemming_animations.bashing_right.as_apng())?;
-    std::fs::write("output_main_lemming_bashing_left.animation.png", maindat.lemming_animations.bashing_left.as_apng())?;
-    std::fs::write("output_main_lemming_mining_right.animation.png", maindat.lemming_animations.mining_right.as_apng())?;
-    std::fs::write("output_main_lemming_mining_left.animation.png", maindat.lemming_animations.mining_left.as_apng())?;
-    std::fs::write("output_main_lemming_falling_right.animation.png", maindat.lemming_animations.falling_right.as_apng())?;
-    std::fs::write("output_main_lemming_falling_left.animation.png", maindat.lemming_animations.falling_left.as_apng())?;
-    std::fs::write("output_main_lemming_pre_umbrella_right.animation.png", maindat.lemming_animations.pre_umbrella_right.as_apng())?;
-    std::fs::write("output_main_lemming_umbrella_right.animation.png", maindat.lemming_animations.umbrella_right.as_apng())?;
-    std::fs::write("output_main_lemming_pre_umbrella_left.animation.png", maindat.lemming_animations.pre_umbrella_left.as_apng())?;
-    std::fs::write("output_main_lemming_umbrella_left.animation.png", maindat.lemming_animations.umbrella_left.as_apng())?;
-    std::fs::write("output_main_lemming_splatting.animation.png", maindat.lemming_animations.splatting.as_apng())?;
-    std::fs::write("output_main_lemming_exiting.animation.png", maindat.lemming_animations.exiting.as_apng())?;
-    std::fs::write("output_main_lemming_fried.animation.png", maindat.lemming_animations.fried.as_apng())?;
-    std::fs::write("output_main_lemming_blocking.animation.png", maindat.lemming_animations.blocking.as_apng())?;
-    std::fs::write("output_main_lemming_shrugging_right.animation.png", maindat.lemming_animations.shrugging_right.as_apng())?;
-    std::fs::write("output_main_lemming_shrugging_left.animation.png", maindat.lemming_animations.shrugging_left.as_apng())?;
-    std::fs::write("output_main_lemming_oh_no_ing.animation.png", maindat.lemming_animations.oh_no_ing.as_apng())?;
-    std::fs::write("output_main_lemming_explosion.animation.png", maindat.lemming_animations.explosion.as_apng())?;
-
-    std::fs::write("output_main_mask_bash_right.animation.png", maindat.masks.bash_right.as_apng())?;
-    std::fs::write("output_main_mask_bash_left.animation.png", maindat.masks.bash_left.as_apng())?;
-    std::fs::write("output_main_mask_mine_right.animation.png", maindat.masks.mine_right.as_apng())?;
-    std::fs::write("output_main_mask_mine_left.animation.png", maindat.masks.mine_left.as_apng())?;
-    std::fs::write("output_main_mask_explosion.animation.png", maindat.masks.explosion.as_apng())?;
+    out.write("output_main_lemming_walking_right.animation.png", out.apng(&maindat.lemming_animations.walking_right))?;
+    out.write("output_main_lemming_jumping_right.animation.png", out.apng(&maindat.lemming_animations.jumping_right))?;
+    out.write("output_main_lemming_walking_left.animation.png", out.apng(&maindat.lemming_animations.walking_left))?;
+    out.write("output_main_lemming_jumping_left.animation.png", out.apng(&maindat.lemming_animations.jumping_left))?;
+    out.write("output_main_lemming_digging.animation.png", out.apng(&maindat.lemming_animations.digging))?;
+    out.write("output_main_lemming_climbing_right.animation.png", out.apng(&maindat.lemming_animations.climbing_right))?;
+    out.write("output_main_lemming_climbing_left.animation.png", out.apng(&maindat.lemming_animations.climbing_left))?;
+    out.write("output_main_lemming_drowning.animation.png", out.apng(&maindat.lemming_animations.drowning))?;
+    out.write("output_main_lemming_post_climb_right.animation.png", out.apng(&maindat.lemming_animations.post_climb_right))?;
+    out.write("output_main_lemming_post_climb_left.animation.png", out.apng(&maindat.lemming_animations.post_climb_left))?;
+    out.write("output_main_lemming_brick_laying_right.animation.png", out.apng(&maindat.lemming_animations.brick_laying_right))?;
+    out.write("output_main_lemming_brick_laying_left.animation.png", out.apng(&maindat.lemming_animations.brick_laying_left))?;
+    out.write("output_main_lemming_bashing_right.animation.png", out.apng(&maindat.lemming_animations.bashing_right))?;
+    out.write("output_main_lemming_bashing_left.animation.png", out.apng(&maindat.lemming_animations.bashing_left))?;
+    out.write("output_main_lemming_mining_right.animation.png", out.apng(&maindat.lemming_animations.mining_right))?;
+    out.write("output_main_lemming_mining_left.animation.png", out.apng(&maindat.lemming_animations.mining_left))?;
+    out.write("output_main_lemming_falling_right.animation.png", out.apng(&maindat.lemming_animations.falling_right))?;
+    out.write("output_main_lemming_falling_left.animation.png", out.apng(&maindat.lemming_animations.falling_left))?;
+    out.write("output_main_lemming_pre_umbrella_right.animation.png", out.apng(&maindat.lemming_animations.pre_umbrella_right))?;
+    out.write("output_main_lemming_umbrella_right.animation.png", out.apng(&maindat.lemming_animations.umbrella_right))?;
+    out.write("output_main_lemming_pre_umbrella_left.animation.png", out.apng(&maindat.lemming_animations.pre_umbrella_left))?;
+    out.write("output_main_lemming_umbrella_left.animation.png", out.apng(&maindat.lemming_animations.umbrella_left))?;
+    out.write("output_main_lemming_splatting.animation.png", out.apng(&maindat.lemming_animations.splatting))?;
+    out.write("output_main_lemming_exiting.animation.png", out.apng(&maindat.lemming_animations.exiting))?;
+    out.write("output_main_lemming_fried.animation.png", out.apng(&maindat.lemming_animations.fried))?;
+    out.write("output_main_lemming_blocking.animation.png", out.apng(&maindat.lemming_animations.blocking))?;
+    out.write("output_main_lemming_shrugging_right.animation.png", out.apng(&maindat.lemming_animations.shrugging_right))?;
+    out.write("output_main_lemming_shrugging_left.animation.png", out.apng(&maindat.lemming_animations.shrugging_left))?;
+    out.write("output_main_lemming_oh_no_ing.animation.png", out.apng(&maindat.lemming_animations.oh_no_ing))?;
+    out.write("output_main_lemming_explosion.animation.png", out.apng(&maindat.lemming_animations.explosion))?;
+
+    out.write("output_main_mask_bash_right.animation.png", out.mask_apng(&maindat.masks.bash_right))?;
+    out.write("output_main_mask_bash_left.animation.png", out.mask_apng(&maindat.masks.bash_left))?;
+    out.write("output_main_mask_mine_right.animation.png", out.mask_apng(&maindat.masks.mine_right))?;
+    out.write("output_main_mask_mine_left.animation.png", out.mask_apng(&maindat.masks.mine_left))?;
+    out.write("output_main_mask_explosion.animation.png", out.mask_apng(&maindat.masks.explosion))?;
 
     for (i, image) in maindat.countdown_numbers.iter().enumerate() {
-        std::fs::write(
+        out.write(
             format!("output_main_countdown{}.static.png", i),
-            image.as_png())?;
+            out.png(image))?;
     }
 
-    std::fs::write("output_main_font_percent.static.png", maindat.game_font.percent.as_png())?;
-    std::fs::write("output_main_font_dash.static.png", maindat.game_font.dash.as_png())?;
+    out.write("output_main_font_percent.static.png", out.png(&maindat.game_font.percent))?;
+    out.write("output_main_font_dash.static.png", out.png(&maindat.game_font.dash))?;
     for (i, im) in maindat.game_font.digits.iter().enumerate() {
-        std::fs::write(
+        out.write(
             format!("output_main_font_digit{}.static.png", i),
-            im.as_png())?;
+            out.png(im))?;
     }
     for (i, im) in maindat.game_font.letters.iter().enumerate() {
-        std::fs::write(
+        out.write(
             format!("output_main_font_letter{}.static.png", i),
-            im.as_png())?;
-    }
-
-    std::fs::write("output_main_skill_panel_high.static.png", maindat.skill_panel_high_perf.as_png())?;
-    std::fs::write("output_main_skill_panel.static.png", maindat.skill_panel.as_png())?;
-
-    std::fs::write("output_main_menu_background.static.png", maindat.main_menu.background.as_png())?;
-    std::fs::write("output_main_menu_logo.static.png", maindat.main_menu.logo.as_png())?;
-    std::fs::write("output_main_menu_f1.static.png", maindat.main_menu.f1.as_png())?;
-    std::fs::write("output_main_menu_f2.static.png", maindat.main_menu.f2.as_png())?;
-    std::fs::write("output_main_menu_f3.static.png", maindat.main_menu.f3.as_png())?;
-    std::fs::write("output_main_menu_f4.static.png", maindat.main_menu.f4.as_png())?;
-    std::fs::write("output_main_menu_level_rating.static.png", maindat.main_menu.level_rating.as_png())?;
-    std::fs::write("output_main_menu_exit_to_dos.static.png", maindat.main_menu.exit_to_dos.as_png())?;
-    std::fs::write("output_main_menu_music_note.static.png", maindat.main_menu.music_note.as_png())?;
-    std::fs::write("output_main_menu_fx.static.png", maindat.main_menu.fx.as_png())?;
-    std::fs::write("output_main_menu_reel.static.png", maindat.main_menu.reel.as_png())?;
-    std::fs::write("output_main_menu_mayhem.static.png", maindat.main_menu.mayhem.as_png())?;
-    std::fs::write("output_main_menu_taxing.static.png", maindat.main_menu.taxing.as_png())?;
-    std::fs::write("output_main_menu_tricky.static.png", maindat.main_menu.tricky.as_png())?;
-    std::fs::write("output_main_menu_fun.static.png", maindat.main_menu.fun.as_png())?;
-
-    std::fs::write("output_main_menu_blink1.animation.png", maindat.main_menu.blink1.as_apng())?;
-    std::fs::write("output_main_menu_blink2.animation.png", maindat.main_menu.blink2.as_apng())?;
-    std::fs::write("output_main_menu_blink3.animation.png", maindat.main_menu.blink3.as_apng())?;
-    std::fs::write("output_main_menu_blink4.animation.png", maindat.main_menu.blink4.as_apng())?;
-    std::fs::write("output_main_menu_blink5.animation.png", maindat.main_menu.blink5.as_apng())?;
-    std::fs::write("output_main_menu_blink6.animation.png", maindat.main_menu.blink6.as_apng())?;
-    std::fs::write("output_main_menu_blink7.animation.png", maindat.main_menu.blink7.as_apng())?;
-    std::fs::write("output_main_menu_left_scroller.animation.png", maindat.main_menu.left_scroller.as_apng())?;
-    std::fs::write("output_main_menu_right_scroller.animation.png", maindat.main_menu.right_scroller.as_apng())?;
-    std::fs::write("output_main_menu_menu_font.animation.png", maindat.main_menu.menu_font.as_apng())?;
+            out.png(im))?;
+    }
+
+    out.write("output_main_skill_panel_high.static.png", out.png(&maindat.skill_panel_high_perf))?;
+    out.write("output_main_skill_panel.static.png", out.png(&maindat.skill_panel))?;
 
+    out.write("output_main_menu_background.static.png", out.png(&maindat.main_menu.background))?;
+    out.write("output_main_menu_logo.static.png", out.png(&maindat.main_menu.logo))?;
+    out.write("output_main_menu_f1.static.png", out.png(&maindat.main_menu.f1))?;
+    out.write("output_main_menu_f2.static.png", out.png(&maindat.main_menu.f2))?;
+    out.write("output_main_menu_f3.static.png", out.png(&maindat.main_menu.f3))?;
+    out.write("output_main_menu_f4.static.png", out.png(&maindat.main_menu.f4))?;
+    out.write("output_main_menu_level_rating.static.png", out.png(&maindat.main_menu.level_rating))?;
+    out.write("output_main_menu_exit_to_dos.static.png", out.png(&maindat.main_menu.exit_to_dos))?;
+    out.write("output_main_menu_music_note.static.png", out.png(&maindat.main_menu.music_note))?;
+    out.write("output_main_menu_fx.static.png", out.png(&maindat.main_menu.fx))?;
+    out.write("output_main_menu_reel.static.png", out.png(&maindat.main_menu.reel))?;
+    out.write("output_main_pc_speaker_sounds.bin", maindat.pc_speaker_sounds.to_bytes())?;
+    out.write("output_main_pc_speaker_sounds.txt", maindat.pc_speaker_sounds.describe())?;
+    for (i, rating) in maindat.main_menu.ratings.iter().enumerate() {
+        out.write(format!("output_main_menu_rating{}.static.png", i), out.png(rating))?;
+    }
+
+    out.write("output_main_menu_blink1.animation.png", out.apng(&maindat.main_menu.blink1))?;
+    out.write("output_main_menu_blink2.animation.png", out.apng(&maindat.main_menu.blink2))?;
+    out.write("output_main_menu_blink3.animation.png", out.apng(&maindat.main_menu.blink3))?;
+    out.write("output_main_menu_blink4.animation.png", out.apng(&maindat.main_menu.blink4))?;
+    out.write("output_main_menu_blink5.animation.png", out.apng(&maindat.main_menu.blink5))?;
+    out.write("output_main_menu_blink6.animation.png", out.apng(&maindat.main_menu.blink6))?;
+    out.write("output_main_menu_blink7.animation.png", out.apng(&maindat.main_menu.blink7))?;
+    out.write("output_main_menu_left_scroller.animation.png", out.apng(&maindat.main_menu.left_scroller))?;
+    out.write("output_main_menu_right_scroller.animation.png", out.apng(&maindat.main_menu.right_scroller))?;
+    out.write("output_main_menu_menu_font.animation.png", out.apng(&maindat.main_menu.menu_font))?;
+    Ok(())
+}
+
+fn export_greet(files: &DataFiles, out: &Options) -> Result<()> {
+    match greet::Greet::load(files) {
+        Ok(greet) => {
+            println!("Exporting greeting...");
+            out.write("output_greet.txt", greet.text())?;
+        },
+        Err(error) => println!("Skipping greeting: {}", error),
+    }
+    Ok(())
+}
+
+fn info(options: &Options) -> Result<()> {
+    let (files, variant) = open(&options.path)?;
+    let grounds = grounds_loader::load(&files)?;
+    let specials = specials_loader::load(&files)?;
+    let levels = levels_loader::load(&files, variant)?;
+    let maindat = maindat::MainDat::load(&files, variant)?;
+
+    let mut ground_numbers: Vec<&u32> = grounds.keys().collect();
+    ground_numbers.sort();
+    for number in ground_numbers {
+        let ground = &grounds[number];
+        println!("Ground {}: {} terrain pieces, {} objects", number, ground.terrain.len(), ground.objects.len());
+    }
+    let mut special_numbers: Vec<&u32> = specials.keys().collect();
+    special_numbers.sort();
+    println!("Specials: {:?}", special_numbers);
+    println!("Rating signs: {}", maindat.main_menu.ratings.len());
+    println!("Greeting: {}", if files.get("greet.dat").is_some() { "yes" } else { "no" });
+    let oddtable_count = levels.iter().filter(|l| l.oddtable_entry.is_some()).count();
+    println!("Levels: {} ({} from ODDTABLE.DAT)", levels.len(), oddtable_count);
+    for (i, loaded) in levels.iter().enumerate() {
+        if !options.wants_level(loaded.level.title(), &position_name(loaded, i)) { continue }
+        println!("  {}", describe(loaded, i));
+    }
+    Ok(())
+}
+
+// Eg MAIN.DAT to output_main_section0.bin, etc.
+fn dump_sections(options: &Options) -> Result<()> {
+    let path = Path::new(&options.path);
+    let data = std::fs::read(path)?;
+    let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    let sections = decompressor::decompress(&name, &data)?;
+    let stem = file_safe_string(&name.to_lowercase().replace(".dat", ""));
+    for (i, section) in sections.iter().enumerate() {
+        println!("Section {}: {} bytes", i, section.len());
+        options.write(format!("output_{}_section{}.bin", stem, i), section)?;
+    }
     Ok(())
 }
 
@@ -166,3 +413,26 @@ fn file_safe_string(str: &str) -> String {
     }
     s
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options> {
+        Options::parse(&args.iter().map(|a| a.to_string()).collect::<Vec<String>>())
+    }
+
+    #[test]
+    fn test_options() {
+        let options = parse(&["data/lemmings", "--out", "out", "--only", "levels,main", "--level", "Tricky 1", "--ground", "2", "--dry-run", "--indexed", "--animated", "--overlay", "--full-width"]).unwrap();
+        assert_eq!(options.path, "data/lemmings");
+        assert_eq!(options.out, PathBuf::from("out"));
+        assert!(options.dry_run && options.indexed && options.animated && options.overlay && options.full_width);
+        assert!(options.wants("main") && !options.wants("grounds"));
+        assert!(options.wants_level("Anything", "Tricky 12") && !options.wants_level("Just dig!", "Fun 1"));
+        assert!(options.wants_ground(2) && !options.wants_ground(0));
+        assert!(parse(&["data/lemmings", "--only", "sounds"]).is_err());
+        assert!(parse(&["data/lemmings", "--out"]).is_err());
+        assert!(parse(&["--dry-run"]).is_err());
+    }
+}
@@ -1,104 +1,105 @@
 // This is for decoding the contents of main.dat
 // https://www.camanis.net/lemmings/files/docs/lemmings_main_dat_file_format.txt
 
-use anyhow::{Result, bail};
 use crate::bit_iter_ms_first;
-use crate::image::{Image, Animation, Mask};
+use crate::error::{Error, Result, check_range, from_offset};
+use crate::image::{IndexedImage, IndexedAnimation, Mask};
 use crate::decompressor;
+use crate::file_finder;
+use crate::game_variant::GameVariant;
+use crate::pc_speaker::PcSpeakerSounds;
 
 const SKILL_PANEL_WIDTH: usize = 320;
 const SKILL_PANEL_HEIGHT: usize = 40;
+const RATINGS_OFFSET: usize = 0x5A80; // In section 4, after the reel.
+const RATING_WIDTH: usize = 72;
+const RATING_HEIGHT: usize = 27;
+const RATING_SIZE: usize = RATING_WIDTH * RATING_HEIGHT / 2; // 4bpp.
+const MENU_FONT_SIZE: usize = 94 * 16 * 16 * 3 / 8; // 3bpp, after the rating signs.
 
 pub struct MainDat {
     pub lemming_animations: LemmingAnimations,
     pub masks: Masks,
-    pub countdown_numbers: [Image; 10],
-    pub skill_panel_high_perf: Image,
-    #[allow(dead_code)]
+    pub countdown_numbers: [IndexedImage; 10],
+    pub skill_panel_high_perf: IndexedImage,
     pub skill_number_digits: SkillNumberDigits,
-    #[allow(dead_code)]
     pub game_font_high_perf: GameFont,
     pub main_menu: MainMenu,
-    pub skill_panel: Image,
+    pub pc_speaker_sounds: PcSpeakerSounds, // Section 5, the same in every game.
+    pub skill_panel: IndexedImage,
     pub game_font: GameFont,
 }
 
 pub struct MainMenu {
-    pub background: Image,
-    pub logo: Image,
-    pub f1: Image,
-    pub f2: Image,
-    pub f3: Image,
-    pub f4: Image,
-    pub level_rating: Image,
-    pub exit_to_dos: Image,
-    pub music_note: Image,
-    pub fx: Image,
+    pub background: IndexedImage,
+    pub logo: IndexedImage,
+    pub f1: IndexedImage,
+    pub f2: IndexedImage,
+    pub f3: IndexedImage,
+    pub f4: IndexedImage,
+    pub level_rating: IndexedImage,
+    pub exit_to_dos: IndexedImage,
+    pub music_note: IndexedImage,
+    pub fx: IndexedImage,
 
-    pub blink1: Animation,
-    pub blink2: Animation,
-    pub blink3: Animation,
-    pub blink4: Animation,
-    pub blink5: Animation,
-    pub blink6: Animation,
-    pub blink7: Animation,
-    pub left_scroller: Animation,
-    pub right_scroller: Animation,
-    pub reel: Image,
-    // TODO change this to skill indexes so it's not lemmings-1-specific.
-    // TODO change to support 5 skills for oh-no-more.
-    pub mayhem: Image,
-    pub taxing: Image,
-    pub tricky: Image,
-    pub fun: Image,
-    pub menu_font: Animation, // 16x16, 94 frames, '!'(33) - '~'(126), in ascii order. Not really an animation, but this makes texture atlas conversion simpler. 
+    pub blink1: IndexedAnimation,
+    pub blink2: IndexedAnimation,
+    pub blink3: IndexedAnimation,
+    pub blink4: IndexedAnimation,
+    pub blink5: IndexedAnimation,
+    pub blink6: IndexedAnimation,
+    pub blink7: IndexedAnimation,
+    pub left_scroller: IndexedAnimation,
+    pub right_scroller: IndexedAnimation,
+    pub reel: IndexedImage,
+    pub ratings: Vec<IndexedImage>, // Easiest first, eg Fun, Tricky, Taxing, Mayhem. Oh No! More Lemmings has 5.
+    pub menu_font: IndexedAnimation, // 16x16, 94 frames, '!'(33) - '~'(126), in ascii order. Not really an animation, but this makes texture atlas conversion simpler. 
 }
 
 #[derive(Default)]
 pub struct GameFont {
-    pub percent: Image,
-    pub digits: [Image; 10], // 0-9
-    pub dash: Image,
-    pub letters: [Image; 26], // A-Z
+    pub percent: IndexedImage,
+    pub digits: [IndexedImage; 10], // 0-9
+    pub dash: IndexedImage,
+    pub letters: [IndexedImage; 26], // A-Z
 }
 
-#[allow(dead_code)]
 pub struct SkillNumberDigits {
-    pub left: [Image; 10],
-    pub right: [Image; 10],
+    pub left: [IndexedImage; 10],
+    pub right: [IndexedImage; 10],
 }
 
 pub struct LemmingAnimations {
-    pub walking_right: Animation,
-    pub jumping_right: Animation, // Walking up a step 3-6px tall. This is a 1-frame 'animation'.
-    pub walking_left: Animation,
-    pub jumping_left: Animation, // This is a 1-frame 'animation'.
-    pub digging: Animation,
-    pub climbing_right: Animation,
-    pub climbing_left: Animation,
-    pub drowning: Animation,
-    pub post_climb_right: Animation,
-    pub post_climb_left: Animation,
-    pub brick_laying_right: Animation,
-    pub brick_laying_left: Animation,
-    pub bashing_right: Animation,
-    pub bashing_left: Animation,
-    pub mining_right: Animation,
-    pub mining_left: Animation,
-    pub falling_right: Animation,
-    pub falling_left: Animation,
-    pub pre_umbrella_right: Animation,
-    pub umbrella_right: Animation,
-    pub pre_umbrella_left: Animation,
-    pub umbrella_left: Animation,
-    pub splatting: Animation,
-    pub exiting: Animation,
-    pub fried: Animation,
-    pub blocking: Animation,
-    pub shrugging_right: Animation, // Builder running out of bricks.
-    pub shrugging_left: Animation,
-    pub oh_no_ing: Animation,
-    pub explosion: Animation, // 1 frame.
+    pub walking_right: IndexedAnimation,
+    pub jumping_right: IndexedAnimation, // Walking up a step 3-6px tall. This is a 1-frame 'animation'.
+    pub walking_left: IndexedAnimation,
+    pub jumping_left: IndexedAnimation, // This is a 1-frame 'animation'.
+    pub digging: IndexedAnimation,
+    pub climbing_right: IndexedAnimation,
+    pub climbing_left: IndexedAnimation,
+    pub drowning: IndexedAnimation,
+    pub post_climb_right: IndexedAnimation,
+    pub post_climb_left: IndexedAnimation,
+    pub brick_laying_right: IndexedAnimation,
+    pub brick_laying_left: IndexedAnimation,
+    pub bashing_right: IndexedAnimation,
+    pub bashing_left: IndexedAnimation,
+    pub mining_right: IndexedAnimation,
+    pub mining_left: IndexedAnimation,
+    pub falling_right: IndexedAnimation,
+    pub falling_left: IndexedAnimation,
+    pub pre_umbrella_right: IndexedAnimation,
+    pub umbrella_right: IndexedAnimation,
+    pub pre_umbrella_left: IndexedAnimation,
+    pub umbrella_left: IndexedAnimation,
+    pub splatting: IndexedAnimation,
+    pub exiting: IndexedAnimation,
+    pub fried: IndexedAnimation,
+    pub blocking: IndexedAnimation,
+    pub shrugging_right: IndexedAnimation, // Builder running out of bricks.
+    pub shrugging_left: IndexedAnimation,
+    pub oh_no_ing: IndexedAnimation,
+    pub explosion: IndexedAnimation, // 1 frame.
 }
 
 pub struct Masks {
@@ -112,43 +113,44 @@ pub struct Masks {
 impl LemmingAnimations {
     fn parse(data: &[u8], palette: &[u32; 16]) -> Result<LemmingAnimations> {
         Ok(LemmingAnimations {
-            walking_right: Animation::parse(&data[0x0000..], 16, 10, 8, palette, 2),
-            jumping_right: Animation::parse(&data[0x0140..], 16, 10, 1, palette, 2),
-            walking_left: Animation::parse(&data[0x0168..], 16, 10, 8, palette, 2),
-            jumping_left: Animation::parse(&data[0x02A8..], 16, 10, 1, palette, 2),
-            digging: Animation::parse(&data[0x02D0..], 16, 14, 16, palette, 3),
-            climbing_right: Animation::parse(&data[0x0810..], 16, 12, 8, palette, 2),
-            climbing_left: Animation::parse(&data[0x0990..], 16, 12, 8, palette, 2),
-            drowning: Animation::parse(&data[0x0B10..], 16, 10, 16, palette, 2),
-            post_climb_right: Animation::parse(&data[0x0D90..], 16, 12, 8, palette, 2),
-            post_climb_left: Animation::parse(&data[0x0F10..], 16, 12, 8, palette, 2),
-            brick_laying_right: Animation::parse(&data[0x1090..], 16, 13, 16, palette, 3),
-            brick_laying_left: Animation::parse(&data[0x1570..], 16, 13, 16, palette, 3), 
-            bashing_right: Animation::parse(&data[0x1A50..], 16, 10, 32, palette, 3), 
-            bashing_left: Animation::parse(&data[0x21D0..], 16, 10, 32, palette, 3), 
-            mining_right: Animation::parse(&data[0x2950..], 16, 13, 24, palette, 3), 
-            mining_left: Animation::parse(&data[0x30A0..], 16, 13, 24, palette, 3), 
-            falling_right: Animation::parse(&data[0x37F0..], 16, 10, 4, palette, 2), 
-            falling_left: Animation::parse(&data[0x3890..], 16, 10, 4, palette, 2), 
-            pre_umbrella_right: Animation::parse(&data[0x3930..], 16, 16, 4, palette, 3),
-            umbrella_right: Animation::parse(&data[0x3AB0..], 16, 16, 4, palette, 3), 
-            pre_umbrella_left: Animation::parse(&data[0x3C30..], 16, 16, 4, palette, 3), 
-            umbrella_left: Animation::parse(&data[0x3DB0..], 16, 16, 4, palette, 3),
-            splatting: Animation::parse(&data[0x3F30..], 16, 10, 16, palette, 2), 
-            exiting: Animation::parse(&data[0x41B0..], 16, 13, 8, palette, 2), 
-            fried: Animation::parse(&data[0x4350 ..], 16, 14, 14, palette, 4), 
-            blocking: Animation::parse(&data[0x4970..], 16, 10, 16, palette, 2), 
-            shrugging_right: Animation::parse(&data[0x4BF0..], 16, 10, 8, palette, 2), 
-            shrugging_left: Animation::parse(&data[0x4D30..], 16, 10, 8, palette, 2), 
-            oh_no_ing: Animation::parse(&data[0x4E70..], 16, 10, 16, palette, 2), 
-            explosion: Animation::parse(&data[0x50F0..], 32, 32, 1, palette, 3),
+            walking_right: IndexedAnimation::parse(from_offset(data, 0x0000)?, 16, 10, 8, palette, 2)?,
+            jumping_right: IndexedAnimation::parse(from_offset(data, 0x0140)?, 16, 10, 1, palette, 2)?,
+            walking_left: IndexedAnimation::parse(from_offset(data, 0x0168)?, 16, 10, 8, palette, 2)?,
+            jumping_left: IndexedAnimation::parse(from_offset(data, 0x02A8)?, 16, 10, 1, palette, 2)?,
+            digging: IndexedAnimation::parse(from_offset(data, 0x02D0)?, 16, 14, 16, palette, 3)?,
+            climbing_right: IndexedAnimation::parse(from_offset(data, 0x0810)?, 16, 12, 8, palette, 2)?,
+            climbing_left: IndexedAnimation::parse(from_offset(data, 0x0990)?, 16, 12, 8, palette, 2)?,
+            drowning: IndexedAnimation::parse(from_offset(data, 0x0B10)?, 16, 10, 16, palette, 2)?,
+            post_climb_right: IndexedAnimation::parse(from_offset(data, 0x0D90)?, 16, 12, 8, palette, 2)?,
+            post_climb_left: IndexedAnimation::parse(from_offset(data, 0x0F10)?, 16, 12, 8, palette, 2)?,
+            brick_laying_right: IndexedAnimation::parse(from_offset(data, 0x1090)?, 16, 13, 16, palette, 3)?,
+            brick_laying_left: IndexedAnimation::parse(from_offset(data, 0x1570)?, 16, 13, 16, palette, 3)?, 
+            bashing_right: IndexedAnimation::parse(from_offset(data, 0x1A50)?, 16, 10, 32, palette, 3)?, 
+            bashing_left: IndexedAnimation::parse(from_offset(data, 0x21D0)?, 16, 10, 32, palette, 3)?, 
+            mining_right: IndexedAnimation::parse(from_offset(data, 0x2950)?, 16, 13, 24, palette, 3)?, 
+            mining_left: IndexedAnimation::parse(from_offset(data, 0x30A0)?, 16, 13, 24, palette, 3)?, 
+            falling_right: IndexedAnimation::parse(from_offset(data, 0x37F0)?, 16, 10, 4, palette, 2)?, 
+            falling_left: IndexedAnimation::parse(from_offset(data, 0x3890)?, 16, 10, 4, palette, 2)?, 
+            pre_umbrella_right: IndexedAnimation::parse(from_offset(data, 0x3930)?, 16, 16, 4, palette, 3)?,
+            umbrella_right: IndexedAnimation::parse(from_offset(data, 0x3AB0)?, 16, 16, 4, palette, 3)?, 
+            pre_umbrella_left: IndexedAnimation::parse(from_offset(data, 0x3C30)?, 16, 16, 4, palette, 3)?, 
+            umbrella_left: IndexedAnimation::parse(from_offset(data, 0x3DB0)?, 16, 16, 4, palette, 3)?,
+            splatting: IndexedAnimation::parse(from_offset(data, 0x3F30)?, 16, 10, 16, palette, 2)?, 
+            exiting: IndexedAnimation::parse(from_offset(data, 0x41B0)?, 16, 13, 8, palette, 2)?, 
+            fried: IndexedAnimation::parse(from_offset(data, 0x4350)?, 16, 14, 14, palette, 4)?, 
+            blocking: IndexedAnimation::parse(from_offset(data, 0x4970)?, 16, 10, 16, palette, 2)?, 
+            shrugging_right: IndexedAnimation::parse(from_offset(data, 0x4BF0)?, 16, 10, 8, palette, 2)?, 
+            shrugging_left: IndexedAnimation::parse(from_offset(data, 0x4D30)?, 16, 10, 8, palette, 2)?, 
+            oh_no_ing: IndexedAnimation::parse(from_offset(data, 0x4E70)?, 16, 10, 16, palette, 2)?, 
+            explosion: IndexedAnimation::parse(from_offset(data, 0x50F0)?, 32, 32, 1, palette, 3)?,
         })
     }
 }
 
 impl Mask {
-    fn parse(data: &[u8], width: usize, height: usize, frame_count: usize) -> Mask {
+    fn parse(data: &[u8], width: usize, height: usize, frame_count: usize) -> Result<Mask> {
         let pixels = width * height;
+        check_range(data, 0, (pixels * frame_count).div_ceil(8))?;
         let mut frames: Vec<Vec<u8>> = Vec::with_capacity(frame_count);
         for frame_index in 0..frame_count {
             let offset_bits = frame_index * pixels;
@@ -160,120 +162,123 @@ impl Mask {
             }
             frames.push(bitmap);
         }
-        return Mask { frames: frames, width: width, height: height };
+        Ok(Mask { frames, width, height })
     }
 }
 
 impl Masks {
-    fn parse(data: &[u8]) -> Masks {
-        Masks {
-            bash_right: Mask::parse(&data[0x0000..], 16, 10, 4),
-            bash_left:  Mask::parse(&data[0x0050..], 16, 10, 4),
-            mine_right: Mask::parse(&data[0x00a0..], 16, 13, 2),
-            mine_left:  Mask::parse(&data[0x00d4..], 16, 13, 2),
-            explosion:  Mask::parse(&data[0x0108..], 16, 22, 1),
-        }
+    fn parse(data: &[u8]) -> Result<Masks> {
+        Ok(Masks {
+            bash_right: Mask::parse(from_offset(data, 0x0000)?, 16, 10, 4)?,
+            bash_left:  Mask::parse(from_offset(data, 0x0050)?, 16, 10, 4)?,
+            mine_right: Mask::parse(from_offset(data, 0x00a0)?, 16, 13, 2)?,
+            mine_left:  Mask::parse(from_offset(data, 0x00d4)?, 16, 13, 2)?,
+            explosion:  Mask::parse(from_offset(data, 0x0108)?, 16, 22, 1)?,
+        })
     }
 }
 
-fn parse_countdown_numbers(data: &[u8]) -> [Image; 10] {
-    [
-        Image::parse_1bpp(&data[0x017C..], 8, 8),
-        Image::parse_1bpp(&data[0x0174..], 8, 8),
-        Image::parse_1bpp(&data[0x016C..], 8, 8),
-        Image::parse_1bpp(&data[0x0164..], 8, 8),
-        Image::parse_1bpp(&data[0x015C..], 8, 8),
-        Image::parse_1bpp(&data[0x0154..], 8, 8),
-        Image::parse_1bpp(&data[0x014C..], 8, 8),
-        Image::parse_1bpp(&data[0x0144..], 8, 8),
-        Image::parse_1bpp(&data[0x013C..], 8, 8),
-        Image::parse_1bpp(&data[0x0134..], 8, 8),
-    ]
+fn parse_countdown_numbers(data: &[u8]) -> Result<[IndexedImage; 10]> {
+    Ok([
+        IndexedImage::parse_1bpp(from_offset(data, 0x017C)?, 8, 8)?,
+        IndexedImage::parse_1bpp(from_offset(data, 0x0174)?, 8, 8)?,
+        IndexedImage::parse_1bpp(from_offset(data, 0x016C)?, 8, 8)?,
+        IndexedImage::parse_1bpp(from_offset(data, 0x0164)?, 8, 8)?,
+        IndexedImage::parse_1bpp(from_offset(data, 0x015C)?, 8, 8)?,
+        IndexedImage::parse_1bpp(from_offset(data, 0x0154)?, 8, 8)?,
+        IndexedImage::parse_1bpp(from_offset(data, 0x014C)?, 8, 8)?,
+        IndexedImage::parse_1bpp(from_offset(data, 0x0144)?, 8, 8)?,
+        IndexedImage::parse_1bpp(from_offset(data, 0x013C)?, 8, 8)?,
+        IndexedImage::parse_1bpp(from_offset(data, 0x0134)?, 8, 8)?,
+    ])
 }
 
 impl SkillNumberDigits {
-    fn parse(data: &[u8]) -> SkillNumberDigits {
-        SkillNumberDigits {
+    fn parse(data: &[u8]) -> Result<SkillNumberDigits> {
+        Ok(SkillNumberDigits {
             left: [
-                Image::parse_1bpp(&data[0x1908..], 8, 8),
-                Image::parse_1bpp(&data[0x1918..], 8, 8),
-                Image::parse_1bpp(&data[0x1928..], 8, 8),
-                Image::parse_1bpp(&data[0x1938..], 8, 8),
-                Image::parse_1bpp(&data[0x1948..], 8, 8),
-                Image::parse_1bpp(&data[0x1958..], 8, 8),
-                Image::parse_1bpp(&data[0x1968..], 8, 8),
-                Image::parse_1bpp(&data[0x1978..], 8, 8),
-                Image::parse_1bpp(&data[0x1988..], 8, 8),
-                Image::parse_1bpp(&data[0x1998..], 8, 8),
+                IndexedImage::parse_1bpp(from_offset(data, 0x1908)?, 8, 8)?,
+                IndexedImage::parse_1bpp(from_offset(data, 0x1918)?, 8, 8)?,
+                IndexedImage::parse_1bpp(from_offset(data, 0x1928)?, 8, 8)?,
+                IndexedImage::parse_1bpp(from_offset(data, 0x1938)?, 8, 8)?,
+                IndexedImage::parse_1bpp(from_offset(data, 0x1948)?, 8, 8)?,
+                IndexedImage::parse_1bpp(from_offset(data, 0x1958)?, 8, 8)?,
+                IndexedImage::parse_1bpp(from_offset(data, 0x1968)?, 8, 8)?,
+                IndexedImage::parse_1bpp(from_offset(data, 0x1978)?, 8, 8)?,
+                IndexedImage::parse_1bpp(from_offset(data, 0x1988)?, 8, 8)?,
+                IndexedImage::parse_1bpp(from_offset(data, 0x1998)?, 8, 8)?,
             ],
             right: [
-                Image::parse_1bpp(&data[0x1900..], 8, 8),
-                Image::parse_1bpp(&data[0x1910..], 8, 8),
-                Image::parse_1bpp(&data[0x1920..], 8, 8),
-                Image::parse_1bpp(&data[0x1930..], 8, 8),
-                Image::parse_1bpp(&data[0x1940..], 8, 8),
-                Image::parse_1bpp(&data[0x1950..], 8, 8),
-                Image::parse_1bpp(&data[0x1960..], 8, 8),
-                Image::parse_1bpp(&data[0x1970..], 8, 8),
-                Image::parse_1bpp(&data[0x1980..], 8, 8),
-                Image::parse_1bpp(&data[0x1990..], 8, 8),
+                IndexedImage::parse_1bpp(from_offset(data, 0x1900)?, 8, 8)?,
+                IndexedImage::parse_1bpp(from_offset(data, 0x1910)?, 8, 8)?,
+                IndexedImage::parse_1bpp(from_offset(data, 0x1920)?, 8, 8)?,
+                IndexedImage::parse_1bpp(from_offset(data, 0x1930)?, 8, 8)?,
+                IndexedImage::parse_1bpp(from_offset(data, 0x1940)?, 8, 8)?,
+                IndexedImage::parse_1bpp(from_offset(data, 0x1950)?, 8, 8)?,
+                IndexedImage::parse_1bpp(from_offset(data, 0x1960)?, 8, 8)?,
+                IndexedImage::parse_1bpp(from_offset(data, 0x1970)?, 8, 8)?,
+                IndexedImage::parse_1bpp(from_offset(data, 0x1980)?, 8, 8)?,
+                IndexedImage::parse_1bpp(from_offset(data, 0x1990)?, 8, 8)?,
             ]
-        }
+        })
     }
 }
 
 impl GameFont {
-    fn parse(data: &[u8], palette: &[u32; 16]) -> GameFont {
+    fn parse(data: &[u8], palette: &[u32; 16]) -> Result<GameFont> {
         const SIZE_PER_CHAR: usize = 0x30;
         let mut font = GameFont::default();
         let mut offset: usize = 0;
-        font.percent = Image::parse_3bpp(&data[offset..], 8, 16, palette);
+        font.percent = IndexedImage::parse_3bpp(from_offset(data, offset)?, 8, 16, palette)?;
         offset += SIZE_PER_CHAR;
         for i in 0..10 {
-            font.digits[i] = Image::parse_3bpp(&data[offset..], 8, 16, palette);
+            font.digits[i] = IndexedImage::parse_3bpp(from_offset(data, offset)?, 8, 16, palette)?;
             offset += SIZE_PER_CHAR;
         }
-        font.dash = Image::parse_3bpp(&data[offset..], 8, 16, palette);
+        font.dash = IndexedImage::parse_3bpp(from_offset(data, offset)?, 8, 16, palette)?;
         offset += SIZE_PER_CHAR;
         for i in 0..26 {
-            font.letters[i] = Image::parse_3bpp(&data[offset..], 8, 16, palette);
+            font.letters[i] = IndexedImage::parse_3bpp(from_offset(data, offset)?, 8, 16, palette)?;
             offset += SIZE_PER_CHAR;
         }
-        return font;
+        Ok(font)
     }
 }
 
 impl MainMenu {
-    fn parse(section_3: &[u8], section_4: &[u8], palette: &[u32; 16]) -> MainMenu {
-        let mut back_palette = palette.clone(); // Make 0 solid black, not transparent, for the background.
+    fn parse(section_3: &[u8], section_4: &[u8], palette: &[u32; 16], rating_count: usize) -> Result<MainMenu> {
+        let mut back_palette = *palette; // Make 0 solid black, not transparent, for the background.
         back_palette[0] = 0x000000ff;
-        MainMenu {
-            background:     Image::parse_2bpp(&section_3, 320, 104, &back_palette),
-            logo:           Image::parse_4bpp(&section_3[0x2080..], 632, 94, palette),
-            f1:             Image::parse_4bpp(&section_3[0x9488..], 120, 61, palette),
-            f2:             Image::parse_4bpp(&section_3[0xa2d4..], 120, 61, palette),
-            f3:             Image::parse_4bpp(&section_3[0xb120..], 120, 61, palette),
-            f4:             Image::parse_4bpp(&section_3[0xdc04..], 120, 61, palette),
-            level_rating:   Image::parse_4bpp(&section_3[0xbf6c..], 120, 61, palette),
-            exit_to_dos:    Image::parse_4bpp(&section_3[0xCDB8..], 120, 61, palette),
-            music_note:     Image::parse_4bpp(&section_3[0xEA50..], 64, 31, palette),
-            fx:             Image::parse_4bpp(&section_3[0xEE30..], 64, 31, palette),
-            blink1:         Animation::parse(&section_4[0x0000..], 32, 12, 8, palette, 4),
-            blink2:         Animation::parse(&section_4[0x0600..], 32, 12, 8, palette, 4),
-            blink3:         Animation::parse(&section_4[0x0C00..], 32, 12, 8, palette, 4),
-            blink4:         Animation::parse(&section_4[0x1200..], 32, 12, 8, palette, 4),
-            blink5:         Animation::parse(&section_4[0x1800..], 32, 12, 8, palette, 4),
-            blink6:         Animation::parse(&section_4[0x1E00..], 32, 12, 8, palette, 4),
-            blink7:         Animation::parse(&section_4[0x2400..], 32, 12, 8, palette, 4),
-            left_scroller:  Animation::parse(&section_4[0x2A00..], 48, 16, 16, palette, 4),
-            right_scroller: Animation::parse(&section_4[0x4200..], 48, 16, 16, palette, 4),
-            reel:           Image::parse_4bpp(&section_4[0x5A00..], 16, 16, palette),
-            mayhem:         Image::parse_4bpp(&section_4[0x5A80..], 72, 27, &back_palette),
-            taxing:         Image::parse_4bpp(&section_4[0x5E4C..], 72, 27, &back_palette),
-            tricky:         Image::parse_4bpp(&section_4[0x6218..], 72, 27, &back_palette),
-            fun:            Image::parse_4bpp(&section_4[0x65E4..], 72, 27, &back_palette),
-            menu_font:      Animation::parse(&section_4[0x69B0..], 16, 16, 94, palette, 3),
-        }
+        // The rating signs are stored hardest first, followed by the menu font.
+        check_range(section_4, RATINGS_OFFSET, rating_count * RATING_SIZE + MENU_FONT_SIZE)?;
+        let ratings = (0..rating_count).rev().map(|i| {
+            IndexedImage::parse_4bpp(from_offset(section_4, RATINGS_OFFSET + i * RATING_SIZE)?, RATING_WIDTH, RATING_HEIGHT, &back_palette)
+        }).collect::<Result<Vec<IndexedImage>>>()?;
+        let menu_font_offset = RATINGS_OFFSET + rating_count * RATING_SIZE;
+        Ok(MainMenu {
+            background:     IndexedImage::parse_2bpp(section_3, 320, 104, &back_palette)?,
+            logo:           IndexedImage::parse_4bpp(from_offset(section_3, 0x2080)?, 632, 94, palette)?,
+            f1:             IndexedImage::parse_4bpp(from_offset(section_3, 0x9488)?, 120, 61, palette)?,
+            f2:             IndexedImage::parse_4bpp(from_offset(section_3, 0xa2d4)?, 120, 61, palette)?,
+            f3:             IndexedImage::parse_4bpp(from_offset(section_3, 0xb120)?, 120, 61, palette)?,
+            f4:             IndexedImage::parse_4bpp(from_offset(section_3, 0xdc04)?, 120, 61, palette)?,
+            level_rating:   IndexedImage::parse_4bpp(from_offset(section_3, 0xbf6c)?, 120, 61, palette)?,
+            exit_to_dos:    IndexedImage::parse_4bpp(from_offset(section_3, 0xCDB8)?, 120, 61, palette)?,
+            music_note:     IndexedImage::parse_4bpp(from_offset(section_3, 0xEA50)?, 64, 31, palette)?,
+            fx:             IndexedImage::parse_4bpp(from_offset(section_3, 0xEE30)?, 64, 31, palette)?,
+            blink1:         IndexedAnimation::parse(from_offset(section_4, 0x0000)?, 32, 12, 8, palette, 4)?,
+            blink2:         IndexedAnimation::parse(from_offset(section_4, 0x0600)?, 32, 12, 8, palette, 4)?,
+            blink3:         IndexedAnimation::parse(from_offset(section_4, 0x0C00)?, 32, 12, 8, palette, 4)?,
+            blink4:         IndexedAnimation::parse(from_offset(section_4, 0x1200)?, 32, 12, 8, palette, 4)?,
+            blink5:         IndexedAnimation::parse(from_offset(section_4, 0x1800)?, 32, 12, 8, palette, 4)?,
+            blink6:         IndexedAnimation::parse(from_offset(section_4, 0x1E00)?, 32, 12, 8, palette, 4)?,
+            blink7:         IndexedAnimation::parse(from_offset(section_4, 0x2400)?, 32, 12, 8, palette, 4)?,
+            left_scroller:  IndexedAnimation::parse(from_offset(section_4, 0x2A00)?, 48, 16, 16, palette, 4)?,
+            right_scroller: IndexedAnimation::parse(from_offset(section_4, 0x4200)?, 48, 16, 16, palette, 4)?,
+            reel:           IndexedImage::parse_4bpp(from_offset(section_4, 0x5A00)?, 16, 16, palette)?,
+            ratings,
+            menu_font:      IndexedAnimation::parse(from_offset(section_4, menu_font_offset)?, 16, 16, 94, palette, 3)?,
+        })
     }
 }
 
@@ -282,9 +287,9 @@ macro_rules! rgba_from_rgb { ($r:expr, $g:expr, $b:expr) => {
 }}
 
 impl MainDat {
-    fn parse(sections: &Vec<Vec<u8>>) -> Result<MainDat> {
+    fn parse(sections: &[Vec<u8>], variant: GameVariant) -> Result<MainDat> {
         if sections.len() < 7 {
-            bail!("Not enough sections");
+            return Err(Error::invalid(format!("expected 7 sections but got {}", sections.len())));
         }
 
         let menu_palette: [u32; 16] = [
@@ -315,20 +320,51 @@ impl MainDat {
 
         Ok(MainDat {
             lemming_animations: LemmingAnimations::parse(&sections[0], &game_palette)?,
-            masks: Masks::parse(&sections[1]),
-            countdown_numbers: parse_countdown_numbers(&sections[1]),
-            skill_panel_high_perf: Image::parse_4bpp(&sections[2], SKILL_PANEL_WIDTH, SKILL_PANEL_HEIGHT, &game_palette),
-            skill_number_digits: SkillNumberDigits::parse(&sections[2]),
-            game_font_high_perf: GameFont::parse(&sections[2][0x19a0..], &game_palette),
-            main_menu: MainMenu::parse(&sections[3], &sections[4], &menu_palette),
-            skill_panel: Image::parse_4bpp(&sections[6], SKILL_PANEL_WIDTH, SKILL_PANEL_HEIGHT, &game_palette),
-            game_font: GameFont::parse(&sections[6][0x1900..], &game_palette),
+            masks: Masks::parse(&sections[1])?,
+            countdown_numbers: parse_countdown_numbers(&sections[1])?,
+            skill_panel_high_perf: IndexedImage::parse_4bpp(&sections[2], SKILL_PANEL_WIDTH, SKILL_PANEL_HEIGHT, &game_palette)?,
+            skill_number_digits: SkillNumberDigits::parse(&sections[2])?,
+            game_font_high_perf: GameFont::parse(from_offset(&sections[2], 0x19a0)?, &game_palette)?,
+            main_menu: MainMenu::parse(&sections[3], &sections[4], &menu_palette, variant.rating_signs())?,
+            pc_speaker_sounds: PcSpeakerSounds::parse(&sections[5])?,
+            skill_panel: IndexedImage::parse_4bpp(&sections[6], SKILL_PANEL_WIDTH, SKILL_PANEL_HEIGHT, &game_palette)?,
+            game_font: GameFont::parse(from_offset(&sections[6], 0x1900)?, &game_palette)?,
         })
     }
 
-    pub fn load(dir: &str) -> Result<MainDat> {
-        let file: Vec<u8> = std::fs::read(format!("{}/main.dat", dir))?;
-        let sections = decompressor::decompress(&file);
-        Self::parse(&sections)
+    pub fn load(files: &file_finder::DataFiles, variant: GameVariant) -> Result<MainDat> {
+        let Some(file) = files.get("main.dat") else { return Err(Error::MissingFile("MAIN.DAT".to_string())) };
+        let sections = decompressor::decompress(&file.name, &file.data)?;
+        Self::parse(&sections, variant).map_err(|e| e.in_file(&file.name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rating_count() {
+        for (folder, count) in [("data/lemmings", 4), ("data/ohnomore", 5), ("data/holidays94", 4)] {
+            let files = file_finder::DataFiles::from_folder(folder).unwrap();
+            let maindat = MainDat::load(&files, GameVariant::detect(&files).unwrap()).unwrap();
+            assert_eq!(maindat.main_menu.ratings.len(), count, "{}", folder);
+        }
+    }
+
+    #[test]
+    fn test_truncated_section() {
+        let mut sections = decompressor::decompress("MAIN.DAT", &std::fs::read("data/lemmings/MAIN.DAT").unwrap()).unwrap();
+        sections[3].truncate(0x9488);
+        let Err(Error::OutOfRange { length, available, .. }) = MainDat::parse(&sections, GameVariant::Lemmings) else { panic!() };
+        assert_eq!((length, available), (120 * 61 / 2, 0)); // F1 sign.
+    }
+
+    #[test]
+    fn test_short_rating_signs() {
+        // Oh No! More Lemmings' 5 signs don't fit in the original's section 4.
+        let sections = decompressor::decompress("MAIN.DAT", &std::fs::read("data/lemmings/MAIN.DAT").unwrap()).unwrap();
+        let Err(Error::OutOfRange { offset, length, available, .. }) = MainDat::parse(&sections, GameVariant::OhNoMoreLemmings) else { panic!() };
+        assert_eq!((offset, length, available), (RATINGS_OFFSET, 5 * RATING_SIZE + MENU_FONT_SIZE, sections[4].len()));
     }
 }
@@ -0,0 +1,93 @@
+// ODDTABLE.DAT holds alternate globals and names for the levels that reuse another level's layout.
+// It is not compressed: 80 entries of 56 bytes, one per base level (LEVEL000 section 0 is entry 0,
+// LEVEL001 section 0 is entry 8, etc). Each entry is 12 big-endian words (release rate, lemmings,
+// to rescue, time limit, 8 skills) then a 32 byte name. Unused entries are all 99s.
+
+use crate::file_finder;
+use crate::level;
+use crate::error::{Error, Result};
+
+const ENTRY_SIZE: usize = 56;
+const NAME_OFFSET: usize = 24;
+const UNUSED_NAME: &str = "This is a non-used duplicate";
+
+#[derive(Debug, Clone)]
+pub struct OddLevel {
+    pub entry: usize, // Also the index of the base level whose layout is reused.
+    pub release_rate: u16,
+    pub num_of_lemmings: u16,
+    pub num_to_rescue: u16,
+    pub time_limit: u16,
+    pub skills: level::Skills,
+    pub name: String, // As stored, padded with spaces to 32 characters.
+}
+
+impl OddLevel {
+    // Combines these globals and name with the base level's layout.
+    pub fn apply(&self, base: &level::Level) -> level::Level {
+        let mut level = base.clone();
+        level.globals.release_rate = self.release_rate;
+        level.globals.num_of_lemmings = self.num_of_lemmings;
+        level.globals.num_to_rescue = self.num_to_rescue;
+        level.globals.time_limit = self.time_limit;
+        level.globals.skills = self.skills.clone();
+        level.name = self.name.clone();
+        level
+    }
+}
+
+fn word(data: &[u8], index: usize) -> u16 {
+    ((data[index * 2] as u16) << 8) + (data[index * 2 + 1] as u16)
+}
+
+// Returns the used entries only.
+pub fn parse(data: &[u8]) -> Result<Vec<OddLevel>> {
+    if !data.len().is_multiple_of(ENTRY_SIZE) {
+        return Err(Error::invalid(format!("length {} isn't a multiple of {}", data.len(), ENTRY_SIZE)));
+    }
+    let mut all: Vec<OddLevel> = Vec::new();
+    for (entry, chunk) in data.chunks(ENTRY_SIZE).enumerate() {
+        let Ok(name) = String::from_utf8(chunk[NAME_OFFSET..].to_vec()) else { return Err(Error::invalid(format!("bad name in entry {}", entry))) };
+        if name.trim() == UNUSED_NAME { continue }
+        all.push(OddLevel {
+            entry,
+            release_rate: word(chunk, 0),
+            num_of_lemmings: word(chunk, 1),
+            num_to_rescue: word(chunk, 2),
+            time_limit: word(chunk, 3),
+            skills: level::Skills {
+                climbers: word(chunk, 4),
+                floaters: word(chunk, 5),
+                bombers: word(chunk, 6),
+                blockers: word(chunk, 7),
+                builders: word(chunk, 8),
+                bashers: word(chunk, 9),
+                miners: word(chunk, 10),
+                diggers: word(chunk, 11),
+            },
+            name,
+        });
+    }
+    Ok(all)
+}
+
+// Only the original Lemmings has an oddtable, so this is empty for the other games.
+pub fn load(files: &file_finder::DataFiles) -> Result<Vec<OddLevel>> {
+    let Some(file) = files.get("oddtable.dat") else { return Ok(Vec::new()) };
+    parse(&file.data).map_err(|e| e.in_file(&file.name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bundled_oddtable() {
+        let odd = load(&file_finder::DataFiles::from_folder("data/lemmings").unwrap()).unwrap();
+        assert_eq!(odd.len(), 40);
+        let entry = odd.iter().find(|o| o.entry == 14).unwrap();
+        assert_eq!(entry.name.trim(), "We all fall down");
+        assert_eq!((entry.release_rate, entry.num_of_lemmings, entry.skills.diggers), (1, 40, 40));
+        assert!(load(&file_finder::DataFiles::from_folder("data/ohnomore").unwrap()).unwrap().is_empty());
+    }
+}
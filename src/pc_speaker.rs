@@ -0,0 +1,146 @@
+// MAIN.DAT section 5: the sound effects used when the PC speaker is chosen for sound, and the
+// x86 driver that plays them. It's identical in every game. The game calls the driver through an
+// interrupt with a function number in AH: 0 plays one tick, 1 stops, 3 starts the effect in AL.
+// Each tick it works out a timer divisor (the tone is 1193182 / divisor Hz), and writes it to
+// ports 0x43 and 0x42, then switches the speaker on through port 0x61.
+// Layout: the driver's variables and jump table, 23 effects of 15 bytes, then the code.
+
+use crate::error::{Result, check_range};
+use crate::reader::Reader;
+
+const EFFECTS_OFFSET: usize = 0x28; // The start function reads effect AL from here.
+const EFFECT_SIZE: usize = 15;
+const EFFECT_COUNT: usize = 23; // As many as fit before the code.
+const CODE_OFFSET: usize = EFFECTS_OFFSET + EFFECT_COUNT * EFFECT_SIZE;
+
+pub struct PcSpeakerSounds {
+    pub variables: Vec<u8>, // The driver's state and its jump table, which it works on in place.
+    pub effects: Vec<SoundEffect>,
+    pub code: Vec<u8>, // The driver itself, and whatever follows it to the end of the section.
+}
+
+// The effect's fields as the driver reads them, in the order they're stored.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SoundEffect {
+    pub slide: i16, // Added to the divisor every tick.
+    pub divisor: u16, // Where the divisor starts.
+    pub restart_divisor: u16, // The divisor is set back to this every restart_ticks.
+    pub step_set: i16, // Added every step_ticks when bit 0 of the step pattern is 1.
+    pub step_clear: i16, // Or when it's 0.
+    pub step_pattern: u8, // Rotated right at each step.
+    pub mute_pattern: u8, // Rotated right every tick. Bit 0 being 1 mutes that tick.
+    pub ticks: u8, // The last tick silences the speaker, so 10 plays 9 tones.
+    pub restart_ticks: u8, // The counters are decremented before they're checked, so 0 means 256.
+    pub step_ticks: u8,
+}
+
+impl PcSpeakerSounds {
+    pub fn parse(data: &[u8]) -> Result<PcSpeakerSounds> {
+        check_range(data, 0, CODE_OFFSET)?;
+        let mut reader = Reader::new(&data[EFFECTS_OFFSET..CODE_OFFSET]);
+        let mut effects = Vec::with_capacity(EFFECT_COUNT);
+        for _ in 0..EFFECT_COUNT {
+            effects.push(SoundEffect {
+                slide: reader.u16_le()? as i16,
+                divisor: reader.u16_le()?,
+                restart_divisor: reader.u16_le()?,
+                step_set: reader.u16_le()? as i16,
+                step_clear: reader.u16_le()? as i16,
+                step_pattern: reader.u8()?,
+                mute_pattern: reader.u8()?,
+                ticks: reader.u8()?,
+                restart_ticks: reader.u8()?,
+                step_ticks: reader.u8()?,
+            });
+        }
+        Ok(PcSpeakerSounds {
+            variables: data[..EFFECTS_OFFSET].to_vec(),
+            effects,
+            code: data[CODE_OFFSET..].to_vec(),
+        })
+    }
+
+    // The section as it was read.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = self.variables.clone();
+        for effect in &self.effects {
+            data.extend_from_slice(&(effect.slide as u16).to_le_bytes());
+            data.extend_from_slice(&effect.divisor.to_le_bytes());
+            data.extend_from_slice(&effect.restart_divisor.to_le_bytes());
+            data.extend_from_slice(&(effect.step_set as u16).to_le_bytes());
+            data.extend_from_slice(&(effect.step_clear as u16).to_le_bytes());
+            data.extend_from_slice(&[effect.step_pattern, effect.mute_pattern, effect.ticks, effect.restart_ticks, effect.step_ticks]);
+        }
+        data.extend_from_slice(&self.code);
+        data
+    }
+
+    // One line per effect, eg for output_main_pc_speaker_sounds.txt.
+    pub fn describe(&self) -> String {
+        self.effects.iter().enumerate().map(|(i, effect)| {
+            let tones: Vec<String> = effect.divisors().iter().map(|d| match d {
+                Some(divisor) => divisor.to_string(),
+                None => "-".to_string(),
+            }).collect();
+            format!("Effect {}: {}\n", i, tones.join(" "))
+        }).collect()
+    }
+}
+
+impl SoundEffect {
+    // The timer divisor the driver plays on each tick, or None where the tick is muted.
+    pub fn divisors(&self) -> Vec<Option<u16>> {
+        let mut divisor = self.divisor;
+        let mut step_pattern = self.step_pattern;
+        let mut mute_pattern = self.mute_pattern;
+        let mut step_countdown = self.step_ticks;
+        let mut restart_countdown = self.restart_ticks;
+        let mut tones = Vec::new();
+        for _ in 0..self.ticks.wrapping_sub(1) {
+            step_countdown = step_countdown.wrapping_sub(1);
+            if step_countdown == 0 {
+                step_countdown = self.step_ticks;
+                let step = if step_pattern & 1 == 1 { self.step_set } else { self.step_clear };
+                step_pattern = step_pattern.rotate_right(1);
+                divisor = divisor.wrapping_add(step as u16);
+            }
+            divisor = divisor.wrapping_add(self.slide as u16);
+            restart_countdown = restart_countdown.wrapping_sub(1);
+            if restart_countdown == 0 {
+                restart_countdown = self.restart_ticks;
+                divisor = self.restart_divisor;
+            }
+            let muted = mute_pattern & 1 == 1;
+            mute_pattern = mute_pattern.rotate_right(1);
+            tones.push(if muted { None } else { Some(divisor) });
+        }
+        tones
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decompressor;
+
+    #[test]
+    fn test_bundled_sounds() {
+        let sections = decompressor::decompress("MAIN.DAT", &std::fs::read("data/lemmings/MAIN.DAT").unwrap()).unwrap();
+        let sounds = PcSpeakerSounds::parse(&sections[5]).unwrap();
+        assert_eq!(sounds.effects.len(), 23);
+        assert_eq!(sounds.effects[0], SoundEffect {
+            slide: 800, divisor: 3200, restart_divisor: 3200, step_set: 320, step_clear: -320,
+            step_pattern: 0x55, mute_pattern: 0, ticks: 10, restart_ticks: 0, step_ticks: 1,
+        });
+        assert_eq!(sounds.effects[0].divisors()[..3], [Some(4320), Some(4800), Some(5920)]);
+        assert_eq!(sounds.effects[0].divisors().len(), 9);
+        assert_eq!(sounds.effects[22].slide, 624);
+        // The driver's programming of the timer and speaker, from offset 568.
+        assert_eq!(sounds.code[568 - CODE_OFFSET..588 - CODE_OFFSET], [
+            0xb0, 0xb6, 0xe6, 0x43, 0x8a, 0xc1, 0xe6, 0x42, 0x8a, 0xc5,
+            0xe6, 0x42, 0x89, 0x0e, 0x1a, 0x00, 0xe4, 0x61, 0x0c, 0x03,
+        ]);
+        assert_eq!(sounds.to_bytes(), sections[5]);
+        assert!(PcSpeakerSounds::parse(&sections[5][..CODE_OFFSET - 1]).is_err());
+    }
+}
@@ -1,79 +1,78 @@
-// This file contains enough code to write a very naive PNG format without needing
-// a massive tree of dependencies.
-
-// Converts into an RFC1951 'raw deflate stream' format in a simple non-compressing way.
-// An uncompressed deflate stream looks like N blocks, each being:
-// [is_final, 2 bytes length, 2 bytes length 1's complement, data].
-// Motivation: Avoid dependencies, simple implementation, meet requirements of PNG storage.
-// See: https://datatracker.ietf.org/doc/html/rfc1951
-// Test output with: ruby -rzlib -e 'print Zlib::Inflate.new(-15).inflate(STDIN.read)' < foo.deflateStream
-// https://yob.id.au/2020/06/16/zlib-gzip-and-deflate-in-ruby.html
-fn to_deflate_stream(input: &[u8]) -> Vec<u8> {
-    if input.is_empty() {
-        return vec![1, 0, 0, 0xff, 0xff]; // 1 block with no content.
-    }
-    let mut output = Vec::<u8>::new();
-    let chunks = input.chunks(0xffff);
-    let final_index = chunks.len() - 1;
-    for (index, chunk) in chunks.enumerate() {
-        let is_final = index == final_index;
-        output.push(if is_final { 1 } else { 0 });
-        let len = chunk.len();
-        let len_lsb = (len & 0xff) as u8;
-        let len_msb = (len >> 8) as u8;
-        output.push(len_lsb); // Max len.
-        output.push(len_msb);
-        output.push(!len_lsb); // 1's complement of len.
-        output.push(!len_msb);
-        output.extend_from_slice(chunk);
-    }
-    output
-}
+// This file contains enough code to write (and read back) a PNG without needing a massive tree of dependencies.
 
-// Converts to an uncompressed RFC1950 zlib stream.
+use crate::deflate;
+use crate::error::{Error, Result};
+use crate::image::{Animation, Image};
+use crate::inflate;
+pub use crate::deflate::Compression;
+
+// Converts to an RFC1950 zlib stream, which is a header, the RFC1951 deflate stream and a checksum.
 // See: https://datatracker.ietf.org/doc/html/rfc1950
-// In the simple uncompressed case, this boils down to adding a header and checksum.
 // Test output with: ruby -rzlib -e 'print Zlib::Inflate.new.inflate(STDIN.read)' < foo.zlib
-fn to_zlib_stream(input: &[u8]) -> Vec<u8> {
+fn to_zlib_stream(input: &[u8], compression: Compression) -> Vec<u8> {
     // Header.
     let mut output = Vec::<u8>::new();
     output.push(0x78); // CMF byte. Bits 0-3=method, 4-7=info/window size. Method=8, Window size=7.
-    output.push(1); // FLG byte. Bits 0-4=fcheck, 5=fdict which we dont want so 0, 6-7=flevel where 0 means fastest.
+    // FLG byte. Bits 0-4=fcheck, 5=fdict which we dont want so 0, 6-7=flevel where 0 means fastest.
+    output.push(match compression {
+        Compression::Stored => 0x01,
+        Compression::Fast => 0x5e,
+        Compression::Default => 0x9c,
+        Compression::Best => 0xda,
+    });
 
     // Body.
-    let deflated = to_deflate_stream(input);
+    let deflated = deflate::compress(input, compression);
     output.extend(deflated);
 
     // Checksum.
-    // See: https://en.wikipedia.org/wiki/Adler-32#Example_implementation
+    append_msb(&mut output, adler32(input));
+
+    output
+}
+
+// See: https://en.wikipedia.org/wiki/Adler-32#Example_implementation
+fn adler32(input: &[u8]) -> u32 {
     let mut a: u32 = 1;
     let mut b: u32 = 0;
     for data in input {
         a = (a + (*data as u32)) % 65521;
         b = (b + a) % 65521;
     }
-    output.push((b >> 8) as u8);
-    output.push((b & 0xff) as u8);
-    output.push((a >> 8) as u8);
-    output.push((a & 0xff) as u8);
+    (b << 16) | a
+}
 
-    output
+// The reverse of to_zlib_stream, checking the header and checksum.
+fn from_zlib_stream(input: &[u8]) -> Result<Vec<u8>> {
+    let [cmf, flg, ..] = input else { return Err(Error::invalid("zlib stream is too short")) };
+    if cmf & 0x0f != 8 || !((*cmf as u16) << 8 | *flg as u16).is_multiple_of(31) || flg & 0x20 != 0 {
+        return Err(Error::invalid(format!("unsupported zlib header 0x{:02x}{:02x}", cmf, flg)));
+    }
+    let output = inflate::decompress(&input[2..])?;
+    let Some(checksum) = input.len().checked_sub(4).map(|start| &input[start..]) else {
+        return Err(Error::invalid("zlib stream is too short"));
+    };
+    let expected = u32::from_be_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]);
+    if adler32(&output) != expected {
+        return Err(Error::invalid("zlib checksum mismatch"));
+    }
+    Ok(output)
 }
 
 // http://libpng.org/pub/png/spec/1.0/PNG-CRCAppendix.html
 fn crc(data: &[u8]) -> u32 {
     // Make the CRC table first.
     let mut crc_table: [u32; 256] = [0; 256];
-    for n in 0..256 {
+    for (n, entry) in crc_table.iter_mut().enumerate() {
         let mut c: u32 = n as u32;
         for _k in 0..8 {
             if c & 1 == 1 {
                 c = 0xedb88320u32 ^ (c >> 1);
             } else {
-                c = c >> 1;
+                c >>= 1;
             }
         }
-        crc_table[n] = c;
+        *entry = c;
     }
 
     // Calculate the CRC.
@@ -97,198 +96,623 @@ fn append_msb_u16(vec: &mut Vec<u8>, value: u16) {
     vec.push((value & 0xff) as u8);
 }
 
-// https://en.wikipedia.org/wiki/Portable_Network_Graphics#File_format
-pub fn png_data(width: u32, height: u32, image_data: &[u32]) -> Vec<u8> {
-    let mut output = Vec::<u8>::new();
+// Prepends each row with a filter type byte, then compresses it for an IDAT/fdAT chunk.
+// When compressing, each row uses whichever filter gives the smallest sum of absolute differences,
+// as suggested by the PNG spec: http://libpng.org/pub/png/spec/1.2/PNG-Encoders.html#E.Filter-selection
+fn compressed_image_data(rows: &[u8], row_bytes: usize, bytes_per_pixel: usize, compression: Compression) -> Vec<u8> {
+    let mut filtered = Vec::<u8>::with_capacity(rows.len() + rows.len() / row_bytes.max(1));
+    let empty_row = vec![0u8; row_bytes];
+    let mut previous: &[u8] = &empty_row;
+    for row in rows.chunks(row_bytes.max(1)) {
+        if compression == Compression::Stored {
+            filtered.push(0); // None.
+            filtered.extend_from_slice(row);
+        } else {
+            let best = (0..5u8)
+                .map(|filter| (filter, filter_row(filter, row, previous, bytes_per_pixel)))
+                .min_by_key(|(_, bytes)| bytes.iter().map(|b| (*b as i8).unsigned_abs() as usize).sum::<usize>())
+                .unwrap();
+            filtered.push(best.0);
+            filtered.extend(best.1);
+        }
+        previous = row;
+    }
+    to_zlib_stream(&filtered, compression)
+}
 
-    // Header.
-    output.push(0x89);
-    output.push(b'P');
-    output.push(b'N');
-    output.push(b'G');
-    output.push(0x0d); // Cr
-    output.push(0x0a); // Lf
-    output.push(0x1a); // Eof
-    output.push(0x0a); // Lf
-
-    // Build IHDR.
-    let mut ihdr_type_and_data = Vec::<u8>::new();
-    ihdr_type_and_data.push(b'I');
-    ihdr_type_and_data.push(b'H');
-    ihdr_type_and_data.push(b'D');
-    ihdr_type_and_data.push(b'R');
-    append_msb(&mut ihdr_type_and_data, width);
-    append_msb(&mut ihdr_type_and_data, height);
-    ihdr_type_and_data.push(8); // 8bpp.
-    ihdr_type_and_data.push(6); // RGBA.
-    ihdr_type_and_data.push(0); // Compression method: zlib.
-    ihdr_type_and_data.push(0); // Filter method.
-    ihdr_type_and_data.push(0); // No interlace.
-    let ihdr_len = ihdr_type_and_data.len() - 4; // Minus the type.
-    let ihdr_crc = crc(&ihdr_type_and_data);
-    // Append IHDR to output.
-    append_msb(&mut output, ihdr_len as u32);
-    output.extend_from_slice(&ihdr_type_and_data);
-    append_msb(&mut output, ihdr_crc);
-
-    // Build image data.
-    // Left-right, then Top-bottom.
-    // Each line is prepended a filter type byte (0).
-    let mut idat_data = Vec::<u8>::new();
+// Filter types: 0=None, 1=Sub, 2=Up, 3=Average, 4=Paeth.
+// See: http://libpng.org/pub/png/spec/1.2/PNG-Filters.html
+fn filter_row(filter: u8, row: &[u8], previous: &[u8], bytes_per_pixel: usize) -> Vec<u8> {
+    row.iter().enumerate().map(|(i, x)| {
+        let a = if i >= bytes_per_pixel { row[i - bytes_per_pixel] } else { 0 }; // Left.
+        let b = previous[i]; // Above.
+        let c = if i >= bytes_per_pixel { previous[i - bytes_per_pixel] } else { 0 }; // Above left.
+        let predictor = match filter {
+            0 => 0,
+            1 => a,
+            2 => b,
+            3 => ((a as u16 + b as u16) / 2) as u8,
+            _ => paeth(a, b, c),
+        };
+        x.wrapping_sub(predictor)
+    }).collect()
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+    if pa <= pb && pa <= pc { a } else if pb <= pc { b } else { c }
+}
+
+// Left-right, then top-bottom, 4 bytes per pixel.
+fn rgba_rows(width: u32, height: u32, image_data: &[u32]) -> Vec<u8> {
+    let mut rows = Vec::<u8>::with_capacity(image_data.len() * 4);
     let mut data_iter = image_data.iter();
     for _y in 0..height {
-        idat_data.push(0); // Filter.
         for _x in 0..width {
-            append_msb(&mut idat_data, *data_iter.next().unwrap());
+            append_msb(&mut rows, *data_iter.next().unwrap());
+        }
+    }
+    rows
+}
+
+// Smallest bit depth that can index every palette entry.
+fn indexed_bit_depth(palette: &[u32]) -> u8 {
+    match palette.len() {
+        0..=2 => 1,
+        3..=4 => 2,
+        5..=16 => 4,
+        _ => 8,
+    }
+}
+
+// Packs the indices into rows, leftmost pixel in the most significant bits.
+// Each row starts on a byte boundary: http://libpng.org/pub/png/spec/1.2/PNG-DataRep.html#DR.Image-layout
+fn indexed_rows(width: u32, height: u32, indices: &[u8], bit_depth: u8) -> Vec<u8> {
+    let row_bytes = indexed_row_bytes(width, bit_depth);
+    let pixels_per_byte = 8 / bit_depth as usize;
+    let mut rows = vec![0u8; row_bytes * height as usize];
+    for (y, row) in indices.chunks(width.max(1) as usize).take(height as usize).enumerate() {
+        for (x, index) in row.iter().enumerate() {
+            let shift = 8 - bit_depth as usize * (x % pixels_per_byte + 1);
+            rows[y * row_bytes + x / pixels_per_byte] |= index << shift;
+        }
+    }
+    rows
+}
+
+fn indexed_row_bytes(width: u32, bit_depth: u8) -> usize {
+    (width as usize * bit_depth as usize).div_ceil(8)
+}
+
+// How the pixels are stored. Indexed images have a palette (PLTE) and the alpha of each entry (tRNS).
+enum Format<'a> {
+    Rgba,
+    Indexed { palette: &'a [u32], bit_depth: u8 },
+}
+
+// Appends length, type, data and the CRC of the type and data.
+fn append_chunk(output: &mut Vec<u8>, chunk_type: &[u8; 4], data: &[u8]) {
+    let mut type_and_data = chunk_type.to_vec();
+    type_and_data.extend_from_slice(data);
+    append_msb(output, data.len() as u32);
+    output.extend_from_slice(&type_and_data);
+    append_msb(output, crc(&type_and_data));
+}
+
+// The signature, IHDR and (if indexed) PLTE and tRNS chunks.
+fn header(width: u32, height: u32, format: &Format) -> Vec<u8> {
+    // Header: 0x89, PNG, Cr, Lf, Eof, Lf.
+    let mut output: Vec<u8> = vec![0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+
+    let mut ihdr = Vec::<u8>::new();
+    append_msb(&mut ihdr, width);
+    append_msb(&mut ihdr, height);
+    match format {
+        Format::Rgba => {
+            ihdr.push(8); // 8bpp.
+            ihdr.push(6); // RGBA.
+        },
+        Format::Indexed { bit_depth, .. } => {
+            ihdr.push(*bit_depth);
+            ihdr.push(3); // Palette.
+        },
+    }
+    ihdr.push(0); // Compression method: zlib.
+    ihdr.push(0); // Filter method.
+    ihdr.push(0); // No interlace.
+    append_chunk(&mut output, b"IHDR", &ihdr);
+
+    if let Format::Indexed { palette, .. } = format {
+        // http://libpng.org/pub/png/spec/1.2/PNG-Chunks.html#C.PLTE
+        let plte: Vec<u8> = palette.iter().flat_map(|c| [(c >> 24) as u8, (c >> 16) as u8, (c >> 8) as u8]).collect();
+        append_chunk(&mut output, b"PLTE", &plte);
+        // tRNS only needs to go up to the last entry that isn't opaque.
+        // http://libpng.org/pub/png/spec/1.2/PNG-Chunks.html#C.tRNS
+        let mut trns: Vec<u8> = palette.iter().map(|c| *c as u8).collect();
+        while trns.last() == Some(&0xff) {
+            trns.pop();
+        }
+        if !trns.is_empty() {
+            append_chunk(&mut output, b"tRNS", &trns);
         }
     }
-    let compressed_idat_data = to_zlib_stream(&idat_data);
-
-    // Build IDAT.
-    let mut idat_type_and_data = Vec::<u8>::new();
-    idat_type_and_data.push(b'I');
-    idat_type_and_data.push(b'D');
-    idat_type_and_data.push(b'A');
-    idat_type_and_data.push(b'T');
-    idat_type_and_data.extend_from_slice(&compressed_idat_data);
-    let idat_len = idat_type_and_data.len() - 4; // Minus the type.
-    let idat_crc = crc(&idat_type_and_data);
-    // Append IDAT to output.
-    append_msb(&mut output, idat_len as u32);
-    output.extend_from_slice(&idat_type_and_data);
-    append_msb(&mut output, idat_crc);
-
-    // IEND (no data).
-    append_msb(&mut output, 0); // Length.
-    output.push(b'I'); // Type.
-    output.push(b'E');
-    output.push(b'N');
-    output.push(b'D');
-    let iend_crc = crc(b"IEND");
-    append_msb(&mut output, iend_crc);
+    output
+}
+
+fn append_iend(output: &mut Vec<u8>) {
+    append_chunk(output, b"IEND", &[]);
+}
 
+// https://en.wikipedia.org/wiki/Portable_Network_Graphics#File_format
+fn encode_png(width: u32, height: u32, format: &Format, rows: &[u8], row_bytes: usize, bytes_per_pixel: usize, compression: Compression) -> Vec<u8> {
+    let mut output = header(width, height, format);
+    append_chunk(&mut output, b"IDAT", &compressed_image_data(rows, row_bytes, bytes_per_pixel, compression));
+    append_iend(&mut output);
     output
 }
 
 // https://en.wikipedia.org/wiki/APNG#File_format
-pub fn apng_data(width: u32, height: u32, frames: &[Vec<u32>]) -> Vec<u8> {
-    let mut output = Vec::<u8>::new();
+fn encode_apng(width: u32, height: u32, format: &Format, frames: &[Vec<u8>], row_bytes: usize, bytes_per_pixel: usize, compression: Compression) -> Vec<u8> {
+    let mut output = apng_header(width, height, format, frames.len());
+    for (index, rows) in frames.iter().enumerate() {
+        append_apng_frame(&mut output, index, width, height, rows, row_bytes, bytes_per_pixel, compression);
+    }
+    append_iend(&mut output);
+    output
+}
 
-    // Header.
-    output.push(0x89);
-    output.push(b'P');
-    output.push(b'N');
-    output.push(b'G');
-    output.push(0x0d); // Cr
-    output.push(0x0a); // Lf
-    output.push(0x1a); // Eof
-    output.push(0x0a); // Lf
-
-    // Build IHDR.
-    let mut ihdr_type_and_data = Vec::<u8>::new();
-    ihdr_type_and_data.push(b'I');
-    ihdr_type_and_data.push(b'H');
-    ihdr_type_and_data.push(b'D');
-    ihdr_type_and_data.push(b'R');
-    append_msb(&mut ihdr_type_and_data, width);
-    append_msb(&mut ihdr_type_and_data, height);
-    ihdr_type_and_data.push(8); // 8bpp.
-    ihdr_type_and_data.push(6); // RGBA.
-    ihdr_type_and_data.push(0); // Compression method: zlib.
-    ihdr_type_and_data.push(0); // Filter method.
-    ihdr_type_and_data.push(0); // No interlace.
-    let ihdr_len = ihdr_type_and_data.len() - 4; // Minus the type.
-    let ihdr_crc = crc(&ihdr_type_and_data);
-    // Append IHDR to output.
-    append_msb(&mut output, ihdr_len as u32);
-    output.extend_from_slice(&ihdr_type_and_data);
-    append_msb(&mut output, ihdr_crc);
+fn apng_header(width: u32, height: u32, format: &Format, frame_count: usize) -> Vec<u8> {
+    let mut output = header(width, height, format);
 
     // acTL: (just one)
     // https://wiki.mozilla.org/APNG_Specification#%60acTL%60:_The_Animation_Control_Chunk
-    let mut actl_type_and_data = Vec::<u8>::new();
-    actl_type_and_data.push(b'a');
-    actl_type_and_data.push(b'c');
-    actl_type_and_data.push(b'T');
-    actl_type_and_data.push(b'L');
-    append_msb(&mut actl_type_and_data, frames.len() as u32); // Number of frames.
-    append_msb(&mut actl_type_and_data, 0); // Number of times to loop, 0=infinite.
-    let actl_len = actl_type_and_data.len() - 4; // Minus the type.
-    let actl_crc = crc(&actl_type_and_data);
-    // Append acTL to output.
-    append_msb(&mut output, actl_len as u32);
-    output.extend_from_slice(&actl_type_and_data);
-    append_msb(&mut output, actl_crc);
+    let mut actl = Vec::<u8>::new();
+    append_msb(&mut actl, frame_count as u32); // Number of frames.
+    append_msb(&mut actl, 0); // Number of times to loop, 0=infinite.
+    append_chunk(&mut output, b"acTL", &actl);
+    output
+}
+
+#[allow(clippy::too_many_arguments)]
+fn append_apng_frame(output: &mut Vec<u8>, index: usize, width: u32, height: u32, rows: &[u8], row_bytes: usize, bytes_per_pixel: usize, compression: Compression) {
+    // fcTL: (before each frame)
+    // https://wiki.mozilla.org/APNG_Specification#%60fcTL%60:_The_Frame_Control_Chunk
+    let mut fctl = Vec::<u8>::new();
+    let fctl_sequence: u32 = if index == 0 { 0 } else { (index as u32) * 2 - 1 };
+    append_msb(&mut fctl, fctl_sequence); // Sequence number starting 0.
+    append_msb(&mut fctl, width);
+    append_msb(&mut fctl, height);
+    append_msb(&mut fctl, 0); // X-offset.
+    append_msb(&mut fctl, 0); // Y-offset.
+    append_msb_u16(&mut fctl, 10); // Delay numerator.
+    append_msb_u16(&mut fctl, 0); // Delay denominator. 0 means each value is 100ths of a second.
+    fctl.push(1); // Dispose operation. 1 means each frame gets a blank canvas.
+    fctl.push(0); // Blend operation. 0 means all components overwrite.
+    append_chunk(output, b"fcTL", &fctl);
+
+    // IDAT (first) / fdAT (subsequent frames, which start with their sequence number).
+    let compressed = compressed_image_data(rows, row_bytes, bytes_per_pixel, compression);
+    if index == 0 {
+        append_chunk(output, b"IDAT", &compressed);
+    } else {
+        let mut fdat = Vec::<u8>::new();
+        append_msb(&mut fdat, (index as u32) * 2);
+        fdat.extend(compressed);
+        append_chunk(output, b"fdAT", &fdat);
+    }
+}
+
+// Writes an RGBA APNG a frame at a time, so long animations needn't hold every frame at once.
+// Exactly frame_count frames should be added before finish.
+pub struct ApngWriter {
+    output: Vec<u8>,
+    width: u32,
+    height: u32,
+    frames_added: usize,
+    compression: Compression,
+}
+
+impl ApngWriter {
+    pub fn new(width: u32, height: u32, frame_count: usize, compression: Compression) -> ApngWriter {
+        ApngWriter { output: apng_header(width, height, &Format::Rgba, frame_count), width, height, frames_added: 0, compression }
+    }
+
+    pub fn add_frame(&mut self, frame: &[u32]) {
+        let rows = rgba_rows(self.width, self.height, frame);
+        append_apng_frame(&mut self.output, self.frames_added, self.width, self.height, &rows, self.width as usize * 4, 4, self.compression);
+        self.frames_added += 1;
+    }
 
+    pub fn finish(mut self) -> Vec<u8> {
+        append_iend(&mut self.output);
+        self.output
+    }
+}
+
+pub fn png_data(width: u32, height: u32, image_data: &[u32], compression: Compression) -> Vec<u8> {
+    let rows = rgba_rows(width, height, image_data);
+    encode_png(width, height, &Format::Rgba, &rows, width as usize * 4, 4, compression)
+}
+
+pub fn apng_data(width: u32, height: u32, frames: &[Vec<u32>], compression: Compression) -> Vec<u8> {
+    let frames: Vec<Vec<u8>> = frames.iter().map(|frame| rgba_rows(width, height, frame)).collect();
+    encode_apng(width, height, &Format::Rgba, &frames, width as usize * 4, 4, compression)
+}
+
+// A palette PNG, which keeps the original colour indices. The palette must have 1 to 256 entries, 0xrrggbbaa.
+pub fn indexed_png_data(width: u32, height: u32, indices: &[u8], palette: &[u32], compression: Compression) -> Vec<u8> {
+    let bit_depth = indexed_bit_depth(palette);
+    let rows = indexed_rows(width, height, indices, bit_depth);
+    encode_png(width, height, &Format::Indexed { palette, bit_depth }, &rows, indexed_row_bytes(width, bit_depth), 1, compression)
+}
+
+pub fn indexed_apng_data(width: u32, height: u32, frames: &[Vec<u8>], palette: &[u32], compression: Compression) -> Vec<u8> {
+    let bit_depth = indexed_bit_depth(palette);
+    let frames: Vec<Vec<u8>> = frames.iter().map(|frame| indexed_rows(width, height, frame, bit_depth)).collect();
+    encode_apng(width, height, &Format::Indexed { palette, bit_depth }, &frames, indexed_row_bytes(width, bit_depth), 1, compression)
+}
+
+// Reading, so edited graphics can be imported:
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+
+// Splits into (type, data) chunks up to IEND, checking each CRC.
+fn chunks(data: &[u8]) -> Result<Vec<([u8; 4], &[u8])>> {
+    if !data.starts_with(&SIGNATURE) {
+        return Err(Error::invalid("not a PNG"));
+    }
+    let mut chunks = Vec::new();
+    let mut offset = SIGNATURE.len();
+    loop {
+        let Some(header) = data.get(offset..offset + 8) else {
+            return Err(Error::Truncated { file: String::new(), offset });
+        };
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let chunk_type = [header[4], header[5], header[6], header[7]];
+        let Some(type_and_data) = data.get(offset + 4..offset + 8 + length) else {
+            return Err(Error::Truncated { file: String::new(), offset });
+        };
+        let Some(expected) = data.get(offset + 8 + length..offset + 12 + length) else {
+            return Err(Error::Truncated { file: String::new(), offset });
+        };
+        if crc(type_and_data) != u32::from_be_bytes([expected[0], expected[1], expected[2], expected[3]]) {
+            return Err(Error::invalid(format!("CRC mismatch in {} chunk at offset 0x{:x}", String::from_utf8_lossy(&chunk_type), offset)));
+        }
+        if &chunk_type == b"IEND" {
+            return Ok(chunks);
+        }
+        chunks.push((chunk_type, &type_and_data[4..]));
+        offset += 12 + length;
+    }
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+// What's needed from IHDR, PLTE and tRNS to turn the image data into RGBA.
+// http://libpng.org/pub/png/spec/1.2/PNG-Chunks.html#C.IHDR
+struct Header {
+    width: usize,
+    height: usize,
+    bit_depth: u8,
+    colour_type: u8, // 0=greyscale, 2=RGB, 3=palette, 4=greyscale+alpha, 6=RGBA.
+    palette: Vec<u32>, // 0xrrggbbaa, with tRNS alphas.
+    transparent: Option<[u16; 3]>, // The greyscale or RGB sample that tRNS makes transparent.
+}
+
+impl Header {
+    fn parse(chunks: &[([u8; 4], &[u8])]) -> Result<Header> {
+        let Some((b"IHDR", ihdr)) = chunks.first().map(|(t, d)| (t, *d)) else {
+            return Err(Error::invalid("PNG doesn't start with IHDR"));
+        };
+        if ihdr.len() != 13 {
+            return Err(Error::invalid(format!("IHDR is {} bytes instead of 13", ihdr.len())));
+        }
+        let mut header = Header {
+            width: u32_at(ihdr, 0) as usize,
+            height: u32_at(ihdr, 4) as usize,
+            bit_depth: ihdr[8],
+            colour_type: ihdr[9],
+            palette: Vec::new(),
+            transparent: None,
+        };
+        let valid_depths: &[u8] = match header.colour_type {
+            0 => &[1, 2, 4, 8, 16],
+            3 => &[1, 2, 4, 8],
+            2 | 4 | 6 => &[8, 16],
+            _ => &[],
+        };
+        if !valid_depths.contains(&header.bit_depth) {
+            return Err(Error::invalid(format!("unsupported bit depth {} for colour type {}", header.bit_depth, header.colour_type)));
+        }
+        if ihdr[10] != 0 || ihdr[11] != 0 {
+            return Err(Error::invalid("unknown compression or filter method"));
+        }
+        if ihdr[12] != 0 {
+            return Err(Error::invalid("interlaced PNGs aren't supported"));
+        }
+        for (chunk_type, data) in chunks {
+            match chunk_type {
+                b"PLTE" => header.palette = data.chunks_exact(3)
+                    .map(|rgb| ((rgb[0] as u32) << 24) + ((rgb[1] as u32) << 16) + ((rgb[2] as u32) << 8) + 0xff)
+                    .collect(),
+                b"tRNS" if header.colour_type == 3 => {
+                    for (colour, alpha) in header.palette.iter_mut().zip(data.iter()) {
+                        *colour = (*colour & 0xffffff00) | *alpha as u32;
+                    }
+                },
+                b"tRNS" => {
+                    let samples: Vec<u16> = data.chunks_exact(2).map(|s| ((s[0] as u16) << 8) + s[1] as u16).collect();
+                    header.transparent = match samples.as_slice() {
+                        [grey] => Some([*grey; 3]),
+                        [r, g, b] => Some([*r, *g, *b]),
+                        _ => None,
+                    };
+                },
+                _ => {},
+            }
+        }
+        if header.colour_type == 3 && header.palette.is_empty() {
+            return Err(Error::invalid("palette PNG has no PLTE"));
+        }
+        Ok(header)
+    }
+
+    fn channels(&self) -> usize {
+        match self.colour_type {
+            2 => 3,
+            4 => 2,
+            6 => 4,
+            _ => 1,
+        }
+    }
+
+    // Scales a sample to 8 bits.
+    fn eight_bit(&self, sample: u16) -> u32 {
+        match self.bit_depth {
+            16 => (sample >> 8) as u32,
+            8 => sample as u32,
+            depth => sample as u32 * 255 / ((1 << depth) - 1),
+        }
+    }
+
+    // Unfilters the (decompressed) image data of a width x height image or frame, into RGBA.
+    fn pixels(&self, width: usize, height: usize, data: &[u8]) -> Result<Vec<u32>> {
+        let bits_per_pixel = self.channels() * self.bit_depth as usize;
+        let row_bytes = (width * bits_per_pixel).div_ceil(8);
+        let bytes_per_pixel = bits_per_pixel.div_ceil(8);
+        if data.len() < (row_bytes + 1) * height {
+            return Err(Error::invalid(format!("image data is {} bytes but {}x{} needs {}", data.len(), width, height, (row_bytes + 1) * height)));
+        }
+        let mut pixels: Vec<u32> = Vec::with_capacity(width * height);
+        let mut previous = vec![0u8; row_bytes];
+        for filtered in data.chunks(row_bytes + 1).take(height) {
+            let row = unfilter_row(filtered[0], &filtered[1..], &previous, bytes_per_pixel)?;
+            let sample = |index: usize| -> u16 {
+                match self.bit_depth {
+                    16 => ((row[index * 2] as u16) << 8) + row[index * 2 + 1] as u16,
+                    8 => row[index] as u16,
+                    depth => {
+                        let bit = index * depth as usize;
+                        ((row[bit / 8] >> (8 - depth as usize - bit % 8)) & ((1 << depth) - 1)) as u16
+                    },
+                }
+            };
+            for x in 0..width {
+                let i = x * self.channels();
+                let pixel = match self.colour_type {
+                    0 | 2 => {
+                        let rgb = if self.colour_type == 0 { [sample(i); 3] } else { [sample(i), sample(i + 1), sample(i + 2)] };
+                        let alpha = if self.transparent == Some(rgb) { 0 } else { 0xff };
+                        (self.eight_bit(rgb[0]) << 24) + (self.eight_bit(rgb[1]) << 16) + (self.eight_bit(rgb[2]) << 8) + alpha
+                    },
+                    3 => match self.palette.get(sample(i) as usize) {
+                        Some(colour) => *colour,
+                        None => return Err(Error::invalid(format!("palette index {} is past the end of the palette", sample(i)))),
+                    },
+                    4 => {
+                        let grey = self.eight_bit(sample(i));
+                        (grey << 24) + (grey << 16) + (grey << 8) + self.eight_bit(sample(i + 1))
+                    },
+                    _ => (self.eight_bit(sample(i)) << 24) + (self.eight_bit(sample(i + 1)) << 16) +
+                        (self.eight_bit(sample(i + 2)) << 8) + self.eight_bit(sample(i + 3)),
+                };
+                pixels.push(pixel);
+            }
+            previous = row;
+        }
+        Ok(pixels)
+    }
+}
+
+fn unfilter_row(filter: u8, filtered: &[u8], previous: &[u8], bytes_per_pixel: usize) -> Result<Vec<u8>> {
+    if filter > 4 {
+        return Err(Error::invalid(format!("unknown filter type {}", filter)));
+    }
+    let mut row: Vec<u8> = Vec::with_capacity(filtered.len());
+    for (i, x) in filtered.iter().enumerate() {
+        let a = if i >= bytes_per_pixel { row[i - bytes_per_pixel] } else { 0 }; // Left.
+        let b = previous[i]; // Above.
+        let c = if i >= bytes_per_pixel { previous[i - bytes_per_pixel] } else { 0 }; // Above left.
+        let predictor = match filter {
+            0 => 0,
+            1 => a,
+            2 => b,
+            3 => ((a as u16 + b as u16) / 2) as u8,
+            _ => paeth(a, b, c),
+        };
+        row.push(x.wrapping_add(predictor));
+    }
+    Ok(row)
+}
+
+// Reads a PNG of any colour type into RGBA. For an APNG, this is the default image.
+pub fn read_png(data: &[u8]) -> Result<Image> {
+    let chunks = chunks(data)?;
+    let header = Header::parse(&chunks)?;
+    let idat: Vec<u8> = chunks.iter().filter(|(t, _)| t == b"IDAT").flat_map(|(_, d)| d.iter().copied()).collect();
+    if idat.is_empty() {
+        return Err(Error::invalid("PNG has no IDAT"));
+    }
+    let bitmap = header.pixels(header.width, header.height, &from_zlib_stream(&idat)?)?;
+    Ok(Image { bitmap, width: header.width, height: header.height })
+}
+
+// An APNG frame, as described by its fcTL.
+// https://wiki.mozilla.org/APNG_Specification#%60fcTL%60:_The_Frame_Control_Chunk
+struct Frame {
+    width: usize,
+    height: usize,
+    x: usize,
+    y: usize,
+    dispose: u8, // 0=leave, 1=clear to transparent, 2=restore what was there before.
+    blend: u8, // 0=overwrite, 1=alpha blend over.
+    data: Vec<u8>, // Compressed.
+}
+
+// Reads each frame of an APNG into full size RGBA frames. A plain PNG is one frame.
+pub fn read_apng(data: &[u8]) -> Result<Animation> {
+    let chunks = chunks(data)?;
+    if !chunks.iter().any(|(t, _)| t == b"acTL") {
+        let image = read_png(data)?;
+        return Ok(Animation { frames: vec![image.bitmap], width: image.width, height: image.height });
+    }
+    let header = Header::parse(&chunks)?;
+    let mut frames: Vec<Frame> = Vec::new();
+    for (chunk_type, data) in &chunks {
+        match chunk_type {
+            b"fcTL" => {
+                if data.len() != 26 {
+                    return Err(Error::invalid(format!("fcTL is {} bytes instead of 26", data.len())));
+                }
+                frames.push(Frame {
+                    width: u32_at(data, 4) as usize,
+                    height: u32_at(data, 8) as usize,
+                    x: u32_at(data, 12) as usize,
+                    y: u32_at(data, 16) as usize,
+                    dispose: data[24],
+                    blend: data[25],
+                    data: Vec::new(),
+                });
+            },
+            // The default image is only the first frame if its fcTL comes first, otherwise it's skipped.
+            b"IDAT" => {
+                if let Some(frame) = frames.first_mut() {
+                    frame.data.extend_from_slice(data);
+                }
+            },
+            b"fdAT" => {
+                let (Some(frame), Some(data)) = (frames.last_mut(), data.get(4..)) else {
+                    return Err(Error::invalid("fdAT without a frame"));
+                };
+                frame.data.extend_from_slice(data); // After the sequence number.
+            },
+            _ => {},
+        }
+    }
+
+    // Composite each frame onto the canvas.
+    let (width, height) = (header.width, header.height);
+    let mut canvas = vec![0u32; width * height];
+    let mut output: Vec<Vec<u32>> = Vec::with_capacity(frames.len());
     for (index, frame) in frames.iter().enumerate() {
-        // fcTL: (before each frame)
-        // https://wiki.mozilla.org/APNG_Specification#%60fcTL%60:_The_Frame_Control_Chunk
-        let mut fctl_type_and_data = Vec::<u8>::new();
-        fctl_type_and_data.push(b'f');
-        fctl_type_and_data.push(b'c');
-        fctl_type_and_data.push(b'T');
-        fctl_type_and_data.push(b'L');
-        let fctl_sequence: u32 = if index == 0 { 0 } else { (index as u32) * 2 - 1 };
-        append_msb(&mut fctl_type_and_data, fctl_sequence); // Sequence number starting 0.
-        append_msb(&mut fctl_type_and_data, width);
-        append_msb(&mut fctl_type_and_data, height);
-        append_msb(&mut fctl_type_and_data, 0); // X-offset.
-        append_msb(&mut fctl_type_and_data, 0); // Y-offset.
-        append_msb_u16(&mut fctl_type_and_data, 10); // Delay numerator.
-        append_msb_u16(&mut fctl_type_and_data, 0); // Delay denominator. 0 means each value is 100ths of a second.
-        fctl_type_and_data.push(1); // Dispose operation. 1 means each frame gets a blank canvas.
-        fctl_type_and_data.push(0); // Blend operation. 0 means all components overwrite.
-        let fctl_len = fctl_type_and_data.len() - 4; // Minus the type.
-        let fctl_crc = crc(&fctl_type_and_data);
-        // Append fctl to output.
-        append_msb(&mut output, fctl_len as u32);
-        output.extend_from_slice(&fctl_type_and_data);
-        append_msb(&mut output, fctl_crc);
-
-        // Build image data.
-        // Left-right, then Top-bottom.
-        // Each line is prepended a filter type byte (0).
-        let mut idat_data = Vec::<u8>::new();
-        let mut data_iter = frame.iter();
-        for _y in 0..height {
-            idat_data.push(0); // Filter.
-            for _x in 0..width {
-                append_msb(&mut idat_data, *data_iter.next().unwrap());
+        if frame.x + frame.width > width || frame.y + frame.height > height {
+            return Err(Error::invalid(format!("APNG frame {} is outside the image", index)));
+        }
+        let pixels = header.pixels(frame.width, frame.height, &from_zlib_stream(&frame.data)?)?;
+        let before = canvas.clone();
+        for (y, row) in pixels.chunks(frame.width.max(1)).enumerate() {
+            for (x, pixel) in row.iter().enumerate() {
+                let target = &mut canvas[(frame.y + y) * width + frame.x + x];
+                *target = if frame.blend == 0 { *pixel } else { blend_over(*pixel, *target) };
             }
         }
-        let compressed_idat_data = to_zlib_stream(&idat_data);
-
-        // Build IDAT (first) / fdAT (subsequent frames).
-        let mut idat_type_and_data = Vec::<u8>::new();
-        if index == 0 {
-            idat_type_and_data.push(b'I');
-            idat_type_and_data.push(b'D');
-            idat_type_and_data.push(b'A');
-            idat_type_and_data.push(b'T');
-        } else {
-            idat_type_and_data.push(b'f');
-            idat_type_and_data.push(b'd');
-            idat_type_and_data.push(b'A');
-            idat_type_and_data.push(b'T');
-            append_msb(&mut idat_type_and_data, (index as u32) * 2);
+        output.push(canvas.clone());
+        if frame.dispose == 1 || (frame.dispose == 2 && index == 0) {
+            for y in frame.y..frame.y + frame.height {
+                canvas[y * width + frame.x..y * width + frame.x + frame.width].fill(0);
+            }
+        } else if frame.dispose == 2 {
+            canvas = before;
         }
-        idat_type_and_data.extend_from_slice(&compressed_idat_data);
-        let idat_len = idat_type_and_data.len() - 4; // Minus the type.
-        let idat_crc = crc(&idat_type_and_data);
-        // Append IDAT to output.
-        append_msb(&mut output, idat_len as u32);
-        output.extend_from_slice(&idat_type_and_data);
-        append_msb(&mut output, idat_crc);
-    }
-
-    // IEND (no data).
-    append_msb(&mut output, 0); // Length.
-    output.push(b'I'); // Type.
-    output.push(b'E');
-    output.push(b'N');
-    output.push(b'D');
-    let iend_crc = crc(b"IEND");
-    append_msb(&mut output, iend_crc);
+    }
+    Ok(Animation { frames: output, width, height })
+}
 
+// Source over destination, both 0xrrggbbaa with straight (not premultiplied) alpha.
+fn blend_over(source: u32, destination: u32) -> u32 {
+    let source_alpha = source & 0xff;
+    if source_alpha == 0xff { return source }
+    if source_alpha == 0 { return destination }
+    let destination_alpha = (destination & 0xff) * (255 - source_alpha) / 255;
+    let alpha = source_alpha + destination_alpha;
+    let mut output = alpha;
+    for shift in [8, 16, 24] {
+        let s = (source >> shift) & 0xff;
+        let d = (destination >> shift) & 0xff;
+        output += ((s * source_alpha + d * destination_alpha) / alpha) << shift;
+    }
     output
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_indexed_rows() {
+        // 3 pixels at 2 bits each pack into the top 6 bits of each row's byte.
+        let rows = indexed_rows(3, 2, &[1, 2, 3, 3, 0, 1], 2);
+        assert_eq!(rows, vec![0b01_10_11_00, 0b11_00_01_00]);
+        assert_eq!(indexed_bit_depth(&[0; 17]), 8);
+        assert_eq!(indexed_bit_depth(&[0; 16]), 4);
+    }
+
+    #[test]
+    fn test_read_back() {
+        let bitmap: Vec<u32> = (0..15 * 7).map(|i| (i as u32).wrapping_mul(0x9e3779b9) | 0x80).collect();
+        let image = read_png(&png_data(15, 7, &bitmap, Compression::Best)).unwrap();
+        assert_eq!((image.width, image.height), (15, 7));
+        assert_eq!(image.bitmap, bitmap);
+
+        // A masked ground palette has 17 entries, so this is 8 bit with tRNS.
+        let mut palette: Vec<u32> = (0..16).map(|i| (i << 24) | 0xff).collect();
+        palette.push(0);
+        let indices: Vec<u8> = (0..15 * 7).map(|i| (i % 17) as u8).collect();
+        let image = read_png(&indexed_png_data(15, 7, &indices, &palette, Compression::Fast)).unwrap();
+        assert_eq!(image.bitmap, indices.iter().map(|i| palette[*i as usize]).collect::<Vec<u32>>());
+
+        let frames = vec![bitmap.clone(), bitmap.iter().rev().copied().collect()];
+        let animation = read_apng(&apng_data(15, 7, &frames, Compression::Default)).unwrap();
+        assert_eq!(animation.frames, frames);
+        let animation = read_apng(&indexed_apng_data(15, 7, &[indices.clone(), indices.clone()], &palette, Compression::Stored)).unwrap();
+        assert_eq!(animation.frames.len(), 2);
+        assert_eq!(animation.frames[1], image.bitmap);
+
+        let mut corrupt = png_data(15, 7, &bitmap, Compression::Default);
+        corrupt[40] ^= 1;
+        assert!(read_png(&corrupt).is_err());
+    }
+
+    #[test]
+    fn test_read_greyscale_and_rgb() {
+        // 3x1 2-bit greyscale, with grey level 1 transparent.
+        let mut png = SIGNATURE.to_vec();
+        append_chunk(&mut png, b"IHDR", &[0, 0, 0, 3, 0, 0, 0, 1, 2, 0, 0, 0, 0]);
+        append_chunk(&mut png, b"tRNS", &[0, 1]);
+        append_chunk(&mut png, b"IDAT", &to_zlib_stream(&[0, 0b11_01_10_00], Compression::Default));
+        append_iend(&mut png);
+        assert_eq!(read_png(&png).unwrap().bitmap, vec![0xffffffff, 0x55555500, 0xaaaaaaff]);
+
+        // 2x1 16-bit RGB, using the Sub filter.
+        let mut png = SIGNATURE.to_vec();
+        append_chunk(&mut png, b"IHDR", &[0, 0, 0, 2, 0, 0, 0, 1, 16, 2, 0, 0, 0]);
+        append_chunk(&mut png, b"IDAT", &to_zlib_stream(&[1, 0x12, 0, 0x34, 0, 0x56, 0, 1, 0, 1, 0, 1, 0], Compression::Default));
+        append_iend(&mut png);
+        assert_eq!(read_png(&png).unwrap().bitmap, vec![0x123456ff, 0x133557ff]);
+    }
+}
@@ -0,0 +1,271 @@
+// Fits true-colour artwork into the game's palettes, eg to import custom terrain, objects or special backgrounds.
+// Median cut picks the starting colours, then k-means refines them around the fixed palette slots. Colours are
+// kept to the 6 bits per channel the VGA palette holds, so they survive being saved.
+
+use crate::ground;
+use crate::image::{Image, IndexedImage};
+use std::collections::HashMap;
+
+const BLACK: u32 = 0x000000ff;
+const GROUND_FIXED_COLOURS: usize = 7; // The ones ground::extend_palette prepends.
+const GROUND_CUSTOM_COLOURS: usize = 8;
+const SPECIAL_CUSTOM_COLOURS: usize = 7; // The first special colour is always black.
+const KMEANS_ITERATIONS: usize = 10;
+const DRAWN_ALPHA: u32 = 0x80; // Like IndexedImage::from_image, mostly transparent pixels are masked out.
+const ORDERED_SPREAD: f64 = 32.0; // How far the ordered dither pattern nudges each channel.
+const BAYER: [[f64; 4]; 4] = [
+    [0.0, 8.0, 2.0, 10.0],
+    [12.0, 4.0, 14.0, 6.0],
+    [3.0, 11.0, 1.0, 9.0],
+    [15.0, 7.0, 13.0, 5.0],
+];
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Dither {
+    #[default]
+    None,
+    Ordered, // 4x4 Bayer.
+    FloydSteinberg,
+}
+
+pub struct Quantised {
+    pub palette: Vec<u32>, // 0xrrggbbaa, the fixed colours then the chosen ones.
+    pub images: Vec<IndexedImage>, // In the same order as the images passed in.
+    pub errors: Vec<f64>, // Root mean square error per channel (0-255) over each image's drawn pixels.
+}
+
+type Rgb = [f64; 3];
+
+// Picks custom_count colours to go after the fixed ones, shared by all the images, then maps each image onto them.
+pub fn quantise(images: &[&Image], fixed: &[u32], custom_count: usize, dither: Dither) -> Quantised {
+    let fixed_rgb: Vec<Rgb> = fixed.iter().map(|colour| rgb(*colour)).collect();
+    let mut histogram: HashMap<u32, f64> = HashMap::new();
+    for image in images {
+        for colour in image.bitmap.iter().filter(|colour| is_drawn(**colour)) {
+            *histogram.entry(colour | 0xff).or_default() += 1.0;
+        }
+    }
+    // Colours the fixed slots already show exactly don't need a custom one.
+    let colours: Vec<(Rgb, f64)> = histogram.into_iter()
+        .map(|(colour, weight)| (rgb(colour), weight))
+        .filter(|(colour, _)| !fixed_rgb.contains(&vga_precision(*colour)))
+        .collect();
+
+    let mut custom = median_cut(colours.clone(), custom_count);
+    refine(&colours, &fixed_rgb, &mut custom);
+    let mut palette: Vec<u32> = fixed.to_vec();
+    palette.extend(custom.iter().map(|colour| rgba(*colour)));
+    palette.resize(fixed.len() + custom_count, BLACK); // Unused slots, if there weren't enough colours.
+
+    let mut quantised = Quantised { palette, images: Vec::new(), errors: Vec::new() };
+    for image in images {
+        let (indexed, error) = map_image(image, &quantised.palette, dither);
+        quantised.images.push(indexed);
+        quantised.errors.push(error);
+    }
+    quantised
+}
+
+// For terrain and objects: 8 custom colours after the 7 fixed ones. The palette comes out as ground::extend_palette
+// lays it out, ready for Ground::palette, with the custom colours at indices 8-15.
+pub fn for_ground(images: &[&Image], dither: Dither) -> Quantised {
+    let fixed = ground::extend_palette([BLACK; GROUND_CUSTOM_COLOURS]);
+    let mut quantised = quantise(images, &fixed[..GROUND_FIXED_COLOURS], GROUND_CUSTOM_COLOURS, dither);
+    let mut upper_palette: [u32; GROUND_CUSTOM_COLOURS] = [BLACK; GROUND_CUSTOM_COLOURS];
+    upper_palette.copy_from_slice(&quantised.palette[GROUND_FIXED_COLOURS..]);
+    quantised.palette = ground::extend_palette(upper_palette).to_vec();
+    for image in quantised.images.iter_mut() {
+        for index in image.indices.iter_mut().filter(|index| **index as usize >= GROUND_FIXED_COLOURS) {
+            *index += 1; // Skip the duplicate of the first custom colour.
+        }
+        image.palette = quantised.palette.clone();
+    }
+    quantised
+}
+
+// For VGASPEC backgrounds: 7 colours after black. Pass the image to special::Special::from_indexed.
+pub fn for_special(image: &Image, dither: Dither) -> Quantised {
+    quantise(&[image], &[BLACK], SPECIAL_CUSTOM_COLOURS, dither)
+}
+
+fn is_drawn(colour: u32) -> bool {
+    colour & 0xff >= DRAWN_ALPHA
+}
+
+fn rgb(colour: u32) -> Rgb {
+    [(colour >> 24) as f64, ((colour >> 16) & 0xff) as f64, ((colour >> 8) & 0xff) as f64]
+}
+
+fn rgba(colour: Rgb) -> u32 {
+    let [r, g, b] = colour.map(|channel| channel.round().clamp(0.0, 255.0) as u32);
+    (r << 24) + (g << 16) + (b << 8) + 0xff
+}
+
+// Snaps to the nearest colour the game can show: 6 bits per channel, upgraded to 8 the way the loaders do.
+fn vga_precision(colour: Rgb) -> Rgb {
+    colour.map(|channel| {
+        let six = ((channel - 3.0) / 4.0).round().clamp(0.0, 63.0);
+        if six == 0.0 { 0.0 } else { six * 4.0 + 3.0 }
+    })
+}
+
+fn distance(a: Rgb, b: Rgb) -> f64 {
+    (0..3).map(|channel| (a[channel] - b[channel]).powi(2)).sum()
+}
+
+fn nearest(colour: Rgb, palette: &[Rgb]) -> usize {
+    (0..palette.len())
+        .min_by(|a, b| distance(colour, palette[*a]).total_cmp(&distance(colour, palette[*b])))
+        .unwrap_or(0)
+}
+
+fn mean(colours: &[(Rgb, f64)]) -> Rgb {
+    let total: f64 = colours.iter().map(|(_, weight)| weight).sum();
+    let mut sum: Rgb = [0.0; 3];
+    for (colour, weight) in colours {
+        for channel in 0..3 {
+            sum[channel] += colour[channel] * weight;
+        }
+    }
+    vga_precision(sum.map(|channel| channel / total))
+}
+
+// Splits the widest box at its weighted median until there's one box per colour, or nothing left to split.
+fn median_cut(colours: Vec<(Rgb, f64)>, count: usize) -> Vec<Rgb> {
+    if colours.is_empty() || count == 0 { return Vec::new() }
+    let mut boxes: Vec<Vec<(Rgb, f64)>> = vec![colours];
+    while boxes.len() < count {
+        let widest = boxes.iter().enumerate().flat_map(|(index, colours)| {
+            (0..3).map(move |channel| {
+                let values = colours.iter().map(|(colour, _)| colour[channel]);
+                let range = values.clone().fold(f64::MIN, f64::max) - values.fold(f64::MAX, f64::min);
+                (index, channel, range)
+            })
+        }).max_by(|a, b| a.2.total_cmp(&b.2));
+        let Some((index, channel, range)) = widest else { break };
+        if range <= 0.0 { break }
+        let mut colours = boxes.swap_remove(index);
+        colours.sort_by(|a, b| a.0[channel].total_cmp(&b.0[channel]));
+        let total: f64 = colours.iter().map(|(_, weight)| weight).sum();
+        let mut running = 0.0;
+        let mut split = colours.iter().position(|(_, weight)| { running += weight; running >= total / 2.0 }).unwrap_or(0) + 1;
+        split = split.clamp(1, colours.len() - 1);
+        let upper = colours.split_off(split);
+        boxes.push(colours);
+        boxes.push(upper);
+    }
+    boxes.iter().map(|colours| mean(colours)).collect()
+}
+
+// K-means, where colours closest to a fixed slot are left to it instead of pulling the custom ones around.
+fn refine(colours: &[(Rgb, f64)], fixed: &[Rgb], custom: &mut [Rgb]) {
+    for _ in 0..KMEANS_ITERATIONS {
+        let palette: Vec<Rgb> = fixed.iter().chain(custom.iter()).copied().collect();
+        let mut clusters: Vec<Vec<(Rgb, f64)>> = vec![Vec::new(); custom.len()];
+        for (colour, weight) in colours {
+            let index = nearest(*colour, &palette);
+            if index >= fixed.len() {
+                clusters[index - fixed.len()].push((*colour, *weight));
+            }
+        }
+        let mut changed = false;
+        for (colour, cluster) in custom.iter_mut().zip(&clusters) {
+            if cluster.is_empty() { continue }
+            let centre = mean(cluster);
+            changed |= centre != *colour;
+            *colour = centre;
+        }
+        if !changed { break }
+    }
+}
+
+// Returns the indexed image and its error.
+fn map_image(image: &Image, palette: &[u32], dither: Dither) -> (IndexedImage, f64) {
+    let palette_rgb: Vec<Rgb> = palette.iter().map(|colour| rgb(*colour)).collect();
+    let mut wanted: Vec<Rgb> = image.bitmap.iter().map(|colour| rgb(*colour)).collect(); // Floyd-Steinberg adds to this.
+    let mut indices: Vec<u8> = Vec::with_capacity(image.bitmap.len());
+    let mask: Vec<bool> = image.bitmap.iter().map(|colour| is_drawn(*colour)).collect();
+    let mut squared_error = 0.0;
+    for y in 0..image.height {
+        for x in 0..image.width {
+            let i = y * image.width + x;
+            if !mask[i] {
+                indices.push(0);
+                continue;
+            }
+            let mut colour = wanted[i];
+            if dither == Dither::Ordered {
+                let offset = ((BAYER[y % 4][x % 4] + 0.5) / 16.0 - 0.5) * ORDERED_SPREAD;
+                colour = colour.map(|channel| channel + offset);
+            }
+            let index = nearest(colour, &palette_rgb);
+            indices.push(index as u8);
+            let chosen = palette_rgb[index];
+            squared_error += distance(rgb(image.bitmap[i]), chosen);
+            if dither == Dither::FloydSteinberg {
+                let error: Rgb = [0, 1, 2].map(|channel| colour[channel] - chosen[channel]);
+                let mut spread = |dx: isize, dy: usize, fraction: f64| {
+                    let (nx, ny) = (x as isize + dx, y + dy);
+                    if nx < 0 || nx as usize >= image.width || ny >= image.height { return }
+                    let neighbour = &mut wanted[ny * image.width + nx as usize];
+                    for channel in 0..3 {
+                        neighbour[channel] += error[channel] * fraction;
+                    }
+                };
+                spread(1, 0, 7.0 / 16.0);
+                spread(-1, 1, 3.0 / 16.0);
+                spread(0, 1, 5.0 / 16.0);
+                spread(1, 1, 1.0 / 16.0);
+            }
+        }
+    }
+    let drawn = mask.iter().filter(|drawn| **drawn).count();
+    let error = if drawn == 0 { 0.0 } else { (squared_error / (drawn * 3) as f64).sqrt() };
+    let mask = if drawn == mask.len() { None } else { Some(mask) };
+    let indexed = IndexedImage { indices, palette: palette.to_vec(), mask, width: image.width, height: image.height };
+    (indexed, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_finder;
+    use crate::grounds_loader;
+    use crate::specials_loader;
+
+    #[test]
+    fn test_original_colours_are_exact() {
+        let files = file_finder::DataFiles::from_folder("data/lemmings").unwrap();
+        let specials = specials_loader::load(&files).unwrap();
+        let image = specials[&0].image.to_image();
+        let quantised = for_special(&image, Dither::None);
+        assert_eq!(quantised.errors, vec![0.0]);
+        assert_eq!(quantised.images[0].rgba(), image.bitmap);
+
+        let grounds = grounds_loader::load(&files).unwrap();
+        let terrain: Vec<Image> = grounds[&0].terrain.values().map(|sprite| sprite.to_image()).collect();
+        let terrain: Vec<&Image> = terrain.iter().collect();
+        let quantised = for_ground(&terrain, Dither::None);
+        assert_eq!(quantised.palette[..GROUND_FIXED_COLOURS], grounds[&0].ground.palette[..GROUND_FIXED_COLOURS]);
+        for (indexed, image) in quantised.images.iter().zip(&terrain) {
+            assert_eq!(indexed.rgba(), image.bitmap);
+        }
+        assert!(quantised.errors.iter().all(|error| *error == 0.0));
+    }
+
+    #[test]
+    fn test_dither() {
+        // A gradient with more shades than there are colours, and a transparent corner.
+        let mut bitmap: Vec<u32> = (0..64 * 16).map(|i| ((i % 64) as u32 * 4) * 0x01010100 + 0xff).collect();
+        bitmap[0] = 0;
+        let image = Image { bitmap, width: 64, height: 16 };
+        for dither in [Dither::None, Dither::Ordered, Dither::FloydSteinberg] {
+            let quantised = quantise(&[&image], &[BLACK], 3, dither);
+            assert_eq!(quantised.palette.len(), 4);
+            let indexed = &quantised.images[0];
+            assert!(indexed.indices.iter().all(|index| (*index as usize) < quantised.palette.len()));
+            assert_eq!(indexed.mask.as_ref().map(|mask| mask[0..2].to_vec()), Some(vec![false, true]));
+            assert!(quantised.errors[0] > 0.0 && quantised.errors[0] < 64.0, "{:?} {}", dither, quantised.errors[0]);
+        }
+    }
+}
@@ -0,0 +1,42 @@
+// Reads through a byte slice, reporting where it ran out.
+
+use crate::error::{Error, Result};
+
+pub struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader { data, offset: 0 }
+    }
+
+    pub fn u8(&mut self) -> Result<u8> {
+        let Some(byte) = self.data.get(self.offset) else {
+            return Err(Error::Truncated { file: String::new(), offset: self.offset });
+        };
+        self.offset += 1;
+        Ok(*byte)
+    }
+
+    pub fn u16_be(&mut self) -> Result<u16> {
+        let big = self.u8()?;
+        let little = self.u8()?;
+        Ok(((big as u16) << 8) + (little as u16))
+    }
+
+    pub fn u16_le(&mut self) -> Result<u16> {
+        let little = self.u8()?;
+        let big = self.u8()?;
+        Ok(((big as u16) << 8) + (little as u16))
+    }
+
+    pub fn bytes(&mut self, count: usize) -> Result<&'a [u8]> {
+        let Some(bytes) = self.data.get(self.offset..).and_then(|rest| rest.get(..count)) else {
+            return Err(Error::Truncated { file: String::new(), offset: self.offset });
+        };
+        self.offset += count;
+        Ok(bytes)
+    }
+}
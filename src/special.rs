@@ -1,73 +1,67 @@
-// This loads the special levels eg VGASPEC0.DAT.
+// This loads the special levels eg VGASPEC0.DAT, and builds them for custom special levels.
 // https://www.camanis.net/lemmings/files/docs/lemmings_vgaspecx_dat_file_format.txt
 
-use std::io::{Error, ErrorKind, Result};
-use std::slice::Iter;
+use crate::error::{Error, Result};
 use crate::image;
 use crate::bit_iter_ms_first;
+use crate::compressor;
+use crate::reader::Reader;
+use crate::vga_colour::{read_rgb, write_rgb};
 
-// Reads a byte, failing gracefully if none are left.
-fn read_u8(data: &mut Iter<u8>) -> Result<u8> {
-    match data.next() {
-        Some(t) => Ok(*t),
-        None => Err(Error::new(ErrorKind::UnexpectedEof, "No data remaining")),
-    }
-}
-
-// Upgrades a 6-bit colour to 8, while still allowing 100% black and white.
-fn colour_upgrade(six: u8) -> u8 {
-    if six == 0 { 0 } else { (six << 2) + 3 }
-}
-
-// Read 3 RGB bytes, outputting RGBA.
-fn read_rgb(data: &mut Iter<u8>) -> Result<u32> {
-    let r6 = read_u8(data)?;
-    let g6 = read_u8(data)?;
-    let b6 = read_u8(data)?;
-    let r8: u8 = colour_upgrade(r6);
-    let g8: u8 = colour_upgrade(g6);
-    let b8: u8 = colour_upgrade(b6);
-    Ok(((r8 as u32) << 24) + ((g8 as u32) << 16) + ((b8 as u32) << 8) + 0xff)
+pub struct Special {
+    pub image: image::IndexedImage, // 8 colours, the first always black.
+    pub ega_palette: [u8; 16],
 }
 
 pub const WIDTH: usize = 960;
-const HEIGHT: usize = 160;
+pub const HEIGHT: usize = 160;
 const PIXELS: usize = WIDTH * HEIGHT;
 const SECTION_HEIGHT: usize = 40;
 const SECTION_PIXELS: usize = WIDTH * SECTION_HEIGHT;
 const SECTION_CAPACITY: usize = SECTION_PIXELS * 3 / 8; // Decompressed quarter-section size in bytes.
+const BLACK: u32 = 0x000000ff;
+const MAX_CHUNK: usize = 128; // Longest raw or run chunk.
+const END_OF_SECTION: u8 = 0x80;
+
+pub fn file_name(special_number: u32) -> String {
+    format!("VGASPEC{}.DAT", special_number)
+}
 
 // Pass this data that has already been DAT-decompressed.
-pub fn parse(data: &[u8]) -> Result<image::Image> {
-    let mut iter = data.into_iter();
+pub fn parse(data: &[u8]) -> Result<Special> {
+    let mut reader = Reader::new(data);
 
     // Palette.
     let mut palette: [u32; 8] = [0; 8];
-    let _unused_first = read_rgb(&mut iter)?; // First palette entry is replaced with black.
-    palette[0] = 0x000000ff;
-    for i in 1..8 {
-        palette[i] = read_rgb(&mut iter)?;
+    let _unused_first = read_rgb(&mut reader)?; // First palette entry is replaced with black.
+    palette[0] = BLACK;
+    for colour in palette.iter_mut().skip(1) {
+        *colour = read_rgb(&mut reader)?;
     }
-    for _ in 0..16 { let _ega = read_u8(&mut iter); } // Discard EGA.
+    let mut ega_palette: [u8; 16] = [0; 16];
+    ega_palette.copy_from_slice(reader.bytes(16)?);
 
     // RLE-decompress it.
-    let mut bitmap: Vec<u32> = Vec::with_capacity(PIXELS);
+    let mut indices: Vec<u8> = Vec::with_capacity(PIXELS);
     let mut decompressed: Vec<u8> = Vec::with_capacity(SECTION_CAPACITY);
-    while bitmap.len() < PIXELS {
-        let byte = read_u8(&mut iter)?;
+    while indices.len() < PIXELS {
+        let byte = reader.u8()?;
         if byte <= 0x7f { // Raw chunk.
             let count = byte + 1;
             for _ in 0..count {
-                let b = read_u8(&mut iter)?;
+                let b = reader.u8()?;
                 decompressed.push(b);
             }
         } else if byte >= 0x81 { // Run chunk.
             let count = (255 - byte) + 2;
-            let b = read_u8(&mut iter)?;
+            let b = reader.u8()?;
             for _ in 0..count {
                 decompressed.push(b);
             }
         } else { // End of section. Each section should be of size 14400 bytes.
+            if decompressed.len() < SECTION_CAPACITY {
+                return Err(Error::BadLength { file: String::new(), expected: SECTION_CAPACITY, actual: decompressed.len() });
+            }
             { // Apply this section to the pixels. Braces to scope the iterators.
                 let mut image_iter_0 = bit_iter_ms_first::iterate(&decompressed);
                 let mut image_iter_1 = bit_iter_ms_first::iterate(&decompressed).skip(SECTION_PIXELS);
@@ -77,17 +71,139 @@ pub fn parse(data: &[u8]) -> Result<image::Image> {
                         image_iter_0.next().unwrap() +
                         (image_iter_1.next().unwrap() << 1) +
                         (image_iter_2.next().unwrap() << 2);
-                    let colour = palette[colour_index as usize];
-                    bitmap.push(colour);
+                    indices.push(colour_index);
                 }
             }
             decompressed.clear(); // This happily keeps the capacity.
         }
     }
 
-    Ok(image::Image {
+    let image = image::IndexedImage {
         width: WIDTH,
         height: HEIGHT,
-        bitmap: bitmap,
-    })
+        indices,
+        palette: palette.to_vec(),
+        mask: None,
+    };
+    Ok(Special { image, ega_palette })
+}
+
+impl Special {
+    // Maps a 960x160 image to the nearest of the given colours. For a better fit, pick the colours
+    // with quantise::for_special and use from_indexed.
+    pub fn from_image(image: &image::Image, palette: &[u32; 8]) -> Result<Special> {
+        let mut palette = *palette;
+        palette[0] = BLACK; // The first colour is always black in the game.
+        Special::from_indexed(image::IndexedImage::from_image(image, &palette))
+    }
+
+    // The EGA palette is approximated from the VGA one.
+    pub fn from_indexed(mut image: image::IndexedImage) -> Result<Special> {
+        if image.width != WIDTH || image.height != HEIGHT {
+            return Err(Error::invalid(format!("special images are {}x{}, not {}x{}", WIDTH, HEIGHT, image.width, image.height)));
+        }
+        image.mask = None; // Backgrounds are fully drawn.
+        let mut ega_palette: [u8; 16] = [0; 16];
+        for (index, ega) in ega_palette.iter_mut().enumerate() {
+            *ega = ega_colour(image.palette.get(index % 8).copied().unwrap_or(BLACK));
+        }
+        Ok(Special { image, ega_palette })
+    }
+
+    // The reverse of parse: the uncompressed section.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let image = &self.image;
+        if image.width != WIDTH || image.height != HEIGHT || image.indices.len() != PIXELS {
+            return Err(Error::invalid(format!("special images are {}x{}, not {}x{}", WIDTH, HEIGHT, image.width, image.height)));
+        }
+        if image.palette.len() > 8 {
+            return Err(Error::invalid(format!("special palettes have 8 colours, not {}", image.palette.len())));
+        }
+        if let Some(index) = image.indices.iter().find(|index| **index >= 8) {
+            return Err(Error::invalid(format!("special images can only use colours 0-7, not {}", index)));
+        }
+
+        let mut data: Vec<u8> = Vec::new();
+        for index in 0..8 {
+            let colour = if index == 0 { 0 } else { image.palette.get(index).copied().unwrap_or(BLACK) };
+            write_rgb(&mut data, colour);
+        }
+        data.extend_from_slice(&self.ega_palette);
+
+        // Each quarter is planarised, then RLE-compressed.
+        for quarter in image.indices.chunks(SECTION_PIXELS) {
+            let mut planes: Vec<u8> = Vec::with_capacity(SECTION_CAPACITY);
+            for plane in 0..3 {
+                planes.extend(bit_iter_ms_first::pack(quarter.iter().map(|index| (index >> plane) & 1 == 1)));
+            }
+            rle_encode(&planes, &mut data);
+        }
+        Ok(data)
+    }
+}
+
+// The compressed VGASPECx.DAT.
+pub fn build(special: &Special) -> Result<Vec<u8>> {
+    compressor::compress(&[special.to_bytes()?])
+}
+
+// Runs of 3 or more identical bytes become run chunks, everything else goes in raw chunks.
+// Like the game's own files, a run of 2 is a run chunk too when no raw chunk is under way.
+fn rle_encode(data: &[u8], output: &mut Vec<u8>) {
+    let mut raw_start = 0;
+    let mut i = 0;
+    while i < data.len() {
+        let run = data[i..].iter().take(MAX_CHUNK).take_while(|b| **b == data[i]).count();
+        let min_run = if raw_start == i { 2 } else { 3 };
+        if run < min_run {
+            i += 1;
+            continue;
+        }
+        write_raw(&data[raw_start..i], output);
+        output.push((257 - run) as u8);
+        output.push(data[i]);
+        i += run;
+        raw_start = i;
+    }
+    write_raw(&data[raw_start..], output);
+    output.push(END_OF_SECTION);
+}
+
+fn write_raw(data: &[u8], output: &mut Vec<u8>) {
+    for chunk in data.chunks(MAX_CHUNK) {
+        output.push((chunk.len() - 1) as u8);
+        output.extend_from_slice(chunk);
+    }
+}
+
+// Nearest EGA colour, which has 2 bits per channel: 0brgbRGB, where RGB are 2/3 intensity and rgb are 1/3.
+fn ega_colour(colour: u32) -> u8 {
+    let level = |shift: u32| ((((colour >> shift) & 0xff) * 3 + 127) / 255) as u8;
+    let (r, g, b) = (level(24), level(16), level(8));
+    ((r >> 1) << 2) | ((g >> 1) << 1) | (b >> 1) | ((r & 1) << 5) | ((g & 1) << 4) | ((b & 1) << 3)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decompressor;
+    use crate::file_finder;
+
+    #[test]
+    fn test_rebuild_original_specials() {
+        let files = file_finder::DataFiles::from_folder("data/lemmings").unwrap();
+        for number in 0..4 {
+            let original = files.get(&file_name(number).to_lowercase()).unwrap();
+            let original_section = decompressor::decompress(&original.name, &original.data).unwrap().remove(0);
+            let special = parse(&original_section).unwrap();
+            let data = build(&special).unwrap();
+            let section = decompressor::decompress(&file_name(number), &data).unwrap().remove(0);
+            assert_eq!(section, original_section, "special {}", number);
+
+            // And from RGBA, eg an edited PNG.
+            let palette: [u32; 8] = special.image.palette.clone().try_into().unwrap();
+            let quantised = Special::from_image(&special.image.to_image(), &palette).unwrap();
+            assert_eq!(quantised.image.rgba(), special.image.rgba(), "special {}", number);
+        }
+    }
 }
@@ -1,16 +1,15 @@
 use crate::file_finder;
 use crate::decompressor;
 use crate::special;
-use crate::image;
 use std::collections::HashMap;
-use anyhow::Result;
+use crate::error::Result;
 
-pub fn load(path: &str) -> Result<HashMap::<u32, image::Image>> {
-    let mut all = HashMap::<u32, image::Image>::new();
-    for file in file_finder::find(path, "vgaspec", ".dat")? {
-        let sections = decompressor::decompress(&file.data);
+pub fn load(files: &file_finder::DataFiles) -> Result<HashMap::<u32, special::Special>> {
+    let mut all = HashMap::<u32, special::Special>::new();
+    for file in files.find("vgaspec", ".dat") {
+        let sections = decompressor::decompress(&file.name, &file.data)?;
         let Some(section) = sections.first() else { continue };
-        let special = special::parse(&section)?;
+        let special = special::parse(section).map_err(|e| e.in_file(&file.name))?;
         all.insert(file.number, special);
     }
     Ok(all)
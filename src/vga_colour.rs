@@ -0,0 +1,44 @@
+// VGA palette entries, as ground and special files store them: 3 bytes of 6-bit red, green and blue.
+// Source file: (0x3F, 0x00, 0x00) gives you the brightest red you can get (camanis.net)
+
+use crate::error::Result;
+use crate::reader::Reader;
+
+// Upgrades a 6-bit colour to 8, while still allowing 100% black and white.
+pub fn colour_upgrade(six: u8) -> u8 {
+    if six == 0 { 0 } else { (six << 2) + 3 }
+}
+
+// Read 3 RGB bytes, converting to 0-255 RGBA format.
+pub fn read_rgb(reader: &mut Reader) -> Result<u32> {
+    let r6 = reader.u8()?;
+    let g6 = reader.u8()?;
+    let b6 = reader.u8()?;
+    let r8: u8 = colour_upgrade(r6);
+    let g8: u8 = colour_upgrade(g6);
+    let b8: u8 = colour_upgrade(b6);
+    Ok(((r8 as u32) << 24) + ((g8 as u32) << 16) + ((b8 as u32) << 8) + 0xff)
+}
+
+// The inverse of read_rgb, back to 6 bits per channel.
+pub fn write_rgb(data: &mut Vec<u8>, colour: u32) {
+    data.push((colour >> 26) as u8);
+    data.push(((colour >> 18) & 0x3f) as u8);
+    data.push(((colour >> 10) & 0x3f) as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        for rgb in [[0, 0, 0], [0x3f, 0, 0], [0x10, 0x10, 0x38], [0x3f, 0x3f, 0x3f]] {
+            let colour = read_rgb(&mut Reader::new(&rgb)).unwrap();
+            let mut data = Vec::new();
+            write_rgb(&mut data, colour);
+            assert_eq!(data, rgb);
+        }
+        assert_eq!(read_rgb(&mut Reader::new(&[0x3f, 0, 0])).unwrap(), 0xff0000ff);
+    }
+}